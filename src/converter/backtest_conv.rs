@@ -0,0 +1,270 @@
+use serde::Serialize;
+
+use polars::frame::DataFrame;
+
+use crate::scanner::{
+    BacktestRun, BenchmarkComparison, Degradation, Fill, Heatmap, Leaderboard, LeaderboardRecord, LeaderboardRun, Metric, MonteCarloRun, OptimizerRun, OrderRecord, ParamGrid, PerformanceMetrics, Portfolio, PortfolioRun, Robustness, ScanRecord, Screener, ScreenerRun, Sizing,
+    SplitRun, SymbolContribution, Trade, TrainTestSplit,
+    WalkForward, WalkForwardRun, WalkForwardWindow
+};
+use crate::jobs::Job;
+use crate::strategy::StrategyParams;
+
+#[derive(Serialize, Debug)]
+pub struct EquityPoint {
+    pub datetime: String,
+    pub cash: f64,
+    pub shares: f64,
+    pub equity: f64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct BacktestReport {
+    pub signal: String,
+    pub params: StrategyParams,
+    pub initial_capital: f64,
+    pub final_equity: f64,
+    pub sizing: Sizing,
+    pub metrics: PerformanceMetrics,
+    pub benchmarks: Vec<BenchmarkComparison>,
+    pub trades: Vec<Trade>,
+    pub equity_curve: Vec<EquityPoint>,
+}
+
+fn equity_points(curve: &DataFrame) -> Result<Vec<EquityPoint>, Box<dyn std::error::Error>> {
+    let datetime = curve.column("datetime")?.str()?;
+    let cash = curve.column("cash")?.f64()?;
+    let shares = curve.column("shares")?.f64()?;
+    let equity = curve.column("equity")?.f64()?;
+    Ok((0..curve.height())
+        .filter_map(|row| Some(EquityPoint {
+            datetime: datetime.get(row)?.to_string(),
+            cash: cash.get(row)?,
+            shares: shares.get(row)?,
+            equity: equity.get(row)?,
+        }))
+        .collect())
+}
+
+impl BacktestReport {
+    pub fn new(
+        params: StrategyParams,
+        run: BacktestRun,
+        metrics: PerformanceMetrics,
+        benchmarks: Vec<BenchmarkComparison>
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let equity_curve = equity_points(&run.equity_curve)?;
+        Ok(BacktestReport {
+            signal: run.signal,
+            params,
+            initial_capital: run.initial_capital,
+            final_equity: run.final_equity,
+            sizing: run.sizing,
+            metrics,
+            benchmarks,
+            trades: run.trades,
+            equity_curve,
+        })
+    }
+}
+
+/// Backtest of an event-driven strategy with every order it sent
+#[derive(Serialize, Debug)]
+pub struct EventBacktestReport {
+    #[serde(flatten)]
+    pub backtest: BacktestReport,
+    pub orders: Vec<OrderRecord>,
+}
+
+/// Ranked results of a parameter search, combinations the strategy rejected are left out
+#[derive(Serialize, Debug)]
+pub struct OptimizationReport {
+    pub grid: ParamGrid,
+    pub metric: Metric,
+    pub combinations: usize,
+    #[serde(flatten)]
+    pub run: OptimizerRun,
+}
+
+/// Symbols ranked by the out-of-sample score of their best parameters
+#[derive(Serialize, Debug)]
+pub struct LeaderboardReport {
+    pub strategy: String,
+    pub metric: Metric,
+    pub run_at: String,
+    #[serde(flatten)]
+    pub leaderboard: Leaderboard,
+    pub grid: ParamGrid,
+    #[serde(flatten)]
+    pub run: LeaderboardRun,
+}
+
+/// Stored leaderboard rows, oldest run first
+#[derive(Serialize, Debug)]
+pub struct LeaderboardHistory {
+    pub strategy: String,
+    pub records: Vec<LeaderboardRecord>,
+}
+
+/// Stored scans, newest first
+#[derive(Serialize, Debug)]
+pub struct ScanHistory {
+    pub records: Vec<ScanRecord>,
+}
+
+/// Submitted jobs, newest first
+#[derive(Serialize, Debug)]
+pub struct JobList {
+    pub jobs: Vec<Job>,
+}
+
+/// Symbols whose latest bar passed the screener, with the strategy that screened them
+#[derive(Serialize, Debug)]
+pub struct ScreenerReport {
+    pub strategy: String,
+    pub params: StrategyParams,
+    #[serde(flatten)]
+    pub screener: Screener,
+    #[serde(flatten)]
+    pub run: ScreenerRun,
+}
+
+/// Scan surface of a parameter grid, with the best scoring and the most robust combination
+#[derive(Serialize, Debug)]
+pub struct SensitivityReport {
+    pub grid: ParamGrid,
+    pub metric: Metric,
+    pub radius: usize,
+    pub heatmap: Heatmap,
+    pub peak: Robustness,
+    pub stable: Robustness,
+    /// Every backtested combination, most robust first
+    pub results: Vec<Robustness>,
+}
+
+/// Closed trades of one backtest, in the order they were closed
+#[derive(Serialize, Debug)]
+pub struct TradeLog {
+    pub signal: String,
+    pub params: StrategyParams,
+    pub trades: Vec<Trade>,
+}
+
+/// Metrics of the best signal found by a scanner, next to the signal data
+#[derive(Serialize, Debug)]
+pub struct BestPerformanceResponse<T: Serialize> {
+    #[serde(flatten)]
+    pub response: T,
+    pub metrics: PerformanceMetrics,
+    pub benchmarks: Vec<BenchmarkComparison>,
+}
+
+/// Out-of-sample results of a walk-forward optimization, metrics are of the stitched equity curve
+#[derive(Serialize, Debug)]
+pub struct WalkForwardReport {
+    #[serde(flatten)]
+    pub walk_forward: WalkForward,
+    pub metrics: PerformanceMetrics,
+    pub benchmarks: Vec<BenchmarkComparison>,
+    pub windows: Vec<WalkForwardWindow>,
+    pub trades: Vec<Trade>,
+    pub equity_curve: Vec<EquityPoint>,
+}
+
+impl WalkForwardReport {
+    pub fn new(
+        walk_forward: WalkForward,
+        wf_run: WalkForwardRun,
+        metrics: PerformanceMetrics,
+        benchmarks: Vec<BenchmarkComparison>
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let equity_curve = equity_points(&wf_run.run.equity_curve)?;
+        Ok(WalkForwardReport {
+            walk_forward,
+            metrics,
+            benchmarks,
+            windows: wf_run.windows,
+            trades: wf_run.run.trades,
+            equity_curve,
+        })
+    }
+}
+
+/// Best parameters of the in-sample bars and how they held up on the out-of-sample bars,
+/// benchmarks, trades and equity curve are of the out-of-sample bars
+#[derive(Serialize, Debug)]
+pub struct ValidationReport {
+    pub split: TrainTestSplit,
+    pub train_start: String,
+    pub train_end: String,
+    pub test_start: String,
+    pub test_end: String,
+    pub params: StrategyParams,
+    pub in_sample: PerformanceMetrics,
+    pub out_of_sample: PerformanceMetrics,
+    pub degradation: Degradation,
+    pub benchmarks: Vec<BenchmarkComparison>,
+    pub trades: Vec<Trade>,
+    pub equity_curve: Vec<EquityPoint>,
+}
+
+impl ValidationReport {
+    pub fn new(
+        split: TrainTestSplit,
+        split_run: SplitRun,
+        benchmarks: Vec<BenchmarkComparison>
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let equity_curve = equity_points(&split_run.run.equity_curve)?;
+        Ok(ValidationReport {
+            split,
+            train_start: split_run.train_start,
+            train_end: split_run.train_end,
+            test_start: split_run.test_start,
+            test_end: split_run.test_end,
+            params: split_run.params,
+            in_sample: split_run.in_sample,
+            out_of_sample: split_run.out_of_sample,
+            degradation: split_run.degradation,
+            benchmarks,
+            trades: split_run.run.trades,
+            equity_curve,
+        })
+    }
+}
+
+/// Backtest metrics of a signal and the Monte Carlo simulations of its trades
+#[derive(Serialize, Debug)]
+pub struct MonteCarloReport {
+    pub signal: String,
+    pub params: StrategyParams,
+    pub metrics: PerformanceMetrics,
+    pub monte_carlo: MonteCarloRun,
+}
+
+#[derive(Serialize, Debug)]
+pub struct PortfolioReport {
+    pub portfolio: Portfolio,
+    pub final_equity: f64,
+    pub metrics: PerformanceMetrics,
+    pub symbols: Vec<SymbolContribution>,
+    pub fills: Vec<Fill>,
+    pub equity_curve: Vec<EquityPoint>,
+}
+
+impl PortfolioReport {
+    pub fn new(
+        portfolio: Portfolio,
+        portfolio_run: PortfolioRun,
+        metrics: PerformanceMetrics
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let equity_curve = equity_points(&portfolio_run.run.equity_curve)?;
+        Ok(PortfolioReport {
+            portfolio,
+            final_equity: portfolio_run.run.final_equity,
+            metrics,
+            symbols: portfolio_run.symbols,
+            fills: portfolio_run.fills,
+            equity_curve,
+        })
+    }
+}
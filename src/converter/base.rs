@@ -1,6 +1,8 @@
 use serde::{Deserialize, Serialize};
 use polars::prelude::*;
 
+use crate::strategy::{EXIT_PRICE_COL, EXIT_REASON_COL, REGIME_COL};
+
 #[derive(Deserialize, Serialize, Debug)]
 pub struct DfBaseData {
     pub datetime: String,
@@ -8,6 +10,12 @@ pub struct DfBaseData {
     pub low: String,
     pub open: String,
     pub close: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exit_reason: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exit_price: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub regime: Option<String>,
 }
 
 #[derive(Deserialize, Serialize, Debug)]
@@ -24,6 +32,9 @@ impl DfBaseData {
             low: String::new(),
             open: String::new(),
             close: String::new(),
+            exit_reason: None,
+            exit_price: None,
+            regime: None,
         }
     }
     pub fn set_base_data(&mut self, df: &DataFrame, col: &Column, row: usize) {
@@ -55,6 +66,22 @@ impl DfBaseData {
             _ => {}
         }
     }
+
+    pub fn set_extra_data(&mut self, df: &DataFrame, col: &Column, row: usize) {
+        let column = df.column(col.name()).unwrap();
+        match col.name().as_str() {
+            EXIT_REASON_COL => {
+                self.exit_reason = column.str().unwrap().get(row).map(|v| v.to_string());
+            }
+            EXIT_PRICE_COL => {
+                self.exit_price = column.f32().unwrap().get(row).map(|v| v.to_string());
+            }
+            REGIME_COL => {
+                self.regime = column.str().unwrap().get(row).map(|v| v.to_string());
+            }
+            _ => {}
+        }
+    }
 }
 
 impl DfColumns {
@@ -1,6 +1,7 @@
 use polars::prelude::*;
 use serde::{Deserialize, Serialize};
 
+use crate::strategy::{EXIT_PRICE_COL, EXIT_REASON_COL, REGIME_COL};
 use super::base::{DfBaseData, DfColumns};
 
 #[derive(Deserialize, Serialize, Debug)]
@@ -51,6 +52,7 @@ impl BollingerBandsConverter {
                 match col.name().as_str() {
                     "high" | "low" | "open" | 
                     "close" | "datetime" => temp.base_data.set_base_data(df, col, row),
+                    EXIT_REASON_COL | EXIT_PRICE_COL | REGIME_COL => temp.base_data.set_extra_data(df, col, row),
                     name if name.contains("Sig") => temp.signal = df.column(col.name())
                                                                     .unwrap()
                                                                     .i32()
@@ -7,8 +7,22 @@ use super::base::DfColumns;
 use super::{
             CrossingMAConverter, CrossingMAResponse, 
             RSIConverter, RSIResponse,
-            BollingerBandsConverter, BollingerBandsResponse
+            BollingerBandsConverter, BollingerBandsResponse,
+            SupportResistanceConverter, SupportResistanceResponse, LevelsResponse,
+            RegimeReport, BacktestReport, BestPerformanceResponse, EventBacktestReport, JobList, LeaderboardHistory, LeaderboardReport, MonteCarloReport, OptimizationReport, PortfolioReport, ScanHistory, ScreenerReport, SensitivityReport, TradeLog, ValidationReport, WalkForwardReport
         };
+use crate::jobs::Job;
+use crate::scanner::{BenchmarkComparison, PerformanceMetrics};
+use crate::strategy::levels::{PivotPoints, PriceZone};
+
+/// Quotes a value holding a separator, a quote or a line break
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
 
 #[derive(Clone, Debug)]
 pub struct DfConverter {
@@ -29,6 +43,7 @@ impl DfConverter {
         let mut low: Vec<f32> = Vec::new();
         let mut open: Vec<f32> = Vec::new();
         let mut close: Vec<f32> = Vec::new();
+        let mut volume: Vec<Option<f32>> = Vec::new();
         for data_point in data.values {
             datetime.push(data_point.datetime);
             match data_point.high.parse::<f32>() {
@@ -55,15 +70,21 @@ impl DfConverter {
                     error!("Error converting price values: {}", e);
                 }
             }
+            volume.push(data_point.volume.and_then(|v| v.parse::<f32>().ok()));
         }
 
-        if let Ok(df) = DataFrame::new(vec![
+        let mut columns: Vec<Column> = vec![
             Series::new("datetime".into(), datetime).into(),
             Series::new("high".into(), high).into(),
             Series::new("low".into(), low).into(),
             Series::new("open".into(), open).into(),
             Series::new("close".into(), close).into(),
-        ]) {
+        ];
+        // Forex and crypto series come without volume
+        if volume.iter().any(|v| v.is_some()) {
+            columns.push(Series::new("volume".into(), volume).into());
+        }
+        if let Ok(df) = DataFrame::new(columns) {
             self.df = Some(df);
             info!("Converted data to dataframe");
             return Ok(());
@@ -100,6 +121,13 @@ impl DfConverter {
         return serde_json::to_string(&response).unwrap();
     }
     
+    pub fn crossingma_best_to_json(df: &DataFrame, metrics: PerformanceMetrics, benchmarks: Vec<BenchmarkComparison>) -> String {
+        let cols_response = Self::get_cols_info(df, &[]);
+        let data_response = CrossingMAConverter::convert_rows(df);
+        let response = CrossingMAResponse::new(cols_response, data_response);
+        Self::best_performance_to_json(response, metrics, benchmarks)
+    }
+
     pub fn rsi_df_to_json(df: &DataFrame) -> String {
         let exclude_cols = ["delta", "gain", "loss", "avg_gain", "avg_loss", "RS_"];
         let cols_response = Self::get_cols_info(&df, &exclude_cols);
@@ -108,6 +136,14 @@ impl DfConverter {
         return serde_json::to_string(&response).unwrap();
     }
 
+    pub fn rsi_best_to_json(df: &DataFrame, metrics: PerformanceMetrics, benchmarks: Vec<BenchmarkComparison>) -> String {
+        let exclude_cols = ["delta", "gain", "loss", "avg_gain", "avg_loss", "RS_"];
+        let cols_response = Self::get_cols_info(df, &exclude_cols);
+        let data_response = RSIConverter::convert_rows(df);
+        let response = RSIResponse::new(cols_response, data_response);
+        Self::best_performance_to_json(response, metrics, benchmarks)
+    }
+
     pub fn bb_df_to_json(df: &DataFrame) -> String {
         let exclude_cols = ["Std"];
         let cols_response = Self::get_cols_info(&df, &exclude_cols);
@@ -115,4 +151,149 @@ impl DfConverter {
         let response = BollingerBandsResponse::new(cols_response, data_response);
         return serde_json::to_string(&response).unwrap();
     }
-}
\ No newline at end of file
+
+    pub fn bb_best_to_json(df: &DataFrame, metrics: PerformanceMetrics, benchmarks: Vec<BenchmarkComparison>) -> String {
+        let exclude_cols = ["Std"];
+        let cols_response = Self::get_cols_info(df, &exclude_cols);
+        let data_response = BollingerBandsConverter::convert_rows(df);
+        let response = BollingerBandsResponse::new(cols_response, data_response);
+        Self::best_performance_to_json(response, metrics, benchmarks)
+    }
+
+    fn best_performance_to_json<T: serde::Serialize>(
+        response: T,
+        metrics: PerformanceMetrics,
+        benchmarks: Vec<BenchmarkComparison>
+    ) -> String {
+        let response = BestPerformanceResponse { response, metrics, benchmarks };
+        serde_json::to_string(&response).unwrap()
+    }
+
+    pub fn sr_df_to_json(df: &DataFrame) -> String {
+        let cols_response = Self::get_cols_info(df, &[]);
+        let data_response = SupportResistanceConverter::convert_rows(df);
+        let response = SupportResistanceResponse::new(cols_response, data_response);
+        serde_json::to_string(&response).unwrap()
+    }
+
+    pub fn levels_to_json(pivots: Vec<PivotPoints>, levels: Vec<PriceZone>) -> String {
+        let response = LevelsResponse::new(pivots, levels);
+        serde_json::to_string(&response).unwrap()
+    }
+
+    pub fn regime_report_to_json(report: &RegimeReport) -> String {
+        serde_json::to_string(report).unwrap()
+    }
+
+    pub fn backtest_report_to_json(report: &BacktestReport) -> String {
+        serde_json::to_string(report).unwrap()
+    }
+
+    pub fn event_backtest_report_to_json(report: &EventBacktestReport) -> String {
+        serde_json::to_string(report).unwrap()
+    }
+
+    pub fn optimization_report_to_json(report: &OptimizationReport) -> String {
+        serde_json::to_string(report).unwrap()
+    }
+
+    pub fn leaderboard_report_to_json(report: &LeaderboardReport) -> String {
+        serde_json::to_string(report).unwrap()
+    }
+
+    pub fn leaderboard_history_to_json(history: &LeaderboardHistory) -> String {
+        serde_json::to_string(history).unwrap()
+    }
+
+    pub fn scan_history_to_json(history: &ScanHistory) -> String {
+        serde_json::to_string(history).unwrap()
+    }
+
+    pub fn job_to_json(job: &Job) -> String {
+        serde_json::to_string(job).unwrap()
+    }
+
+    pub fn job_list_to_json(list: &JobList) -> String {
+        serde_json::to_string(list).unwrap()
+    }
+
+    pub fn screener_report_to_json(report: &ScreenerReport) -> String {
+        serde_json::to_string(report).unwrap()
+    }
+
+    pub fn sensitivity_report_to_json(report: &SensitivityReport) -> String {
+        serde_json::to_string(report).unwrap()
+    }
+
+    pub fn monte_carlo_report_to_json(report: &MonteCarloReport) -> String {
+        serde_json::to_string(report).unwrap()
+    }
+
+    pub fn portfolio_report_to_json(report: &PortfolioReport) -> String {
+        serde_json::to_string(report).unwrap()
+    }
+
+    pub fn trade_log_to_json(log: &TradeLog) -> String {
+        serde_json::to_string(log).unwrap()
+    }
+
+    /// One row per trade with a header, optional values are left empty
+    pub fn trade_log_to_csv(log: &TradeLog) -> String {
+        let mut csv = String::from(
+            "side,entry_datetime,entry_price,exit_datetime,exit_price,shares,bars_held,pnl,return_pct,\
+            mae_pct,mfe_pct,exit_reason,commission,slippage,borrow_cost,regime\n"
+        );
+        for trade in &log.trades {
+            csv.push_str(&format!(
+                "{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{}\n",
+                trade.side.as_str(),
+                csv_field(&trade.entry_datetime),
+                trade.entry_price,
+                csv_field(&trade.exit_datetime),
+                trade.exit_price,
+                trade.shares,
+                trade.bars_held,
+                trade.pnl,
+                trade.return_pct,
+                trade.mae_pct,
+                trade.mfe_pct,
+                trade.exit_reason.as_str(),
+                trade.commission,
+                trade.slippage,
+                trade.borrow_cost,
+                csv_field(trade.regime.as_deref().unwrap_or_default()),
+            ));
+        }
+        csv
+    }
+
+    pub fn validation_report_to_json(report: &ValidationReport) -> String {
+        serde_json::to_string(report).unwrap()
+    }
+
+    pub fn walk_forward_report_to_json(report: &WalkForwardReport) -> String {
+        serde_json::to_string(report).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::scanner::Backtest;
+    use crate::strategy::{StrategyParams, BUY_SIGNAL};
+    use crate::test_fixtures::{bars, with_signals};
+    use super::*;
+
+    #[test]
+    fn trade_log_csv_quotes_fields_with_commas() {
+        let df = with_signals(bars(&[100.0, 100.0, 110.0]), "Sig", &[BUY_SIGNAL, 0, 0]);
+        let mut trades = Backtest::new().run(&df, "Sig").unwrap().trades;
+        trades[0].regime = Some("trend, up".to_string());
+        let csv = DfConverter::trade_log_to_csv(&TradeLog { signal: "Sig".to_string(), params: StrategyParams::default(), trades });
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].split(',').count(), 16);
+        // The excursions in between are f32 percentages
+        assert!(lines[1].starts_with("long,2024-01-02,100,2024-01-03,110,100,1,1000,10,"));
+        assert!(lines[1].ends_with(",end_of_data,0,0,0,\"trend, up\""));
+    }
+}
@@ -2,10 +2,16 @@ mod df_converter;
 mod crossing_avg_conv;
 mod rsi_conv;
 mod bb_conv;
+mod sr_conv;
+mod regime_conv;
+mod backtest_conv;
 mod base;
 mod response;
 
 pub use df_converter::DfConverter;
 pub use crossing_avg_conv::{CrossingMAConverter, CrossingMAResponse};
 pub use rsi_conv::{RSIConverter, RSIResponse};
-pub use bb_conv::{BollingerBandsConverter, BollingerBandsResponse};
\ No newline at end of file
+pub use bb_conv::{BollingerBandsConverter, BollingerBandsResponse};
+pub use sr_conv::{SupportResistanceConverter, SupportResistanceResponse, LevelsResponse};
+pub use regime_conv::RegimeReport;
+pub use backtest_conv::{BacktestReport, BestPerformanceResponse, EventBacktestReport, JobList, LeaderboardHistory, LeaderboardReport, MonteCarloReport, OptimizationReport, PortfolioReport, ScanHistory, ScreenerReport, SensitivityReport, TradeLog, ValidationReport, WalkForwardReport};
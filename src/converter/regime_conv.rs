@@ -0,0 +1,17 @@
+use std::collections::HashMap;
+use serde::Serialize;
+
+use crate::scanner::RegimePerformance;
+
+#[derive(Serialize, Debug)]
+pub struct RegimeReport {
+    pub signal: String,
+    pub result: f32,
+    pub regimes: HashMap<String, RegimePerformance>,
+}
+
+impl RegimeReport {
+    pub fn new(signal: String, result: f32, regimes: HashMap<String, RegimePerformance>) -> Self {
+        RegimeReport { signal, result, regimes }
+    }
+}
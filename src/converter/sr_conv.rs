@@ -0,0 +1,92 @@
+use polars::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::strategy::{EXIT_PRICE_COL, EXIT_REASON_COL, REGIME_COL};
+use crate::strategy::levels::{PivotPoints, PriceZone};
+use super::base::{DfBaseData, DfColumns};
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct SupportResistanceData {
+    #[serde(flatten)]
+    pub base_data: DfBaseData,
+    pub support: String,
+    pub resistance: String,
+    pub signal: String
+}
+
+impl SupportResistanceData {
+    pub fn new() -> Self {
+        let base_data = DfBaseData::new();
+        SupportResistanceData {
+            base_data,
+            support: String::new(),
+            resistance: String::new(),
+            signal: String::new()
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct SupportResistanceResponse {
+    pub columns: DfColumns,
+    pub data: Vec<SupportResistanceData>,
+}
+
+impl SupportResistanceResponse {
+    pub fn new(columns: DfColumns, data: Vec<SupportResistanceData>) -> Self {
+        SupportResistanceResponse { columns, data }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct LevelsResponse {
+    pub pivots: Vec<PivotPoints>,
+    pub levels: Vec<PriceZone>,
+}
+
+impl LevelsResponse {
+    pub fn new(pivots: Vec<PivotPoints>, levels: Vec<PriceZone>) -> Self {
+        LevelsResponse { pivots, levels }
+    }
+}
+
+pub struct SupportResistanceConverter;
+
+impl SupportResistanceConverter {
+    pub fn convert_rows(df: &DataFrame) -> Vec<SupportResistanceData> {
+        let mut data_response: Vec<SupportResistanceData> = Vec::new();
+        for row in 0..df.height() {
+            let mut temp = SupportResistanceData::new();
+            for col in df.get_columns() {
+                match col.name().as_str() {
+                    "high" | "low" | "open" | 
+                    "close" | "datetime" => temp.base_data.set_base_data(df, col, row),
+                    EXIT_REASON_COL | EXIT_PRICE_COL | REGIME_COL => temp.base_data.set_extra_data(df, col, row),
+                    name if name.contains("Sig") => temp.signal = df.column(col.name())
+                                                                    .unwrap()
+                                                                    .i32()
+                                                                    .unwrap()
+                                                                    .get(row)
+                                                                    .unwrap()
+                                                                    .to_string(),
+                    name if name.starts_with("Support") => {
+                        temp.support = Self::get_f32_col_value(df, col.name(), row)
+                    }
+                    name if name.starts_with("Resistance") => {
+                        temp.resistance = Self::get_f32_col_value(df, col.name(), row)
+                    }
+                    _ => continue
+                }
+            }
+            data_response.push(temp);
+        }
+        data_response
+    }
+
+    fn get_f32_col_value(df: &DataFrame, col_name: &str, row: usize) -> String {
+        match df.column(col_name).unwrap().f32().unwrap().get(row) {
+            Some(value) => value.to_string(),
+            None => "NaN".to_string(),
+        }
+    }
+}
@@ -1,17 +1,28 @@
 use indexmap::IndexMap;
 use polars::{frame::{row::AnyValueBuffer}, prelude::*};
 use duckdb::{Connection, Result};
-use std::{fs, path::Path, sync::{Mutex, MutexGuard}};
+use std::{fs, path::Path, sync::{atomic::{AtomicUsize, Ordering}, Mutex, MutexGuard, OnceLock}};
 use log::{error, debug};
 
+/// Numbers the parquet files of appends so two of them never share a file
+static APPENDS: AtomicUsize = AtomicUsize::new(0);
+
 #[derive(Debug, Clone)]
 pub struct DbManager {
     data_dir: String,
     db_path: Arc<Mutex<String>>
 }
 
+/// Every caller shares the same manager of the price cache, so its lock serializes them
 impl Default for DbManager {
     fn default() -> Self {
+        static CACHE: OnceLock<DbManager> = OnceLock::new();
+        CACHE.get_or_init(DbManager::cache).clone()
+    }
+}
+
+impl DbManager {
+    fn cache() -> Self {
         let base_path = "data";
         let db_path = format!("{}/stocks.db", base_path);
         let data_dir = Path::new(base_path);
@@ -24,15 +35,26 @@ impl Default for DbManager {
             db_path: Arc::new(Mutex::new(String::from(db_path))) 
         }
     }
-}
 
-impl DbManager {
-    fn new(data_dir: String, db_path: String) -> Self {
+    pub(crate) fn new(data_dir: String, db_path: String) -> Self {
         DbManager {
             data_dir: data_dir,
             db_path: Arc::new(Mutex::new(db_path))
         }
     }
+
+    /// Database of stored results, kept apart from the price cache so the daily clean up leaves it.
+    /// Every caller shares the same manager.
+    pub fn results() -> Self {
+        static RESULTS: OnceLock<DbManager> = OnceLock::new();
+        RESULTS.get_or_init(|| {
+            let data_dir = "data/results";
+            if let Err(e) = fs::create_dir_all(data_dir) {
+                error!("Error creating results dir: {}", e);
+            }
+            DbManager::new(data_dir.to_string(), format!("{}/results.db", data_dir))
+        }).clone()
+    }
     
     fn acquire_db(&self) -> anyhow::Result<MutexGuard<'_, String>> {
         self.db_path.lock()
@@ -59,6 +81,52 @@ impl DbManager {
         Ok(())
     }
     
+    /// Appends the rows of `df` to a table, creating it from them when it does not exist yet
+    pub fn append_table(&self, table_name: String, df: &mut DataFrame) -> Result<()> {
+        self.insert_rows(&table_name, df, String::new())?;
+        debug!("Appended {} rows to {} table", df.height(), table_name);
+        Ok(())
+    }
+
+    /// Replaces the rows of a table whose `key_column` is `key` with the rows of `df`
+    pub fn upsert_rows(&self, table_name: String, key_column: &str, key: &str, df: &mut DataFrame) -> Result<()> {
+        let delete = format!("DELETE FROM {} WHERE {} = '{}';", table_name, key_column, key.replace('\'', "''"));
+        self.insert_rows(&table_name, df, delete)?;
+        debug!("Upserted {} in {} table", key, table_name);
+        Ok(())
+    }
+
+    /// Runs `before` and inserts the rows of `df` in one go, creating the table from them when it
+    /// does not exist yet. The rows go through a parquet file of their own, removed afterwards.
+    fn insert_rows(&self, table_name: &str, df: &mut DataFrame, before: String) -> Result<()> {
+        let db_guard = self.acquire_db().unwrap();
+        let parquet_file = format!(
+            "{}/{}_append_{}_{}.parquet",
+            self.data_dir,
+            table_name,
+            std::process::id(),
+            APPENDS.fetch_add(1, Ordering::Relaxed)
+        );
+        let mut file = std::fs::File::create(parquet_file.clone()).expect("Failed to create file");
+        ParquetWriter::new(&mut file)
+            .with_compression(ParquetCompression::Snappy)
+            .finish(df)
+            .expect("Error writing parquet file");
+
+        let inserted = Connection::open(db_guard.clone().as_str()).and_then(|conn| conn.execute_batch(&format!(
+            "CREATE TABLE IF NOT EXISTS {table} AS SELECT * FROM read_parquet('{file}') LIMIT 0;
+            {before}
+            INSERT INTO {table} SELECT * FROM read_parquet('{file}');",
+            table = table_name,
+            file = parquet_file,
+            before = before
+        )));
+        if let Err(e) = fs::remove_file(&parquet_file) {
+            error!("Error removing {}: {}", parquet_file, e);
+        }
+        inserted
+    }
+
     pub fn table_exists(&self, table_name: String) -> Result<bool> {
         let db_guard = self.acquire_db().unwrap();
         let conn = Connection::open(db_guard.clone().as_str())?;
@@ -74,6 +142,17 @@ impl DbManager {
         Ok(table_exists)
     }
     
+    /// Names of every cached table, in alphabetical order
+    pub fn list_tables(&self) -> Result<Vec<String>> {
+        let db_guard = self.acquire_db().unwrap();
+        let conn = Connection::open(db_guard.clone().as_str())?;
+        let mut stmt = conn.prepare(
+            "SELECT table_name FROM information_schema.tables ORDER BY table_name"
+        )?;
+        let tables = stmt.query_map([], |row| row.get(0))?.collect::<Result<Vec<String>>>()?;
+        Ok(tables)
+    }
+
     pub fn get_table(&self, table_name: String) -> Result<DataFrame> {
         let mut series_vec: Vec<Series> = Vec::new();
         let column_names_types = self.get_cols_names_types(table_name.clone())?;
@@ -10,7 +10,9 @@ pub struct StockDataPoint {
     pub open: String,
     pub close: String,
     pub high: String,
-    pub low: String
+    pub low: String,
+    #[serde(default)]
+    pub volume: Option<String>
 }
 
 #[derive(Deserialize, Debug)]
@@ -0,0 +1,139 @@
+use std::error::Error;
+use actix_web::{web::{self, Query}, HttpResponse};
+
+use crate::converter::{BacktestReport, DfConverter, EventBacktestReport, MonteCarloReport, TradeLog};
+use crate::scanner::{Backtest, BenchmarkComparison, DonchianBreakout, EventBacktest, EventStrategy, MonteCarlo, PerformanceMetrics, SignalAdapter};
+use super::strategy_config::StrategyConfig;
+use super::{error_response, load_benchmark, load_prices, respond, DateRange, OrBadRequest, RequestError, StrategyQuery};
+
+pub async fn get_backtest(
+    path: web::Path<(String, String)>,
+    query: Query<StrategyQuery>
+) -> HttpResponse {
+    let (name, symbol) = path.into_inner();
+    let report = backtest_report(&name, &symbol, &query).await;
+    respond("Error running backtest", report.map(|report| DfConverter::backtest_report_to_json(&report)))
+}
+
+/// Event-driven backtest of the built-in `donchian` breakout or of a registry strategy's signals
+pub async fn get_event_backtest(
+    path: web::Path<(String, String)>,
+    query: Query<StrategyQuery>
+) -> HttpResponse {
+    let (name, symbol) = path.into_inner();
+    let report = event_backtest_report(&name, &symbol, &query).await;
+    respond("Error running backtest", report.map(|report| DfConverter::event_backtest_report_to_json(&report)))
+}
+
+/// Trade log of a backtest, `format=csv` downloads it as a CSV file instead of JSON
+pub async fn get_trade_log(
+    path: web::Path<(String, String)>,
+    query: Query<StrategyQuery>
+) -> HttpResponse {
+    let (name, symbol) = path.into_inner();
+    let csv = match query.params.get("format").map(String::as_str) {
+        None | Some("json") => false,
+        Some("csv") => true,
+        Some(other) => return RequestError::BadRequest(format!("Unknown format '{}', use json or csv", other)).to_response()
+    };
+    match trade_log(&name, &symbol, &query).await {
+        Ok(log) if csv => HttpResponse::Ok()
+            .content_type("text/csv")
+            .insert_header(("Content-Disposition", format!("attachment; filename=\"{}_{}_trades.csv\"", symbol, name)))
+            .body(DfConverter::trade_log_to_csv(&log)),
+        Ok(log) => HttpResponse::Ok()
+            .content_type("application/json")
+            .body(DfConverter::trade_log_to_json(&log)),
+        Err(e) => error_response("Error running backtest", e)
+    }
+}
+
+pub async fn get_monte_carlo(
+    path: web::Path<(String, String)>,
+    query: Query<StrategyQuery>
+) -> HttpResponse {
+    let (name, symbol) = path.into_inner();
+    let report = monte_carlo_report(&name, &symbol, &query).await;
+    respond("Error running Monte Carlo", report.map(|report| DfConverter::monte_carlo_report_to_json(&report)))
+}
+
+pub(super) async fn backtest_report(
+    name: &str,
+    symbol: &str,
+    query: &StrategyQuery
+) -> Result<BacktestReport, Box<dyn Error>> {
+    let config = StrategyConfig::from_query(name, &query.params)?;
+    let backtest = Backtest::from_query(&query.params).or_bad_request("Invalid backtest settings")?;
+    let benchmark = load_benchmark(query.params.get("benchmark").cloned(), query).await.or_bad_request("Error loading benchmark")?;
+    let prices = load_prices(symbol, query.start_date(), query.end_date()).await?;
+    let mut strategy = config.build(prices.clone())?;
+    let df = strategy.calc_signal()?;
+    let run = backtest.run(&df, &strategy.signal_col())?;
+    let metrics = PerformanceMetrics::from_run(&run)?;
+    let benchmarks = BenchmarkComparison::all(&run, &prices, benchmark.as_ref())?;
+    BacktestReport::new(config.params, run, metrics, benchmarks)
+}
+
+async fn event_backtest_report(
+    name: &str,
+    symbol: &str,
+    query: &StrategyQuery
+) -> Result<EventBacktestReport, Box<dyn Error>> {
+    let engine = EventBacktest::from_query(&query.params).or_bad_request("Invalid backtest settings")?;
+    let donchian = match name {
+        "donchian" => Some(DonchianBreakout::from_query(&query.params).or_bad_request("Invalid donchian settings")?),
+        _ => None
+    };
+    let config = match donchian {
+        Some(_) => None,
+        None => Some(StrategyConfig::from_query(name, &query.params)?),
+    };
+    let benchmark = load_benchmark(query.params.get("benchmark").cloned(), query).await.or_bad_request("Error loading benchmark")?;
+    let prices = load_prices(symbol, query.start_date(), query.end_date()).await?;
+    let (events, params) = match (donchian, config) {
+        (Some(mut donchian), _) => (engine.run(&prices, &mut donchian)?, donchian.params()),
+        (None, Some(config)) => {
+            let mut strategy = config.build(prices.clone())?;
+            let df = strategy.calc_signal()?;
+            let mut adapter = SignalAdapter::from_query(&df, &strategy.signal_col(), config.params.clone(), &query.params)?;
+            (engine.run(&df, &mut adapter)?, config.params)
+        }
+        (None, None) => return Err("No strategy to run".into()),
+    };
+    let metrics = PerformanceMetrics::from_run(&events.run)?;
+    let benchmarks = BenchmarkComparison::all(&events.run, &prices, benchmark.as_ref())?;
+    Ok(EventBacktestReport {
+        backtest: BacktestReport::new(params, events.run, metrics, benchmarks)?,
+        orders: events.orders,
+    })
+}
+
+async fn trade_log(name: &str, symbol: &str, query: &StrategyQuery) -> Result<TradeLog, Box<dyn Error>> {
+    let config = StrategyConfig::from_query(name, &query.params)?;
+    let backtest = Backtest::from_query(&query.params).or_bad_request("Invalid backtest settings")?;
+    let prices = load_prices(symbol, query.start_date(), query.end_date()).await?;
+    let mut strategy = config.build(prices)?;
+    let df = strategy.calc_signal()?;
+    let run = backtest.run(&df, &strategy.signal_col())?;
+    Ok(TradeLog { signal: run.signal, params: config.params, trades: run.trades })
+}
+
+async fn monte_carlo_report(
+    name: &str,
+    symbol: &str,
+    query: &StrategyQuery
+) -> Result<MonteCarloReport, Box<dyn Error>> {
+    let config = StrategyConfig::from_query(name, &query.params)?;
+    let backtest = Backtest::from_query(&query.params).or_bad_request("Invalid backtest settings")?;
+    let monte_carlo = MonteCarlo::from_query(&query.params).or_bad_request("Invalid Monte Carlo settings")?;
+    let prices = load_prices(symbol, query.start_date(), query.end_date()).await?;
+    let mut strategy = config.build(prices)?;
+    let df = strategy.calc_signal()?;
+    let run = backtest.run(&df, &strategy.signal_col())?;
+    Ok(MonteCarloReport {
+        signal: run.signal.clone(),
+        params: config.params,
+        metrics: PerformanceMetrics::from_run(&run)?,
+        monte_carlo: monte_carlo.run(&run)?,
+    })
+}
@@ -0,0 +1,85 @@
+use actix_web::{web, HttpResponse};
+
+use crate::converter::{DfConverter, JobList};
+use crate::jobs::{JobFuture, JobKind, JobQueue, JobRequest};
+use super::backtest::backtest_report;
+use super::optimize::{optimization_report, sensitivity_report};
+use super::portfolio::leaderboard_report;
+use super::StrategyQuery;
+
+/// Queues a backtest, optimization, sensitivity or leaderboard run with the query parameters of its
+/// endpoint, to be polled under `/jobs/{id}`
+pub async fn post_job(request: web::Json<JobRequest>) -> HttpResponse {
+    let Some(queue) = JobQueue::global() else {
+        return HttpResponse::ServiceUnavailable().body("The job queue is not running")
+    };
+    match queue.submit(request.into_inner()) {
+        Ok(job) => HttpResponse::Accepted()
+            .content_type("application/json")
+            .body(DfConverter::job_to_json(&job)),
+        Err(e) => e.to_response()
+    }
+}
+
+pub async fn get_jobs() -> HttpResponse {
+    let jobs = JobQueue::global().map(|queue| queue.list()).unwrap_or_default();
+    HttpResponse::Ok()
+        .content_type("application/json")
+        .body(DfConverter::job_list_to_json(&JobList { jobs }))
+}
+
+pub async fn get_job(id: web::Path<String>) -> HttpResponse {
+    let Some(queue) = JobQueue::global() else {
+        return HttpResponse::ServiceUnavailable().body("The job queue is not running")
+    };
+    match queue.get(&id) {
+        Ok(job) => HttpResponse::Ok()
+            .content_type("application/json")
+            .body(DfConverter::job_to_json(&job)),
+        Err(e) => e.to_response()
+    }
+}
+
+pub async fn get_job_result(id: web::Path<String>) -> HttpResponse {
+    let Some(queue) = JobQueue::global() else {
+        return HttpResponse::ServiceUnavailable().body("The job queue is not running")
+    };
+    match queue.result(&id) {
+        Ok(result) => HttpResponse::Ok()
+            .content_type("application/json")
+            .body(result),
+        Err(e) => e.to_response()
+    }
+}
+
+/// Cancels a queued job, or stops a running one at its next backtest
+pub async fn delete_job(id: web::Path<String>) -> HttpResponse {
+    let Some(queue) = JobQueue::global() else {
+        return HttpResponse::ServiceUnavailable().body("The job queue is not running")
+    };
+    match queue.cancel(&id) {
+        Ok(job) => HttpResponse::Ok()
+            .content_type("application/json")
+            .body(DfConverter::job_to_json(&job)),
+        Err(e) => e.to_response()
+    }
+}
+
+/// Runs a job through the report function of its endpoint
+pub fn run_job(request: JobRequest) -> JobFuture {
+    Box::pin(async move {
+        let mut params = request.params;
+        let query = StrategyQuery {
+            start_date: params.remove("start_date"),
+            end_date: params.remove("end_date"),
+            params,
+        };
+        let (name, symbol) = (request.strategy.as_str(), request.symbol.unwrap_or_default());
+        Ok(match request.kind {
+            JobKind::Backtest => DfConverter::backtest_report_to_json(&backtest_report(name, &symbol, &query).await?),
+            JobKind::Optimize => DfConverter::optimization_report_to_json(&optimization_report(name, &symbol, &query).await?),
+            JobKind::Sensitivity => DfConverter::sensitivity_report_to_json(&sensitivity_report(name, &symbol, &query).await?),
+            JobKind::Leaderboard => DfConverter::leaderboard_report_to_json(&leaderboard_report(name, &query).await?),
+        })
+    })
+}
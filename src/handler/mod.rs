@@ -1,11 +1,30 @@
+mod backtest;
+mod jobs;
+mod optimize;
+mod portfolio;
+mod strategy_config;
+
+use std::{collections::HashMap, error::Error};
 use actix_web::{web::{self, Query}, HttpResponse};
+use polars::frame::DataFrame;
 use log::error;
 use serde::Deserialize;
 
-use crate::{fetch::StockFetcher, scanner::{ScannerBollingerBands, ScannerCrossingMA, ScannerPerformance, ScannerRSI}};
-use crate::strategy::{Strategy, StrategyCrossingMA, StrategyRSI, StrategyBollingerBands};
-use crate::converter::DfConverter;
+use crate::{fetch::StockFetcher, scanner::{Backtest, Metric, Selection, SymbolError, WalkForwardMode}};
+use crate::strategy::{Strategy, StrategyCrossingMA, StrategyRSI, StrategyBollingerBands, StrategyRegistry, StrategySpec};
+use crate::converter::{DfConverter, RegimeReport};
 use crate::db::DbManager;
+use crate::strategy::levels::{self, PivotMethod, PivotPeriod};
+use crate::strategy::{RegimeClassifier, REGIME_COL};
+use strategy_config::{StrategyConfig, StrategyConfigError};
+
+pub use backtest::{get_backtest, get_event_backtest, get_monte_carlo, get_trade_log};
+pub use jobs::{delete_job, get_job, get_job_result, get_jobs, post_job, run_job};
+pub use optimize::{
+    get_best_performance_bb, get_best_performance_ewma, get_best_performance_rsi, get_best_performance_sma,
+    get_grid_search, get_scan_history, get_sensitivity
+};
+pub use portfolio::{get_leaderboard, get_leaderboard_history, get_portfolio, get_screener};
 
 #[derive(Deserialize)]
 pub struct DateParams {
@@ -18,7 +37,157 @@ pub struct QueryParams {
     start_date: Option<String>,
     end_date: Option<String>,
     short_ma: Option<usize>,
-    long_ma: Option<usize>
+    long_ma: Option<usize>,
+    metric: Option<Metric>,
+    benchmark: Option<String>,
+    walk_forward: Option<WalkForwardMode>,
+    train_bars: Option<usize>,
+    test_bars: Option<usize>,
+    test_ratio: Option<f32>,
+    split_date: Option<String>,
+    select: Option<Selection>,
+    radius: Option<usize>,
+    refresh: Option<bool>,
+}
+
+impl QueryParams {
+    fn out_of_sample(&self) -> bool {
+        self.walk_forward.is_some() || self.test_ratio.is_some() || self.split_date.is_some()
+    }
+}
+
+#[derive(Deserialize)]
+pub struct StrategyQuery {
+    start_date: Option<String>,
+    end_date: Option<String>,
+    #[serde(flatten)]
+    params: HashMap<String, String>,
+}
+
+#[derive(Deserialize)]
+pub struct LevelsQuery {
+    start_date: Option<String>,
+    end_date: Option<String>,
+    method: Option<PivotMethod>,
+    period: Option<PivotPeriod>,
+    swing_window: Option<usize>,
+    tolerance_pct: Option<f32>,
+    min_touches: Option<usize>,
+}
+
+#[derive(Deserialize)]
+pub struct ScanHistoryQuery {
+    strategy: Option<String>,
+    symbol: Option<String>,
+    limit: Option<usize>,
+}
+
+pub trait DateRange {
+    fn start_date(&self) -> Option<String>;
+    fn end_date(&self) -> Option<String>;
+}
+
+impl DateRange for QueryParams {
+    fn start_date(&self) -> Option<String> {
+        self.start_date.clone()
+    }
+    fn end_date(&self) -> Option<String> {
+        self.end_date.clone()
+    }
+}
+
+impl DateRange for LevelsQuery {
+    fn start_date(&self) -> Option<String> {
+        self.start_date.clone()
+    }
+    fn end_date(&self) -> Option<String> {
+        self.end_date.clone()
+    }
+}
+
+impl DateRange for StrategyQuery {
+    fn start_date(&self) -> Option<String> {
+        self.start_date.clone()
+    }
+    fn end_date(&self) -> Option<String> {
+        self.end_date.clone()
+    }
+}
+
+#[derive(Debug)]
+pub enum RequestError {
+    NotFound(String),
+    BadRequest(String),
+}
+
+impl std::fmt::Display for RequestError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RequestError::NotFound(e) | RequestError::BadRequest(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl Error for RequestError {}
+
+impl RequestError {
+    pub fn to_response(&self) -> HttpResponse {
+        match self {
+            RequestError::NotFound(_) => HttpResponse::NotFound().body(self.to_string()),
+            RequestError::BadRequest(_) => HttpResponse::BadRequest().body(self.to_string()),
+        }
+    }
+}
+
+/// Turns the error of a query parser into a bad request
+trait OrBadRequest<T> {
+    fn or_bad_request(self, context: &str) -> Result<T, RequestError>;
+}
+
+impl<T> OrBadRequest<T> for Result<T, Box<dyn Error>> {
+    fn or_bad_request(self, context: &str) -> Result<T, RequestError> {
+        self.map_err(|e| RequestError::BadRequest(format!("{}: {}", context, e)))
+    }
+}
+
+fn respond(context: &str, body: Result<String, Box<dyn Error>>) -> HttpResponse {
+    match body {
+        Ok(body) => HttpResponse::Ok()
+            .content_type("application/json")
+            .body(body),
+        Err(e) => error_response(context, e),
+    }
+}
+
+/// Client errors answer with their own status, anything else is a server error
+fn error_response(context: &str, e: Box<dyn Error>) -> HttpResponse {
+    if let Some(e) = e.downcast_ref::<RequestError>() {
+        return e.to_response();
+    }
+    if let Some(e) = e.downcast_ref::<StrategyConfigError>() {
+        return e.to_response();
+    }
+    error!("{}: {}", context, e);
+    HttpResponse::InternalServerError()
+        .body(format!("{}: {}", context, e))
+}
+
+fn strategy_spec(name: &str) -> Result<&'static StrategySpec, RequestError> {
+    StrategyRegistry::global()
+        .get(name)
+        .ok_or_else(|| RequestError::NotFound(format!("Unknown strategy '{}'", name)))
+}
+
+/// Rows of a results table, none when nothing was stored yet
+fn stored_records<T, F>(table: &str, from_df: F) -> Result<Vec<T>, Box<dyn Error>>
+where
+    F: FnOnce(&DataFrame) -> Result<Vec<T>, Box<dyn Error>>
+{
+    let db = DbManager::results();
+    match db.table_exists(table.to_string())? {
+        true => from_df(&db.get_table(table.to_string())?),
+        false => Ok(Vec::new()),
+    }
 }
 
 pub async fn get_price(
@@ -43,6 +212,92 @@ pub async fn get_price(
     }
 }
 
+pub async fn get_strategies() -> HttpResponse {
+    match serde_json::to_string(&StrategyRegistry::global().specs()) {
+        Ok(response) => HttpResponse::Ok()
+            .content_type("application/json")
+            .body(response),
+        Err(e) => HttpResponse::InternalServerError()
+            .body(format!("Error listing strategies: {}", e))
+    }
+}
+
+pub async fn get_strategy_signal(
+    path: web::Path<(String, String)>,
+    query: Query<StrategyQuery>
+) -> HttpResponse {
+    let (name, symbol) = path.into_inner();
+    let config = match StrategyConfig::from_query(&name, &query.params) {
+        Ok(config) => config,
+        Err(e) => return e.to_response()
+    };
+    fetch_and_process(
+        symbol,
+        &query,
+        |df_proc, _| {
+            let mut strategy = config.build(df_proc.df.unwrap())?;
+            let df = strategy.calc_signal()?;
+            Ok((config.spec.to_json)(&df))
+        }
+    ).await
+}
+
+pub async fn get_regime_performance(
+    path: web::Path<(String, String)>,
+    query: Query<StrategyQuery>
+) -> HttpResponse {
+    let (name, symbol) = path.into_inner();
+    let config = match StrategyConfig::from_query(&name, &query.params) {
+        Ok(config) => config,
+        Err(e) => return e.to_response()
+    };
+    fetch_and_process(
+        symbol,
+        &query,
+        |df_proc, _| {
+            let mut strategy = config.build(df_proc.df.unwrap())?;
+            let mut df = strategy.calc_signal()?;
+            if df.column(REGIME_COL).is_err() {
+                df = RegimeClassifier::default().with_regime(&df)?;
+            }
+            let signal_name = strategy.signal_col();
+            let run = Backtest::new().run(&df, &signal_name)?;
+            let report = RegimeReport::new(
+                signal_name,
+                run.total_return_pct,
+                run.regimes.unwrap_or_default()
+            );
+            Ok(DfConverter::regime_report_to_json(&report))
+        }
+    ).await
+}
+
+
+pub async fn get_levels(
+    symbol: web::Path<String>,
+    query: Query<LevelsQuery>
+) -> HttpResponse {
+    fetch_and_process(
+        symbol.clone(),
+        &query,
+        |df_proc, query| {
+            let df = df_proc.df.unwrap();
+            let pivots = levels::pivot_points(
+                &df,
+                query.method.unwrap_or(PivotMethod::Classic),
+                query.period.unwrap_or(PivotPeriod::Month)
+            )?;
+            let zones = levels::support_resistance(
+                &df,
+                query.swing_window.unwrap_or(5),
+                query.tolerance_pct.unwrap_or(1.0),
+                query.min_touches.unwrap_or(2)
+            )?;
+            Ok(DfConverter::levels_to_json(pivots, zones))
+        }
+    ).await
+}
+
 pub async fn get_sma_signal(
     symbol: web::Path<String>, 
     query:Query<QueryParams>
@@ -77,84 +332,13 @@ pub async fn get_rsi_signal(
                 }
                 Err(e) => {
                     error!("Error calculating signal: {}", e);
-                    Err(Box::new(std::io::Error::new(
-                        std::io::ErrorKind::Other,
-                        format!("Error calculating signal: {}", e)
-                    )))
+                    Err(Box::new(std::io::Error::other(format!("Error calculating signal: {}", e))))
                 }
             }
         }
     ).await
 }
 
-pub async fn get_best_performance_sma(
-    symbol: web::Path<String>, 
-    query:Query<QueryParams>
-    ) -> HttpResponse {
-    get_best_performance_ma(symbol, query, "SMA").await
-}
-
-pub async fn get_best_performance_ewma(
-    symbol: web::Path<String>, 
-    query:Query<QueryParams>
-    ) -> HttpResponse {
-    get_best_performance_ma(symbol, query, "EWMA").await
-}
-
-pub async fn get_best_performance_rsi(
-    symbol: web::Path<String>, 
-    query: Query<QueryParams>,
-) -> HttpResponse {
-    fetch_and_process(
-        symbol.clone(), 
-        &query,
-        |df_proc, _| {
-            let from_ma = 5;
-            let to_ma = 20;
-            let ma_window = 5;
-            let upper_bound = 80;
-            let lower_bound = 20;
-            let rsi_str = StrategyRSI::new(df_proc.df.unwrap(), ma_window, upper_bound, lower_bound);
-            let mut scanner = ScannerRSI::new(rsi_str.clone(), from_ma, to_ma);
-            match scanner.get_best_performance_df() {
-                Some(df) => {
-                    let response = DfConverter::rsi_df_to_json(&df);
-                    Ok(response)
-                },
-                None => Err(Box::new(std::io::Error::new(
-                    std::io::ErrorKind::Other,
-                    "No RSI best performance found"
-                )))
-            }
-        }
-    ).await
-}
-
-pub async fn get_best_performance_bb (
-    symbol: web::Path<String>,
-    query: Query<QueryParams>
-) -> HttpResponse {
-    fetch_and_process(
-        symbol.clone(),
-        &query, 
-        |df_proc, _| {
-            let from_ma = 5;
-            let to_ma = 200;
-            let bb = StrategyBollingerBands::new(df_proc.df.unwrap(), from_ma);
-            let mut scanner = ScannerBollingerBands::new(bb, from_ma, to_ma);
-            match scanner.get_best_performance_df() {
-                Some(df) => {
-                    let response = DfConverter::bb_df_to_json(&df);
-                    Ok(response)
-                },
-                None => Err(Box::new(std::io::Error::new(
-                    std::io::ErrorKind::Other,
-                    "No Bollinger bands best performance found"
-                )))
-            }
-        }).await
-}
-
 pub async fn get_bb_signal(
     symbol: web::Path<String>,
     query: Query<QueryParams>
@@ -175,57 +359,13 @@ pub async fn get_bb_signal(
                 }
                 Err(e) => {
                     error!("Error calculating signal: {}", e);
-                    Err(Box::new(std::io::Error::new(
-                        std::io::ErrorKind::Other,
-                        format!("Error calculating signal: {}", e)
-                    )))
+                    Err(Box::new(std::io::Error::other(format!("Error calculating signal: {}", e))))
                 }
             }
         }
     ).await
 }
 
-async fn fetch_and_process<F>(
-    symbol: String,
-    query: &Query<QueryParams>,
-    process_fn: F
-) -> HttpResponse
-where F: FnOnce(DfConverter, &Query<QueryParams>) -> Result<String, Box<dyn std::error::Error>> 
-{
-    let mut df_cvt = DfConverter::new();
-    let db = DbManager::default();
-
-    let df = if db.table_exists(symbol.to_string()).unwrap_or(false){
-        df_cvt.df = Some(db.get_table(symbol.to_string()).unwrap());
-        df_cvt
-    } else {
-        let fetcher = StockFetcher::new();
-        let start_date = query.start_date.clone();
-        let end_date = query.end_date.clone();
-        fetcher.fetch_prices(symbol.to_string(), start_date, end_date)
-            .await
-            .and_then(|stock_data| {
-                df_cvt.to_df(stock_data)?;
-                db.create_table(symbol.to_string(), &mut df_cvt.df.clone().unwrap())
-                    .expect(format!("Error creating {} db table", symbol).as_str());
-                Ok(df_cvt)
-            }).expect("Error fetching stock price")
-    };
-
-    match process_fn(df, query) {
-        Ok(response) => {
-            return HttpResponse::Ok()
-                .content_type("application/json")
-                .body(response);
-        },
-        Err(e) => {
-            error!("Error calculating signal: {}", e);
-            return HttpResponse::InternalServerError()
-                .body(format!("Error calculating signal: {}", e))
-        }
-    }
-}
-
 async fn get_ma_signal(
     symbol: web::Path<String>,
     ma_type: &str,
@@ -236,8 +376,8 @@ async fn get_ma_signal(
         symbol.clone(), 
         &query, 
         |df_proc, query| {
-            let short_ma = query.short_ma.clone().unwrap_or(20);
-            let long_ma = query.long_ma.clone().unwrap_or(50);
+            let short_ma = query.short_ma.unwrap_or(20);
+            let long_ma = query.long_ma.unwrap_or(50);
             let mut crs_avg = StrategyCrossingMA::new(
                                         df_proc.df.unwrap(), 
                                         short_ma, 
@@ -252,48 +392,99 @@ async fn get_ma_signal(
                 }
                 Err(e) => {
                     error!("Error calculating signal: {}", e);
-                    Err(Box::new(std::io::Error::new(
-                        std::io::ErrorKind::Other,
-                        format!("Error calculating signal: {}", e)
-                    )))
+                    Err(Box::new(std::io::Error::other(format!("Error calculating signal: {}", e))))
                 }
             }
         }
     ).await
 }
 
-async fn get_best_performance_ma(
-    symbol: web::Path<String>, 
-    query: Query<QueryParams>,
-    ma_type: &str
-) -> HttpResponse {
-    let ma_type_owned = ma_type.to_string();
-    fetch_and_process(
-        symbol.clone(), 
-        &query,
-        |df_proc, query| {
-            let from_ma = 10;
-            let to_ma = 200;
-            let short_ma = query.short_ma.clone().unwrap_or(20);
-            let long_ma = query.long_ma.clone().unwrap_or(50);
-            let crs_avg = StrategyCrossingMA::new(
-                                    df_proc.df.unwrap(), 
-                                    short_ma, 
-                                    long_ma, 
-                                    ma_type_owned
-                            );
-            let mut scanner = ScannerCrossingMA::new(crs_avg.clone(), from_ma, to_ma);
-            match scanner.get_best_performance_df() {
-                Some(df) => {
-                    let response = DfConverter::crossingma_df_to_json(&df);
-                    Ok(response)
-                },
-                None => Err(Box::new(std::io::Error::new(
-                    std::io::ErrorKind::Other,
-                    "No crossing MA best performance found"
-                )))
-            }
+async fn fetch_and_process<Q, F>(
+    symbol: String,
+    query: &Query<Q>,
+    process_fn: F
+) -> HttpResponse
+where
+    Q: DateRange,
+    F: FnOnce(DfConverter, &Query<Q>) -> Result<String, Box<dyn std::error::Error>> 
+{
+    let mut df = DfConverter::new();
+    match load_prices(&symbol, query.start_date(), query.end_date()).await {
+        Ok(prices) => df.df = Some(prices),
+        Err(e) => {
+            error!("Error fetching stock price: {}", e);
+            return HttpResponse::InternalServerError()
+                .body(format!("Error fetching stock price: {}", e))
         }
-    ).await
+    }
+
+    match process_fn(df, query) {
+        Ok(response) => {
+            return HttpResponse::Ok()
+                .content_type("application/json")
+                .body(response);
+        },
+        Err(e) => {
+            error!("Error calculating signal: {}", e);
+            return HttpResponse::InternalServerError()
+                .body(format!("Error calculating signal: {}", e))
+        }
+    }
+}
+
+/// Comma separated `symbols`, or every symbol in the DuckDB cache when there are none
+fn universe(raw: &HashMap<String, String>) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    match raw.get("symbols") {
+        Some(symbols) => Ok(symbols.split(',').map(|s| s.trim().to_string()).filter(|s| !s.is_empty()).collect()),
+        None => Ok(DbManager::default().list_tables()?),
+    }
+}
+
+/// Prices of every symbol, the symbols that could not be loaded are returned as errors
+async fn load_universe<Q: DateRange>(
+    symbols: Vec<String>,
+    query: &Q
+) -> (Vec<(String, DataFrame)>, Vec<SymbolError>) {
+    let mut prices = Vec::new();
+    let mut missing = Vec::new();
+    for symbol in symbols {
+        match load_prices(&symbol, query.start_date(), query.end_date()).await {
+            Ok(df) => prices.push((symbol, df)),
+            Err(e) => missing.push(SymbolError { symbol, error: e.to_string() }),
+        }
+    }
+    (prices, missing)
+}
+
+/// Prices from the DuckDB cache, fetched and cached first when the symbol is not there yet
+async fn load_prices(
+    symbol: &str,
+    start_date: Option<String>,
+    end_date: Option<String>
+) -> Result<DataFrame, Box<dyn std::error::Error>> {
+    let db = DbManager::default();
+    if db.table_exists(symbol.to_string()).unwrap_or(false) {
+        return Ok(db.get_table(symbol.to_string())?);
+    }
+    let stock_data = StockFetcher::new().fetch_prices(symbol.to_string(), start_date, end_date).await?;
+    let mut df_cvt = DfConverter::new();
+    df_cvt.to_df(stock_data)?;
+    let mut df = df_cvt.df.ok_or("Error converting stock price")?;
+    db.create_table(symbol.to_string(), &mut df)?;
+    Ok(df)
+}
+
+/// Prices of the optional benchmark symbol, such as SPY, over the requested dates
+async fn load_benchmark<Q: DateRange>(
+    symbol: Option<String>,
+    query: &Q
+) -> Result<Option<(String, DataFrame)>, Box<dyn std::error::Error>> {
+    match symbol {
+        Some(symbol) => {
+            let prices = load_prices(&symbol, query.start_date(), query.end_date()).await?;
+            Ok(Some((symbol, prices)))
+        }
+        None => Ok(None),
+    }
 }
 
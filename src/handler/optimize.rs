@@ -0,0 +1,249 @@
+use std::{collections::HashMap, error::Error};
+use actix_web::{web::{self, Query}, HttpResponse};
+use chrono::Utc;
+use log::{debug, error};
+use polars::frame::DataFrame;
+
+use crate::converter::{DfConverter, OptimizationReport, ScanHistory, SensitivityReport, ValidationReport, WalkForwardReport};
+use crate::db::DbManager;
+use crate::strategy::StrategySpec;
+use crate::scanner::{
+    Backtest, BenchmarkComparison, GridSearch, Optimizer, ParamGrid, PerformanceMetrics, ScanKey, ScanRecord,
+    SearchMethod, Sensitivity, TrainTestSplit, WalkForward, DEFAULT_RADIUS, DEFAULT_TEST_BARS, DEFAULT_TRAIN_BARS,
+    SCAN_RESULTS_TABLE
+};
+use super::{
+    load_benchmark, load_prices, respond, strategy_spec, stored_records, DateRange, OrBadRequest, QueryParams,
+    ScanHistoryQuery, StrategyQuery
+};
+
+type BestToJson = fn(&DataFrame, PerformanceMetrics, Vec<BenchmarkComparison>) -> String;
+
+/// Combinations of a strategy's parameter grid ranked by the chosen metric, every one of them or a
+/// budget of them picked by a random, genetic or TPE `method`
+pub async fn get_grid_search(
+    path: web::Path<(String, String)>,
+    query: Query<StrategyQuery>
+) -> HttpResponse {
+    let (name, symbol) = path.into_inner();
+    let report = optimization_report(&name, &symbol, &query).await;
+    respond("Error running grid search", report.map(|report| DfConverter::optimization_report_to_json(&report)))
+}
+
+/// Heatmap of parameters `x` and `y` with the robustness of every combination over its neighborhood
+pub async fn get_sensitivity(
+    path: web::Path<(String, String)>,
+    query: Query<StrategyQuery>
+) -> HttpResponse {
+    let (name, symbol) = path.into_inner();
+    let report = sensitivity_report(&name, &symbol, &query).await;
+    respond("Error running sensitivity", report.map(|report| DfConverter::sensitivity_report_to_json(&report)))
+}
+
+pub async fn get_best_performance_sma(
+    symbol: web::Path<String>,
+    query: Query<QueryParams>,
+    raw: Query<HashMap<String, String>>
+) -> HttpResponse {
+    respond("Error calculating signal", best_performance_json(&symbol, "sma", &query, &raw, DfConverter::crossingma_best_to_json).await)
+}
+
+pub async fn get_best_performance_ewma(
+    symbol: web::Path<String>,
+    query: Query<QueryParams>,
+    raw: Query<HashMap<String, String>>
+) -> HttpResponse {
+    respond("Error calculating signal", best_performance_json(&symbol, "ewma", &query, &raw, DfConverter::crossingma_best_to_json).await)
+}
+
+pub async fn get_best_performance_rsi(
+    symbol: web::Path<String>,
+    query: Query<QueryParams>,
+    raw: Query<HashMap<String, String>>
+) -> HttpResponse {
+    respond("Error calculating signal", best_performance_json(&symbol, "rsi", &query, &raw, DfConverter::rsi_best_to_json).await)
+}
+
+pub async fn get_best_performance_bb(
+    symbol: web::Path<String>,
+    query: Query<QueryParams>,
+    raw: Query<HashMap<String, String>>
+) -> HttpResponse {
+    respond("Error calculating signal", best_performance_json(&symbol, "bb", &query, &raw, DfConverter::bb_best_to_json).await)
+}
+
+/// Stored bestperf scans, newest first
+pub async fn get_scan_history(query: Query<ScanHistoryQuery>) -> HttpResponse {
+    let records = stored_records(SCAN_RESULTS_TABLE, ScanRecord::from_df).map(|records| {
+        records.into_iter()
+            .rev()
+            .filter(|r| query.strategy.as_ref().is_none_or(|strategy| &r.key.strategy == strategy)
+                && query.symbol.as_ref().is_none_or(|symbol| &r.key.symbol == symbol))
+            .take(query.limit.unwrap_or(usize::MAX))
+            .collect()
+    });
+    respond("Error reading scan history", records.map(|records| DfConverter::scan_history_to_json(&ScanHistory { records })))
+}
+
+pub(super) async fn optimization_report(
+    name: &str,
+    symbol: &str,
+    query: &StrategyQuery
+) -> Result<OptimizationReport, Box<dyn Error>> {
+    let spec = strategy_spec(name)?;
+    let optimizer = Optimizer::from_query(&query.params).or_bad_request("Invalid optimizer settings")?;
+    // Only the grid backtests every combination, the other methods are bounded by their budget
+    let grid = match optimizer.method {
+        SearchMethod::Grid => ParamGrid::from_query(spec, &query.params),
+        _ => ParamGrid::space_from_query(spec, &query.params),
+    };
+    let grid = grid.or_bad_request("Invalid parameter grid")?;
+    let backtest = Backtest::from_query(&query.params).or_bad_request("Invalid backtest settings")?;
+    let prices = load_prices(symbol, query.start_date(), query.end_date()).await?;
+    let search = GridSearch::new(grid, backtest);
+    let run = optimizer.run(&search, &prices, spec)?;
+    Ok(OptimizationReport {
+        combinations: search.grid.size(),
+        metric: search.backtest.metric,
+        grid: search.grid,
+        run,
+    })
+}
+
+pub(super) async fn sensitivity_report(
+    name: &str,
+    symbol: &str,
+    query: &StrategyQuery
+) -> Result<SensitivityReport, Box<dyn Error>> {
+    let spec = strategy_spec(name)?;
+    let grid = ParamGrid::from_query(spec, &query.params).or_bad_request("Invalid parameter grid")?;
+    let sensitivity = Sensitivity::from_query(grid.clone(), &query.params).or_bad_request("Invalid sensitivity settings")?;
+    let backtest = Backtest::from_query(&query.params).or_bad_request("Invalid backtest settings")?;
+    let prices = load_prices(symbol, query.start_date(), query.end_date()).await?;
+    let search = GridSearch::new(grid, backtest);
+    let results = search.run(&prices, spec)?;
+    let heatmap = sensitivity.heatmap(
+        &results,
+        query.params.get("x").map(String::as_str),
+        query.params.get("y").map(String::as_str)
+    )?;
+    let robustness = sensitivity.robustness(&results);
+    let peak = robustness.iter()
+        .find(|result| result.params == results[0].params)
+        .cloned()
+        .ok_or("The best combination has no robustness")?;
+    Ok(SensitivityReport {
+        grid: search.grid,
+        metric: search.backtest.metric,
+        radius: sensitivity.radius,
+        heatmap,
+        peak,
+        stable: robustness[0].clone(),
+        results: robustness,
+    })
+}
+
+/// Best performance over the strategy's grid, narrowed by `{param}_from`, `{param}_to` and
+/// `{param}_step` like `/optimize`
+async fn best_performance_json(
+    symbol: &str,
+    spec_name: &str,
+    query: &QueryParams,
+    raw: &HashMap<String, String>,
+    to_json: BestToJson
+) -> Result<String, Box<dyn Error>> {
+    let spec = strategy_spec(spec_name)?;
+    let grid = ParamGrid::from_query(spec, raw).or_bad_request("Invalid parameter grid")?;
+    let benchmark = load_benchmark(query.benchmark.clone(), query).await.or_bad_request("Error loading benchmark")?;
+    let prices = load_prices(symbol, query.start_date(), query.end_date()).await?;
+    if query.out_of_sample() {
+        return out_of_sample_json(prices, spec, grid, query, benchmark.as_ref());
+    }
+    let (df, sig_col) = best_signal(prices, symbol, spec, grid, query)?;
+    let (metrics, benchmarks) = best_performance(&df, &sig_col, benchmark.as_ref())?;
+    Ok(to_json(&df, metrics, benchmarks))
+}
+
+/// Backtests the best signal of a scanner with the default settings and compares it to buy and hold
+fn best_performance(
+    df: &DataFrame,
+    sig_col: &str,
+    benchmark: Option<&(String, DataFrame)>
+) -> Result<(PerformanceMetrics, Vec<BenchmarkComparison>), Box<dyn Error>> {
+    let run = Backtest::new().run(df, sig_col)?;
+    Ok((PerformanceMetrics::from_run(&run)?, BenchmarkComparison::all(&run, df, benchmark)?))
+}
+
+/// Signals of the best scored, or with `select=stable` the most robust, parameters and the name of
+/// their signal column. The parameters are stored and reused over the same prices unless `refresh=true`.
+fn best_signal(
+    df: DataFrame,
+    symbol: &str,
+    spec: &StrategySpec,
+    grid: ParamGrid,
+    query: &QueryParams
+) -> Result<(DataFrame, String), Box<dyn Error>> {
+    let (metric, selection, radius) = (query.metric.unwrap_or_default(), query.select.unwrap_or_default(), query.radius.unwrap_or(DEFAULT_RADIUS));
+    let key = ScanKey::new(&spec.name, symbol, &df, metric, selection, radius, &grid)?;
+    let stored = match query.refresh.unwrap_or(false) {
+        true => None,
+        false => stored_records(SCAN_RESULTS_TABLE, ScanRecord::from_df)
+            .map(|records| ScanRecord::find(records, &key))
+            .unwrap_or_else(|e| {
+                error!("Error reading stored scans: {}", e);
+                None
+            }),
+    };
+    let params = match stored {
+        Some(record) => {
+            debug!("Reusing the {} scan of {} from {}", spec.name, symbol, record.run_at);
+            record.params
+        }
+        None => {
+            let search = GridSearch::new(grid, Backtest::new().with_metric(metric));
+            let results = search.run(&df, spec)?;
+            let selected = Sensitivity::new(search.grid, radius).select(&results, selection)?;
+            let record = ScanRecord::new(Utc::now().format("%Y-%m-%d %H:%M:%S").to_string(), key, &selected)?;
+            let stored = record.to_df().and_then(|mut df| Ok(DbManager::results().append_table(SCAN_RESULTS_TABLE.to_string(), &mut df)?));
+            if let Err(e) = stored {
+                error!("Error storing the {} scan of {}: {}", spec.name, symbol, e);
+            }
+            record.params
+        }
+    };
+    let mut strategy = spec.build(df, &params)?;
+    Ok((strategy.calc_signal()?, strategy.signal_col()))
+}
+
+/// Walk-forward optimization or a train/test split, searching the strategy's grid on the training bars
+fn out_of_sample_json(
+    df: DataFrame,
+    spec: &StrategySpec,
+    grid: ParamGrid,
+    query: &QueryParams,
+    benchmark: Option<&(String, DataFrame)>
+) -> Result<String, Box<dyn Error>> {
+    let backtest = Backtest::new().with_metric(query.metric.unwrap_or_default());
+    match (query.walk_forward, TrainTestSplit::from_params(query.test_ratio, query.split_date.clone())?) {
+        (Some(_), Some(_)) => Err("Use either walk_forward or a train/test split, not both".into()),
+        (Some(mode), None) => {
+            let walk_forward = WalkForward::new(
+                mode,
+                query.train_bars.unwrap_or(DEFAULT_TRAIN_BARS),
+                query.test_bars.unwrap_or(DEFAULT_TEST_BARS)
+            )?;
+            let wf_run = walk_forward.run(&df, spec, &backtest, &grid)?;
+            let metrics = PerformanceMetrics::from_run(&wf_run.run)?;
+            let benchmarks = BenchmarkComparison::all(&wf_run.run, &df, benchmark)?;
+            let report = WalkForwardReport::new(walk_forward, wf_run, metrics, benchmarks)?;
+            Ok(DfConverter::walk_forward_report_to_json(&report))
+        }
+        (None, Some(split)) => {
+            let split_run = split.run(&df, spec, &backtest, &grid)?;
+            let benchmarks = BenchmarkComparison::all(&split_run.run, &df, benchmark)?;
+            let report = ValidationReport::new(split, split_run, benchmarks)?;
+            Ok(DfConverter::validation_report_to_json(&report))
+        }
+        (None, None) => Err("Neither walk_forward nor a train/test split was requested".into()),
+    }
+}
@@ -0,0 +1,123 @@
+use std::error::Error;
+use actix_web::{web::{self, Query}, HttpResponse};
+use chrono::Utc;
+
+use crate::converter::{DfConverter, LeaderboardHistory, LeaderboardReport, PortfolioReport, ScreenerReport};
+use crate::db::DbManager;
+use crate::scanner::{
+    Backtest, Leaderboard, LeaderboardRecord, ParamGrid, PerformanceMetrics, Portfolio, Screener, TrainTestSplit,
+    DEFAULT_TEST_RATIO, LEADERBOARD_TABLE
+};
+use super::strategy_config::{PortfolioConfig, StrategyConfig};
+use super::{
+    load_prices, load_universe, respond, strategy_spec, stored_records, universe, DateRange, OrBadRequest,
+    RequestError, StrategyQuery
+};
+
+pub async fn get_portfolio(query: Query<StrategyQuery>) -> HttpResponse {
+    let report = portfolio_report(&query).await;
+    respond("Error running portfolio", report.map(|report| DfConverter::portfolio_report_to_json(&report)))
+}
+
+/// Latest-bar triggers of a strategy on a comma separated `symbols` watchlist, or on every cached
+/// symbol when there is none
+pub async fn get_screener(query: Query<StrategyQuery>) -> HttpResponse {
+    let report = screener_report(&query).await;
+    respond("Error running screener", report.map(|report| DfConverter::screener_report_to_json(&report)))
+}
+
+/// Leaderboard of a strategy on a comma separated `symbols` universe, or on every cached symbol,
+/// ranked by out-of-sample score. Every run is stored so it can be compared with earlier days.
+pub async fn get_leaderboard(
+    path: web::Path<String>,
+    query: Query<StrategyQuery>
+) -> HttpResponse {
+    let report = leaderboard_report(&path, &query).await;
+    respond("Error running leaderboard", report.map(|report| DfConverter::leaderboard_report_to_json(&report)))
+}
+
+/// Stored leaderboard rows of a strategy, optionally of one `symbol`, `metric` or `date`
+pub async fn get_leaderboard_history(
+    path: web::Path<String>,
+    query: Query<StrategyQuery>
+) -> HttpResponse {
+    let strategy = path.into_inner();
+    let records = stored_records(LEADERBOARD_TABLE, LeaderboardRecord::from_df).map(|records| {
+        records.into_iter()
+            .filter(|r| r.strategy == strategy
+                && query.params.get("symbol").is_none_or(|symbol| &r.symbol == symbol)
+                && query.params.get("metric").is_none_or(|metric| &r.metric == metric)
+                && query.params.get("date").is_none_or(|date| r.run_at.starts_with(date.as_str())))
+            .collect()
+    });
+    respond(
+        "Error reading leaderboard history",
+        records.map(|records| DfConverter::leaderboard_history_to_json(&LeaderboardHistory { strategy, records }))
+    )
+}
+
+async fn portfolio_report(query: &StrategyQuery) -> Result<PortfolioReport, Box<dyn Error>> {
+    let config = PortfolioConfig::from_query(&query.params)?;
+    let portfolio = Portfolio::from_query(&query.params).or_bad_request("Invalid portfolio settings")?;
+    let mut prices = Vec::new();
+    for (symbol, _) in &config.legs {
+        let df = load_prices(symbol, query.start_date(), query.end_date()).await
+            .map_err(|e| format!("Error fetching {} stock price: {}", symbol, e))?;
+        prices.push(df);
+    }
+    let legs = config.build_legs(prices)?;
+    let portfolio_run = portfolio.run(&legs)?;
+    let metrics = PerformanceMetrics::from_run(&portfolio_run.run)?;
+    PortfolioReport::new(portfolio, portfolio_run, metrics)
+}
+
+async fn screener_report(query: &StrategyQuery) -> Result<ScreenerReport, Box<dyn Error>> {
+    let name = query.params.get("strategy")
+        .ok_or_else(|| RequestError::BadRequest("Invalid screener settings: strategy is required".to_string()))?;
+    let config = StrategyConfig::from_query(name, &query.params)?;
+    let screener = Screener::from_query(&query.params).or_bad_request("Invalid screener settings")?;
+    let symbols = universe(&query.params).map_err(|e| format!("Error listing cached symbols: {}", e))?;
+    let (prices, missing) = load_universe(symbols, query).await;
+    let mut run = screener.run(prices, |df| config.build(df))?;
+    run.screened += missing.len();
+    run.errors.extend(missing);
+    Ok(ScreenerReport {
+        strategy: config.spec.name.clone(),
+        params: config.params,
+        screener,
+        run,
+    })
+}
+
+/// Runs the leaderboard, compares it with the last stored run of an earlier day and stores it
+pub(super) async fn leaderboard_report(name: &str, query: &StrategyQuery) -> Result<LeaderboardReport, Box<dyn Error>> {
+    let spec = strategy_spec(name)?;
+    let grid = ParamGrid::from_query(spec, &query.params).or_bad_request("Invalid parameter grid")?;
+    let backtest = Backtest::from_query(&query.params).or_bad_request("Invalid backtest settings")?;
+    let split = query.params.get("test_ratio")
+        .map(|ratio| ratio.parse::<f32>().map_err(|e| format!("Invalid test_ratio: {}", e).into()))
+        .transpose()
+        .and_then(|ratio| TrainTestSplit::from_params(ratio, query.params.get("split_date").cloned()))
+        .or_bad_request("Invalid train/test split")?;
+    let leaderboard = Leaderboard::new(split.unwrap_or(TrainTestSplit::TestRatio(DEFAULT_TEST_RATIO)), backtest);
+    let symbols = universe(&query.params).map_err(|e| format!("Error listing cached symbols: {}", e))?;
+    let (prices, missing) = load_universe(symbols, query).await;
+
+    let mut run = leaderboard.run(prices, spec, &grid)?;
+    run.errors.extend(missing);
+    let run_at = Utc::now().format("%Y-%m-%d %H:%M:%S").to_string();
+    let metric = serde_json::to_value(leaderboard.backtest.metric)?.as_str().unwrap_or_default().to_string();
+    let history = stored_records(LEADERBOARD_TABLE, LeaderboardRecord::from_df)?;
+    run.compare_with(&history, &run_at, &spec.name, &metric);
+    if !run.entries.is_empty() {
+        DbManager::results().append_table(LEADERBOARD_TABLE.to_string(), &mut run.to_df(&run_at, &spec.name, &metric)?)?;
+    }
+    Ok(LeaderboardReport {
+        strategy: spec.name.clone(),
+        metric: leaderboard.backtest.metric,
+        run_at,
+        leaderboard,
+        grid,
+        run,
+    })
+}
@@ -0,0 +1,170 @@
+use std::collections::HashMap;
+use polars::frame::DataFrame;
+use rayon::prelude::*;
+
+use crate::strategy::{
+    Regime, RegimeClassifier, RiskRules, Strategy, StrategyParams, StrategyRegimeFilter,
+    StrategyRegistry, StrategyRiskOverlay, StrategySpec
+};
+use crate::scanner::{Execution, PortfolioLeg, PositionMode};
+use crate::transform::{BarType, StrategyOnBars};
+
+/// Everything a generic strategy request can ask for, parsed once from the query string
+pub struct StrategyConfig {
+    pub spec: &'static StrategySpec,
+    pub params: StrategyParams,
+    pub bars: BarType,
+    pub regimes: Vec<Regime>,
+    pub risk_rules: RiskRules,
+    pub mode: PositionMode,
+    pub execution: Execution,
+}
+
+impl StrategyConfig {
+    pub fn from_query(name: &str, raw: &HashMap<String, String>) -> Result<Self, StrategyConfigError> {
+        let spec = StrategyRegistry::global()
+            .get(name)
+            .ok_or_else(|| StrategyConfigError::UnknownStrategy(name.to_string()))?;
+        let invalid = |e: Box<dyn std::error::Error>| StrategyConfigError::InvalidParams(e.to_string());
+        let mode = match raw.get("position_mode") {
+            Some(mode) => PositionMode::parse(mode).map_err(invalid)?,
+            None => PositionMode::default(),
+        };
+        let execution = match raw.get("execution") {
+            Some(execution) => Execution::parse(execution).map_err(invalid)?,
+            None => Execution::default(),
+        };
+        let regimes = StrategyRegimeFilter::allowed_from_query(raw).map_err(invalid)?;
+        if !regimes.is_empty() && !StrategyRegimeFilter::supports(mode) {
+            return Err(invalid("regime needs position_mode long_only or short_only".into()));
+        }
+        Ok(StrategyConfig {
+            spec,
+            params: spec.parse_params(raw).map_err(invalid)?,
+            bars: BarType::from_query(raw).map_err(invalid)?,
+            regimes,
+            risk_rules: RiskRules::from_query(raw).map_err(invalid)?,
+            mode,
+            execution,
+        })
+    }
+
+    /// Strategy on the requested bars, then filtered by regime, then wrapped by the risk overlay
+    pub fn build(&self, df: DataFrame) -> Result<Box<dyn Strategy + Send>, Box<dyn std::error::Error>> {
+        let mut strategy = match self.bars {
+            BarType::Candles => self.spec.build(df, &self.params)?,
+            _ => {
+                let inner = self.spec.build(self.bars.transform(&df)?, &self.params)?;
+                Box::new(StrategyOnBars::new(inner, df))
+            }
+        };
+        if !self.regimes.is_empty() {
+            strategy = Box::new(
+                StrategyRegimeFilter::new(strategy, RegimeClassifier::default(), self.regimes.clone()).with_mode(self.mode)
+            );
+        }
+        if !self.risk_rules.is_empty() {
+            strategy = Box::new(
+                StrategyRiskOverlay::new(strategy, self.risk_rules.clone())
+                    .with_mode(self.mode)
+                    .with_execution(self.execution)
+            );
+        }
+        Ok(strategy)
+    }
+}
+
+/// Symbols of a portfolio request and the strategies run on each of them
+pub struct PortfolioConfig {
+    pub legs: Vec<(String, Vec<String>)>,
+    pub strategies: HashMap<String, StrategyConfig>,
+}
+
+impl PortfolioConfig {
+    /// Reads comma separated `symbols`, the comma separated `strategy` list run on every symbol and
+    /// `symbol_strategies=MSFT:rsi+bb,AAPL:sma` to give some symbols their own strategies.
+    /// Every strategy shares the parameters of the query.
+    pub fn from_query(raw: &HashMap<String, String>) -> Result<Self, StrategyConfigError> {
+        let invalid = |e: &str| StrategyConfigError::InvalidParams(e.to_string());
+        let list = |value: &str, separator: char| -> Vec<String> {
+            value.split(separator).map(|s| s.trim().to_string()).filter(|s| !s.is_empty()).collect()
+        };
+        let symbols = list(raw.get("symbols").ok_or_else(|| invalid("symbols is required"))?, ',');
+        let default_strategies = raw.get("strategy").map(|s| list(s, ',')).unwrap_or_default();
+        let mut overrides = HashMap::new();
+        if let Some(symbol_strategies) = raw.get("symbol_strategies") {
+            for entry in list(symbol_strategies, ',') {
+                let (symbol, names) = entry.split_once(':')
+                    .ok_or_else(|| invalid("symbol_strategies entries look like SYMBOL:strategy+strategy"))?;
+                overrides.insert(symbol.trim().to_string(), list(names, '+'));
+            }
+        }
+        if symbols.is_empty() {
+            return Err(invalid("symbols is empty"));
+        }
+
+        let mut legs = Vec::new();
+        let mut strategies = HashMap::new();
+        for symbol in symbols {
+            let names = overrides.remove(&symbol).unwrap_or_else(|| default_strategies.clone());
+            if names.is_empty() {
+                return Err(StrategyConfigError::InvalidParams(format!("No strategy for {}", symbol)));
+            }
+            for name in &names {
+                if !strategies.contains_key(name) {
+                    strategies.insert(name.clone(), StrategyConfig::from_query(name, raw)?);
+                }
+            }
+            legs.push((symbol, names));
+        }
+        if let Some(symbol) = overrides.keys().next() {
+            return Err(StrategyConfigError::InvalidParams(format!("{} is not in symbols", symbol)));
+        }
+        Ok(PortfolioConfig { legs, strategies })
+    }
+
+    /// Signals of every strategy of every symbol, computed in parallel, `prices` are in the order of the legs
+    pub fn build_legs(&self, prices: Vec<DataFrame>) -> Result<Vec<PortfolioLeg>, Box<dyn std::error::Error>> {
+        let legs: Result<Vec<PortfolioLeg>, String> = self.legs
+            .par_iter()
+            .zip(prices)
+            .map(|((symbol, names), df)| {
+                let signals = names.iter()
+                    .map(|name| {
+                        let mut strategy = self.strategies[name].build(df.clone()).map_err(|e| e.to_string())?;
+                        let signals = strategy.calc_signal().map_err(|e| format!("{} on {}: {}", name, symbol, e))?;
+                        Ok((signals, strategy.signal_col()))
+                    })
+                    .collect::<Result<_, String>>()?;
+                Ok(PortfolioLeg { symbol: symbol.clone(), signals })
+            })
+            .collect();
+        Ok(legs?)
+    }
+}
+
+#[derive(Debug)]
+pub enum StrategyConfigError {
+    UnknownStrategy(String),
+    InvalidParams(String),
+}
+
+impl std::fmt::Display for StrategyConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StrategyConfigError::UnknownStrategy(name) => write!(f, "Unknown strategy '{}'", name),
+            StrategyConfigError::InvalidParams(e) => write!(f, "Invalid parameters: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for StrategyConfigError {}
+
+impl StrategyConfigError {
+    pub fn to_response(&self) -> actix_web::HttpResponse {
+        match self {
+            StrategyConfigError::UnknownStrategy(_) => actix_web::HttpResponse::NotFound().body(self.to_string()),
+            StrategyConfigError::InvalidParams(_) => actix_web::HttpResponse::BadRequest().body(self.to_string()),
+        }
+    }
+}
@@ -0,0 +1,525 @@
+use std::{collections::HashMap, env, future::Future, pin::Pin, thread};
+use std::sync::{mpsc::{self, Receiver, SyncSender, TrySendError}, Arc, Mutex, OnceLock};
+use actix_web::HttpResponse;
+use chrono::Utc;
+use indexmap::IndexMap;
+use log::{debug, error};
+use polars::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::db::DbManager;
+use crate::scanner::{Progress, ProgressSnapshot};
+
+pub const JOBS_TABLE: &str = "jobs";
+const DEFAULT_WORKERS: usize = 2;
+/// Jobs waiting for a worker, submissions beyond it are refused
+const MAX_QUEUED: usize = 100;
+const INTERRUPTED: &str = "Interrupted by a restart";
+
+/// Endpoint a job runs, with the same query parameters
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum JobKind {
+    Backtest,
+    Optimize,
+    Sensitivity,
+    Leaderboard,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct JobRequest {
+    pub kind: JobKind,
+    pub strategy: String,
+    /// Required by every kind but the leaderboard, which reads `symbols` from its params
+    #[serde(default)]
+    pub symbol: Option<String>,
+    /// Query parameters of the kind's endpoint
+    #[serde(default)]
+    pub params: HashMap<String, String>,
+}
+
+impl JobRequest {
+    fn validate(&self) -> Result<(), JobError> {
+        if self.kind != JobKind::Leaderboard && self.symbol.is_none() {
+            return Err(JobError::InvalidRequest("symbol is required".to_string()));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Succeeded,
+    Failed,
+    Cancelled,
+}
+
+impl JobStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            JobStatus::Queued => "queued",
+            JobStatus::Running => "running",
+            JobStatus::Succeeded => "succeeded",
+            JobStatus::Failed => "failed",
+            JobStatus::Cancelled => "cancelled",
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        matches!(self, JobStatus::Succeeded | JobStatus::Failed | JobStatus::Cancelled)
+    }
+}
+
+/// A submitted job, its result is served on its own
+#[derive(Serialize, Debug, Clone)]
+pub struct Job {
+    pub id: String,
+    pub request: JobRequest,
+    pub status: JobStatus,
+    pub submitted_at: String,
+    pub started_at: Option<String>,
+    pub finished_at: Option<String>,
+    pub error: Option<String>,
+    pub progress: ProgressSnapshot,
+    #[serde(skip)]
+    pub result: Option<String>,
+    /// Counts the backtests of the running job and carries its cancellation
+    #[serde(skip)]
+    handle: Arc<Progress>,
+    /// Counts the changes of the job so an older copy is never stored over a newer one
+    #[serde(skip)]
+    revision: u64,
+}
+
+impl Job {
+    fn new(request: JobRequest) -> Self {
+        Job {
+            id: format!("{:016x}", rand::random::<u64>()),
+            request,
+            status: JobStatus::Queued,
+            submitted_at: now(),
+            started_at: None,
+            finished_at: None,
+            error: None,
+            progress: ProgressSnapshot::default(),
+            result: None,
+            handle: Arc::new(Progress::default()),
+            revision: 0,
+        }
+    }
+
+    /// The job with the progress of its backtests so far
+    fn view(&self) -> Job {
+        let mut job = self.clone();
+        if !job.status.is_finished() {
+            job.progress = self.handle.snapshot();
+        }
+        job
+    }
+
+    fn finish(&mut self, status: JobStatus) {
+        self.status = status;
+        self.finished_at = Some(now());
+        self.progress = self.handle.snapshot();
+    }
+
+    /// Fails a job that was queued or running when the server stopped, false when it had finished
+    fn interrupt(&mut self) -> bool {
+        if self.status.is_finished() {
+            return false;
+        }
+        self.status = JobStatus::Failed;
+        self.finished_at = Some(now());
+        self.error = Some(INTERRUPTED.to_string());
+        true
+    }
+
+    /// Copy of the job to store after a change, taken while the jobs are locked
+    fn revise(&mut self) -> Job {
+        self.revision += 1;
+        self.clone()
+    }
+
+    /// Row of the job in the jobs table, the request is stored as JSON
+    fn to_df(&self) -> Result<DataFrame, Box<dyn std::error::Error>> {
+        let df = df!(
+            "id" => [self.id.as_str()],
+            "request" => [serde_json::to_string(&self.request)?],
+            "status" => [self.status.as_str()],
+            "submitted_at" => [self.submitted_at.as_str()],
+            "started_at" => [self.started_at.as_deref()],
+            "finished_at" => [self.finished_at.as_deref()],
+            "error" => [self.error.as_deref()],
+            "result" => [self.result.as_deref()],
+            "done" => [self.progress.done as i32],
+            "total" => [self.progress.total as i32],
+        )?;
+        Ok(df)
+    }
+
+    /// Jobs of the jobs table, in the order they were submitted
+    fn from_df(df: &DataFrame) -> Result<Vec<Self>, Box<dyn std::error::Error>> {
+        let text = |name: &str| -> Result<Vec<Option<String>>, Box<dyn std::error::Error>> {
+            Ok(df.column(name)?.str()?.into_iter().map(|v| v.map(str::to_string)).collect())
+        };
+        let number = |name: &str| -> Result<Vec<usize>, Box<dyn std::error::Error>> {
+            Ok(df.column(name)?.cast(&DataType::Int32)?.i32()?.into_iter().map(|v| v.unwrap_or_default() as usize).collect())
+        };
+        let (id, request, status, submitted_at) = (text("id")?, text("request")?, text("status")?, text("submitted_at")?);
+        let (started_at, finished_at, error, result) = (text("started_at")?, text("finished_at")?, text("error")?, text("result")?);
+        let (done, total) = (number("done")?, number("total")?);
+        let mut jobs = (0..df.height())
+            .map(|i| {
+                let status: JobStatus = serde_json::from_value(serde_json::Value::String(status[i].clone().unwrap_or_default()))?;
+                Ok(Job {
+                    id: id[i].clone().unwrap_or_default(),
+                    request: serde_json::from_str(request[i].as_deref().unwrap_or_default())?,
+                    status,
+                    submitted_at: submitted_at[i].clone().unwrap_or_default(),
+                    started_at: started_at[i].clone(),
+                    finished_at: finished_at[i].clone(),
+                    error: error[i].clone(),
+                    progress: ProgressSnapshot { done: done[i], total: total[i], cancelled: status == JobStatus::Cancelled },
+                    result: result[i].clone(),
+                    handle: Arc::new(Progress::default()),
+                    revision: 0,
+                })
+            })
+            .collect::<Result<Vec<_>, Box<dyn std::error::Error>>>()?;
+        jobs.sort_by(|a, b| a.submitted_at.cmp(&b.submitted_at));
+        Ok(jobs)
+    }
+}
+
+#[derive(Debug)]
+pub enum JobError {
+    NotFound(String),
+    InvalidRequest(String),
+    QueueFull,
+    /// The job already finished, or did not finish yet when its result is asked for
+    Conflict(String),
+}
+
+impl std::fmt::Display for JobError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JobError::NotFound(id) => write!(f, "Unknown job '{}'", id),
+            JobError::InvalidRequest(e) => write!(f, "Invalid job: {}", e),
+            JobError::QueueFull => write!(f, "{} jobs are already waiting, try again later", MAX_QUEUED),
+            JobError::Conflict(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl JobError {
+    pub fn to_response(&self) -> HttpResponse {
+        match self {
+            JobError::NotFound(_) => HttpResponse::NotFound().body(self.to_string()),
+            JobError::InvalidRequest(_) => HttpResponse::BadRequest().body(self.to_string()),
+            JobError::QueueFull => HttpResponse::ServiceUnavailable().body(self.to_string()),
+            JobError::Conflict(_) => HttpResponse::Conflict().body(self.to_string()),
+        }
+    }
+}
+
+/// JSON of the report of a job
+pub type JobFuture = Pin<Box<dyn Future<Output = Result<String, Box<dyn std::error::Error>>>>>;
+
+pub type JobRunner = fn(JobRequest) -> JobFuture;
+
+static QUEUE: OnceLock<JobQueue> = OnceLock::new();
+
+/// Jobs run one at a time by each of a fixed number of worker threads, away from the server's
+/// threads. Every submission and outcome is stored in the results database.
+pub struct JobQueue {
+    jobs: Mutex<IndexMap<String, Job>>,
+    /// Revision of each job last written to the database
+    stored: Mutex<HashMap<String, u64>>,
+    sender: SyncSender<String>,
+    db: DbManager,
+}
+
+impl JobQueue {
+    /// Restores the stored jobs and starts `JOB_WORKERS` workers, 2 when it is not set. Jobs that
+    /// were queued or running when the server stopped are marked as failed.
+    pub fn start(runner: JobRunner) -> &'static JobQueue {
+        let workers = env::var("JOB_WORKERS").ok()
+            .and_then(|workers| workers.parse().ok())
+            .unwrap_or(DEFAULT_WORKERS)
+            .max(1);
+        let (sender, receiver) = mpsc::sync_channel(MAX_QUEUED);
+        let queue = QUEUE.get_or_init(|| JobQueue::open(DbManager::results(), sender));
+        let receiver = Arc::new(Mutex::new(receiver));
+        for i in 0..workers {
+            let receiver = receiver.clone();
+            let spawned = thread::Builder::new()
+                .name(format!("job-worker-{}", i))
+                .spawn(move || queue.work(receiver, runner));
+            if let Err(e) = spawned {
+                error!("Error starting job worker {}: {}", i, e);
+            }
+        }
+        debug!("Started {} job workers", workers);
+        queue
+    }
+
+    /// Queue of the jobs stored in `db`, submissions are sent to the workers through `sender`
+    fn open(db: DbManager, sender: SyncSender<String>) -> JobQueue {
+        let jobs = restore(&db).unwrap_or_else(|e| {
+            error!("Error restoring jobs: {}", e);
+            IndexMap::new()
+        });
+        JobQueue { jobs: Mutex::new(jobs), stored: Mutex::new(HashMap::new()), sender, db }
+    }
+
+    pub fn global() -> Option<&'static JobQueue> {
+        QUEUE.get()
+    }
+
+    pub fn submit(&self, request: JobRequest) -> Result<Job, JobError> {
+        request.validate()?;
+        let job = Job::new(request);
+        let mut jobs = self.jobs.lock().unwrap();
+        match self.sender.try_send(job.id.clone()) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => return Err(JobError::QueueFull),
+            Err(TrySendError::Disconnected(_)) => return Err(JobError::Conflict("The job workers stopped".to_string())),
+        }
+        jobs.insert(job.id.clone(), job.clone());
+        drop(jobs);
+        self.store(&job);
+        Ok(job)
+    }
+
+    pub fn get(&self, id: &str) -> Result<Job, JobError> {
+        self.jobs.lock().unwrap()
+            .get(id)
+            .map(Job::view)
+            .ok_or_else(|| JobError::NotFound(id.to_string()))
+    }
+
+    /// Every job, newest first
+    pub fn list(&self) -> Vec<Job> {
+        self.jobs.lock().unwrap().values().rev().map(Job::view).collect()
+    }
+
+    /// Body of a succeeded job's response
+    pub fn result(&self, id: &str) -> Result<String, JobError> {
+        let job = self.get(id)?;
+        match (job.status, job.result) {
+            (JobStatus::Succeeded, Some(result)) => Ok(result),
+            (status, _) => Err(JobError::Conflict(format!("Job {} is {} and has no result", id, status.as_str()))),
+        }
+    }
+
+    /// A queued job is cancelled at once, a running scan stops at its next backtest. Only scans
+    /// check for cancellation, a running backtest job runs to its end and its report is dropped.
+    pub fn cancel(&self, id: &str) -> Result<Job, JobError> {
+        let mut jobs = self.jobs.lock().unwrap();
+        let job = jobs.get_mut(id).ok_or_else(|| JobError::NotFound(id.to_string()))?;
+        job.handle.cancel();
+        let cancelled = match job.status {
+            JobStatus::Queued => {
+                job.finish(JobStatus::Cancelled);
+                Some(job.revise())
+            }
+            JobStatus::Running => None,
+            _ => return Err(JobError::Conflict(format!("Job {} already finished", id))),
+        };
+        let view = job.view();
+        drop(jobs);
+        if let Some(job) = cancelled {
+            self.store(&job);
+        }
+        Ok(view)
+    }
+
+    /// Takes jobs off the channel until the queue is dropped, the progress of a job is the current
+    /// progress of the worker while it runs so its scans report to it
+    fn work(&self, receiver: Arc<Mutex<Receiver<String>>>, runner: JobRunner) {
+        let runtime = match tokio::runtime::Builder::new_current_thread().enable_all().build() {
+            Ok(runtime) => runtime,
+            Err(e) => return error!("Error starting a job worker runtime: {}", e),
+        };
+        loop {
+            let Ok(id) = receiver.lock().unwrap().recv() else {
+                return;
+            };
+            let Some((request, progress)) = self.begin(&id) else {
+                continue;
+            };
+            debug!("Running job {}", id);
+            let outcome = {
+                let _guard = progress.enter();
+                runtime.block_on(runner(request))
+            };
+            self.end(&id, outcome);
+        }
+    }
+
+    /// Marks a queued job as running, None when it was cancelled while it waited
+    fn begin(&self, id: &str) -> Option<(JobRequest, Arc<Progress>)> {
+        let mut jobs = self.jobs.lock().unwrap();
+        let job = jobs.get_mut(id).filter(|job| job.status == JobStatus::Queued)?;
+        job.status = JobStatus::Running;
+        job.started_at = Some(now());
+        let job = job.revise();
+        drop(jobs);
+        self.store(&job);
+        Some((job.request, job.handle))
+    }
+
+    fn end(&self, id: &str, outcome: Result<String, Box<dyn std::error::Error>>) {
+        let mut jobs = self.jobs.lock().unwrap();
+        let Some(job) = jobs.get_mut(id) else {
+            return;
+        };
+        match outcome {
+            _ if job.handle.is_cancelled() => job.finish(JobStatus::Cancelled),
+            Ok(result) => {
+                job.handle.complete();
+                job.result = Some(result);
+                job.finish(JobStatus::Succeeded);
+            }
+            Err(e) => {
+                job.error = Some(e.to_string());
+                job.finish(JobStatus::Failed);
+            }
+        }
+        debug!("Job {} {:?}", id, job.status);
+        let job = job.revise();
+        drop(jobs);
+        self.store(&job);
+    }
+
+    /// Replaces the stored row of a job unless a later revision of it was stored already, so the
+    /// jobs stay readable while the database is written. Failing to store a job leaves it in memory only.
+    fn store(&self, job: &Job) {
+        let mut stored = self.stored.lock().unwrap();
+        if stored.get(&job.id).is_some_and(|revision| *revision > job.revision) {
+            return;
+        }
+        if let Err(e) = job.to_df().and_then(|mut df| Ok(self.db.upsert_rows(JOBS_TABLE.to_string(), "id", &job.id, &mut df)?)) {
+            return error!("Error storing job {}: {}", job.id, e);
+        }
+        stored.insert(job.id.clone(), job.revision);
+    }
+}
+
+/// Stored jobs by id, the ones that were queued or running are failed and stored again
+fn restore(db: &DbManager) -> Result<IndexMap<String, Job>, Box<dyn std::error::Error>> {
+    if !db.table_exists(JOBS_TABLE.to_string())? {
+        return Ok(IndexMap::new());
+    }
+    let mut jobs = IndexMap::new();
+    for mut job in Job::from_df(&db.get_table(JOBS_TABLE.to_string())?)? {
+        if job.interrupt() {
+            db.upsert_rows(JOBS_TABLE.to_string(), "id", &job.id, &mut job.to_df()?)?;
+        }
+        jobs.insert(job.id.clone(), job);
+    }
+    Ok(jobs)
+}
+
+fn now() -> String {
+    Utc::now().format("%Y-%m-%d %H:%M:%S%.3f").to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, path::PathBuf};
+    use super::*;
+
+    /// Directory of a results database of its own, removed when dropped
+    struct TempDb(PathBuf);
+
+    impl TempDb {
+        fn new() -> Self {
+            let dir = env::temp_dir().join(format!("jobs-{:016x}", rand::random::<u64>()));
+            fs::create_dir_all(&dir).unwrap();
+            TempDb(dir)
+        }
+
+        /// Queue of the stored jobs without workers, the receiver stands in for them
+        fn queue(&self) -> (JobQueue, Receiver<String>) {
+            let dir = self.0.to_str().unwrap().to_string();
+            let (sender, receiver) = mpsc::sync_channel(MAX_QUEUED);
+            (JobQueue::open(DbManager::new(dir.clone(), format!("{}/results.db", dir)), sender), receiver)
+        }
+    }
+
+    impl Drop for TempDb {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn request(kind: JobKind) -> JobRequest {
+        JobRequest { kind, strategy: "sma".to_string(), symbol: Some("AAPL".to_string()), params: HashMap::new() }
+    }
+
+    fn status(queue: &JobQueue, id: &str) -> JobStatus {
+        queue.get(id).unwrap().status
+    }
+
+    #[test]
+    fn cancel_follows_the_state_of_the_job() {
+        let db = TempDb::new();
+        let (queue, _receiver) = db.queue();
+
+        let queued = queue.submit(request(JobKind::Backtest)).unwrap();
+        assert_eq!(queue.cancel(&queued.id).unwrap().status, JobStatus::Cancelled);
+        // A worker taking it off the channel leaves it cancelled
+        assert!(queue.begin(&queued.id).is_none());
+        assert!(matches!(queue.cancel(&queued.id), Err(JobError::Conflict(_))));
+
+        let running = queue.submit(request(JobKind::Optimize)).unwrap();
+        queue.begin(&running.id).unwrap();
+        let cancelling = queue.cancel(&running.id).unwrap();
+        assert_eq!(cancelling.status, JobStatus::Running);
+        assert!(cancelling.progress.cancelled);
+        // The report of a job cancelled while it ran is dropped
+        queue.end(&running.id, Ok("{}".to_string()));
+        assert_eq!(status(&queue, &running.id), JobStatus::Cancelled);
+        assert!(matches!(queue.result(&running.id), Err(JobError::Conflict(_))));
+
+        assert!(matches!(queue.cancel("missing"), Err(JobError::NotFound(_))));
+        let invalid = JobRequest { symbol: None, ..request(JobKind::Backtest) };
+        assert!(matches!(queue.submit(invalid), Err(JobError::InvalidRequest(_))));
+    }
+
+    #[test]
+    fn restart_fails_the_unfinished_jobs() {
+        let db = TempDb::new();
+        let (queue, _receiver) = db.queue();
+        let queued = queue.submit(request(JobKind::Backtest)).unwrap();
+        let running = queue.submit(request(JobKind::Sensitivity)).unwrap();
+        queue.begin(&running.id).unwrap();
+        let succeeded = queue.submit(request(JobKind::Optimize)).unwrap();
+        queue.begin(&succeeded.id).unwrap();
+        queue.end(&succeeded.id, Ok("{\"best\":1}".to_string()));
+
+        // The rows the restart reads back
+        let mut df = DataFrame::empty();
+        for job in queue.jobs.lock().unwrap().values() {
+            df.vstack_mut(&job.to_df().unwrap()).unwrap();
+        }
+        let mut jobs: IndexMap<String, Job> = Job::from_df(&df).unwrap().into_iter().map(|job| (job.id.clone(), job)).collect();
+        let interrupted: Vec<bool> = jobs.values_mut().map(Job::interrupt).collect();
+        assert_eq!(interrupted.iter().filter(|i| **i).count(), 2);
+
+        for id in [&queued.id, &running.id] {
+            let job = &jobs[id];
+            assert_eq!((job.status, job.error.as_deref()), (JobStatus::Failed, Some(INTERRUPTED)));
+            assert!(job.finished_at.is_some());
+        }
+        let job = &jobs[&succeeded.id];
+        assert_eq!(job.status, JobStatus::Succeeded);
+        assert_eq!(job.result.as_deref(), Some("{\"best\":1}"));
+        assert_eq!(job.request, request(JobKind::Optimize));
+        assert_eq!(job.started_at, queue.get(&succeeded.id).unwrap().started_at);
+    }
+}
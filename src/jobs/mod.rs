@@ -1,3 +1,5 @@
+mod job_queue;
+
 use std::{str::FromStr, thread};
 use chrono::Utc;
 use cron::Schedule;
@@ -5,6 +7,8 @@ use log::debug;
 
 use crate::db::DbManager;
 
+pub use job_queue::{Job, JobFuture, JobKind, JobQueue, JobRequest};
+
 pub async fn remove_cache_db() {
     let db = DbManager::default();
     let schedule = Schedule::from_str("0 0 7 * * *").unwrap();
@@ -0,0 +1,200 @@
+//! Checks that no strategy or backtest uses future data: dropping the newest bars of a
+//! series must never change anything computed for the bars that are left.
+
+use std::collections::HashMap;
+use chrono::{Duration, NaiveDate};
+use polars::prelude::*;
+
+use crate::scanner::{Backtest, Execution, PositionMode};
+use crate::strategy::{
+    RegimeClassifier, RiskRules, Regime, Strategy, StrategyRegimeFilter, StrategyRegistry,
+    StrategyRiskOverlay, BUY_SIGNAL, SELL_SIGNAL
+};
+use crate::transform::{BarType, BrickSize, StrategyOnBars};
+
+const BARS: usize = 400;
+/// Number of newest bars dropped in each comparison
+const CUTS: [usize; 4] = [1, 37, 120, 250];
+
+/// Daily bars of a seeded random walk with a slow sine drift, the latest day in the first row
+fn prices() -> DataFrame {
+    let start = NaiveDate::from_ymd_opt(2022, 1, 3).unwrap();
+    let mut seed: u64 = 42;
+    let mut next = || {
+        seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        (seed >> 33) as f32 / (1u64 << 31) as f32 - 0.5
+    };
+    let (mut datetime, mut high, mut low, mut open, mut close) = (vec![], vec![], vec![], vec![], vec![]);
+    let mut last = 100.0f32;
+    for i in 0..BARS {
+        let o = last * (1.0 + 0.01 * next());
+        let c = o * (1.0 + 0.04 * next() + 0.002 * (i as f32 / 40.0).sin());
+        datetime.push((start + Duration::days(i as i64)).to_string());
+        open.push(o);
+        close.push(c);
+        high.push(o.max(c) * (1.0 + 0.01 * next().abs()));
+        low.push(o.min(c) * (1.0 - 0.01 * next().abs()));
+        last = c;
+    }
+    let df = df!(
+        "datetime" => datetime,
+        "high" => high,
+        "low" => low,
+        "open" => open,
+        "close" => close
+    ).unwrap();
+    df.reverse()
+}
+
+fn without_newest(df: &DataFrame, cut: usize) -> DataFrame {
+    df.slice(cut as i64, df.height() - cut)
+}
+
+/// Every column of `truncated` must match the same rows of `full`
+fn assert_same_past(name: &str, full: &DataFrame, truncated: &DataFrame, cut: usize) {
+    for column in truncated.get_columns() {
+        let col_name = column.name().as_str();
+        let full_col = full.column(col_name)
+            .unwrap_or_else(|_| panic!("{}: column {} missing", name, col_name))
+            .slice(cut as i64, truncated.height());
+        if column.dtype() == &DataType::String {
+            let past: Vec<Option<&str>> = column.str().unwrap().into_iter().collect();
+            let expected: Vec<Option<&str>> = full_col.str().unwrap().into_iter().collect();
+            assert_eq!(past, expected, "{}: {} changed after dropping {} bars", name, col_name, cut);
+            continue;
+        }
+        let past = column.cast(&DataType::Float64).unwrap();
+        let expected = full_col.cast(&DataType::Float64).unwrap();
+        let pairs = past.f64().unwrap().into_iter().zip(expected.f64().unwrap());
+        for (row, (a, b)) in pairs.enumerate() {
+            let same = match (a, b) {
+                (Some(a), Some(b)) => (a - b).abs() <= 1e-3 * (1.0 + b.abs()) || (a.is_nan() && b.is_nan()),
+                (None, None) => true,
+                _ => false,
+            };
+            assert!(same, "{}: {} changed at row {} after dropping {} bars ({:?} vs {:?})", name, col_name, row, cut, a, b);
+        }
+    }
+}
+
+fn assert_no_lookahead<F>(name: &str, build: F)
+where
+    F: Fn(DataFrame) -> Box<dyn Strategy + Send>
+{
+    let df = prices();
+    let full = build(df.clone()).calc_signal().unwrap();
+    let signal_col = build(df.clone()).signal_col();
+    let signals = full.column(&signal_col).unwrap().i32().unwrap();
+    assert!(
+        signals.into_iter().any(|s| s == Some(BUY_SIGNAL) || s == Some(SELL_SIGNAL)),
+        "{}: test data gives no signal",
+        name
+    );
+    for cut in CUTS {
+        let truncated = build(without_newest(&df, cut)).calc_signal().unwrap();
+        assert_same_past(name, &full, &truncated, cut);
+    }
+}
+
+#[test]
+fn registered_strategies_ignore_future_bars() {
+    for spec in StrategyRegistry::global().specs() {
+        let params = spec.default_params();
+        assert_no_lookahead(&spec.name, |df| spec.build(df, &params).unwrap());
+    }
+}
+
+#[test]
+fn transformed_bars_ignore_future_bars() {
+    let spec = StrategyRegistry::global().get("sma").unwrap();
+    let params = spec.default_params();
+    let bar_types = [
+        BarType::HeikinAshi,
+        BarType::Renko(BrickSize::Fixed(1.0)),
+        BarType::Renko(BrickSize::Atr(14)),
+        BarType::Range(BrickSize::Fixed(2.0)),
+        BarType::Range(BrickSize::Atr(14)),
+    ];
+    for bars in bar_types {
+        assert_no_lookahead(&format!("sma on {:?}", bars), |df| {
+            let inner = spec.build(bars.transform(&df).unwrap(), &params).unwrap();
+            Box::new(StrategyOnBars::new(inner, df))
+        });
+    }
+}
+
+#[test]
+fn regime_filter_and_risk_overlay_ignore_future_bars() {
+    let spec = StrategyRegistry::global().get("rsi").unwrap();
+    let params = spec.default_params();
+    assert_no_lookahead("rsi in trend and range regimes", |df| {
+        Box::new(StrategyRegimeFilter::new(
+            spec.build(df, &params).unwrap(),
+            RegimeClassifier::default(),
+            vec![Regime::Trend, Regime::Range]
+        ))
+    });
+
+    let raw: HashMap<String, String> = [("stop_loss_pct", "3"), ("take_profit_atr", "2"), ("trailing_stop_pct", "5")]
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+    let rules = RiskRules::from_query(&raw).unwrap();
+    for mode in [PositionMode::LongOnly, PositionMode::ShortOnly, PositionMode::LongShort] {
+        for execution in [Execution::NextOpen, Execution::NextClose, Execution::SameClose] {
+            assert_no_lookahead(&format!("rsi with stops in {:?} at {:?}", mode, execution), |df| {
+                Box::new(
+                    StrategyRiskOverlay::new(spec.build(df, &params).unwrap(), rules.clone())
+                        .with_mode(mode)
+                        .with_execution(execution)
+                )
+            });
+        }
+    }
+}
+
+fn backtest_with(execution: &str) -> Backtest {
+    let raw = HashMap::from([("execution".to_string(), execution.to_string())]);
+    Backtest::from_query(&raw).unwrap()
+}
+
+#[test]
+fn backtest_history_ignores_future_bars() {
+    let df = prices();
+    let spec = StrategyRegistry::global().get("sma").unwrap();
+    let params = spec.default_params();
+    let signal_col = spec.build(df.clone(), &params).unwrap().signal_col();
+    for execution in ["next_open", "next_close", "same_close"] {
+        let backtest = backtest_with(execution);
+        let full_signals = spec.build(df.clone(), &params).unwrap().calc_signal().unwrap();
+        let full = backtest.run(&full_signals, &signal_col).unwrap();
+        for cut in CUTS {
+            let signals = spec.build(without_newest(&df, cut), &params).unwrap().calc_signal().unwrap();
+            let truncated = backtest.run(&signals, &signal_col).unwrap();
+            assert_same_past(execution, &full.equity_curve, &truncated.equity_curve, cut);
+        }
+    }
+}
+
+#[test]
+fn signals_fill_on_the_bar_set_by_the_execution() {
+    let df = prices();
+    let newest = df.height() - 1;
+    // One buy on the tenth bar, chronologically, and nothing else
+    let signal_row = newest - 10;
+    let signals: Vec<i32> = (0..df.height()).map(|row| if row == signal_row { BUY_SIGNAL } else { 0 }).collect();
+    let mut df_signal = df.clone();
+    df_signal.with_column(Series::new("Sig".into(), signals)).unwrap();
+
+    let open = df.column("open").unwrap().f32().unwrap();
+    let close = df.column("close").unwrap().f32().unwrap();
+    for (execution, expected) in [
+        ("next_open", open.get(signal_row - 1)),
+        ("next_close", close.get(signal_row - 1)),
+        ("same_close", close.get(signal_row)),
+    ] {
+        let run = backtest_with(execution).run(&df_signal, "Sig").unwrap();
+        assert_eq!(run.trades.len(), 1, "{}", execution);
+        assert_eq!(Some(run.trades[0].entry_price), expected, "{}", execution);
+    }
+}
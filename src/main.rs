@@ -6,6 +6,11 @@ mod converter;
 mod scanner;
 mod db;
 mod jobs;
+mod transform;
+#[cfg(test)]
+mod lookahead_tests;
+#[cfg(test)]
+mod test_fixtures;
 
 use actix_web::{web, App, HttpServer};
 use handler::*;
@@ -15,13 +20,34 @@ use jobs::*;
 async fn main() -> std::io::Result<()> {
     tokio::spawn(remove_cache_db());
     env_logger::init();
+    JobQueue::start(run_job);
     HttpServer::new(|| {
         App::new()
+            .route("/strategies", web::get().to(get_strategies))
+            .route("/strategy/{name}/{symbol}", web::get().to(get_strategy_signal))
+            .route("/regime/{name}/{symbol}", web::get().to(get_regime_performance))
+            .route("/backtest/{strategy}/{symbol}/trades", web::get().to(get_trade_log))
+            .route("/backtest/{strategy}/{symbol}", web::get().to(get_backtest))
+            .route("/optimize/{strategy}/{symbol}", web::get().to(get_grid_search))
+            .route("/sensitivity/{strategy}/{symbol}", web::get().to(get_sensitivity))
+            .route("/events/{strategy}/{symbol}", web::get().to(get_event_backtest))
+            .route("/montecarlo/{strategy}/{symbol}", web::get().to(get_monte_carlo))
+            .route("/portfolio", web::get().to(get_portfolio))
+            .route("/screener", web::get().to(get_screener))
+            .route("/jobs", web::post().to(post_job))
+            .route("/jobs", web::get().to(get_jobs))
+            .route("/jobs/{id}/result", web::get().to(get_job_result))
+            .route("/jobs/{id}", web::get().to(get_job))
+            .route("/jobs/{id}", web::delete().to(delete_job))
+            .route("/leaderboard/{strategy}/history", web::get().to(get_leaderboard_history))
+            .route("/leaderboard/{strategy}", web::get().to(get_leaderboard))
+            .route("/levels/{symbol}", web::get().to(get_levels))
             .route("/{symbol}", web::get().to(get_price))
             .route("/sma/{symbol}", web::get().to(get_sma_signal))
             .route("/ewma/{symbol}", web::get().to(get_ewma_signal))
             .route("/rsi/{symbol}", web::get().to(get_rsi_signal))
             .route("/bb/{symbol}", web::get().to(get_bb_signal))
+            .route("/bestperf/history", web::get().to(get_scan_history))
             .route("/bestperf/sma/{symbol}", web::get().to(get_best_performance_sma))
             .route("/bestperf/ewma/{symbol}", web::get().to(get_best_performance_ewma))
             .route("/bestperf/rsi/{symbol}", web::get().to(get_best_performance_rsi))
@@ -1,56 +1,635 @@
-use log::debug;
 use polars::prelude::*;
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
-#[derive(Debug)]
+use crate::strategy::{indicators, ExitReason, BUY_SIGNAL, EXIT_PRICE_COL, EXIT_REASON_COL, REGIME_COL, SELL_SIGNAL};
+use super::{CostModel, FillBar, Metric, Sizing};
+
+const UNCLASSIFIED: &str = "unclassified";
+pub const DEFAULT_CAPITAL: f64 = 10_000.0;
+
+/// Bars spent in one market regime and the profit of the trades entered in it
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct RegimePerformance {
+    pub bars: usize,
+    pub trades: usize,
+    pub result: f32,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum PositionMode {
+    #[default]
+    LongOnly,
+    ShortOnly,
+    LongShort,
+}
+
+impl PositionMode {
+    pub fn parse(raw: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        match raw {
+            "long_only" => Ok(PositionMode::LongOnly),
+            "short_only" => Ok(PositionMode::ShortOnly),
+            "long_short" => Ok(PositionMode::LongShort),
+            other => Err(format!("Unknown position mode '{}'", other).into()),
+        }
+    }
+
+    pub fn allows(&self, side: Side) -> bool {
+        match self {
+            PositionMode::LongOnly => side == Side::Long,
+            PositionMode::ShortOnly => side == Side::Short,
+            PositionMode::LongShort => true,
+        }
+    }
+}
+
+/// When a signal computed from a bar's close gets filled
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Execution {
+    #[default]
+    NextOpen,
+    NextClose,
+    /// Fills at the close the signal was computed from, which assumes trading exactly at the close
+    SameClose,
+}
+
+impl Execution {
+    pub fn parse(raw: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        match raw {
+            "next_open" => Ok(Execution::NextOpen),
+            "next_close" => Ok(Execution::NextClose),
+            "same_close" => Ok(Execution::SameClose),
+            other => Err(format!("Unknown execution '{}'", other).into()),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Step {
+    Signal,
+    Stop,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Side {
+    Long,
+    Short,
+}
+
+impl Side {
+    /// Side a signal points to, buy signals go long and sell signals go short
+    pub fn from_signal(signal: i32) -> Option<Self> {
+        match signal {
+            BUY_SIGNAL => Some(Side::Long),
+            SELL_SIGNAL => Some(Side::Short),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Side::Long => "long",
+            Side::Short => "short",
+        }
+    }
+
+    /// +1 for long and -1 for short
+    pub(super) fn sign(&self) -> f64 {
+        match self {
+            Side::Long => 1.0,
+            Side::Short => -1.0,
+        }
+    }
+}
+
+/// Why a trade was closed
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum TradeExit {
+    Signal,
+    StopLoss,
+    TakeProfit,
+    TrailingStop,
+    /// Still open on the last bar and closed at its close
+    EndOfData,
+}
+
+impl TradeExit {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TradeExit::Signal => "signal",
+            TradeExit::StopLoss => "stop_loss",
+            TradeExit::TakeProfit => "take_profit",
+            TradeExit::TrailingStop => "trailing_stop",
+            TradeExit::EndOfData => "end_of_data",
+        }
+    }
+
+    /// Exit of a risk overlay stop from its `Exit_reason`
+    fn from_overlay(reason: &str) -> Self {
+        match reason {
+            r if r == ExitReason::TakeProfit.as_str() => TradeExit::TakeProfit,
+            r if r == ExitReason::TrailingStop.as_str() => TradeExit::TrailingStop,
+            _ => TradeExit::StopLoss,
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Trade {
+    pub side: Side,
+    pub entry_datetime: String,
+    pub entry_price: f32,
+    pub exit_datetime: String,
+    pub exit_price: f32,
+    pub shares: f64,
+    /// Commissions paid on entry and exit
+    pub commission: f64,
+    /// Cost of entry and exit slippage, already included in the fill prices
+    pub slippage: f64,
+    /// Borrow fees accrued while short
+    pub borrow_cost: f64,
+    pub pnl: f64,
+    pub return_pct: f32,
+    /// Bars between the entry and the exit fill
+    pub bars_held: usize,
+    /// Maximum adverse excursion, the worst price reached while open as a return on the entry price, zero or negative
+    pub mae_pct: f32,
+    /// Maximum favorable excursion, the best price reached while open as a return on the entry price, zero or positive
+    pub mfe_pct: f32,
+    pub exit_reason: TradeExit,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub regime: Option<String>,
+}
+
+#[derive(Clone)]
+pub(super) struct OpenTrade {
+    pub(super) side: Side,
+    pub(super) entry_datetime: String,
+    pub(super) entry_price: f32,
+    pub(super) shares: f64,
+    pub(super) commission: f64,
+    pub(super) slippage: f64,
+    pub(super) borrow_cost: f64,
+    pub(super) regime: Option<String>,
+    pub(super) entry_bar: usize,
+    /// Lowest and highest prices since the entry fill
+    pub(super) lowest: f32,
+    pub(super) highest: f32,
+}
+
+impl OpenTrade {
+    /// Extends the excursions with a bar held from its open to its close
+    pub(super) fn track(&mut self, low: Option<f32>, high: Option<f32>) {
+        self.lowest = self.lowest.min(low.unwrap_or(self.lowest));
+        self.highest = self.highest.max(high.unwrap_or(self.highest));
+    }
+
+    /// Adds shares filled at `price` on the same side, the entry price becomes their average
+    pub(super) fn add(&mut self, shares: f64, price: f32, commission: f64, slippage: f64) {
+        let total = self.shares + shares;
+        self.entry_price = ((self.shares * self.entry_price as f64 + shares * price as f64) / total) as f32;
+        self.shares = total;
+        self.commission += commission;
+        self.slippage += slippage;
+        self.track(Some(price), Some(price));
+    }
+
+    /// Takes `shares` out of the position along with their share of the costs paid so far
+    pub(super) fn split_off(&mut self, shares: f64) -> OpenTrade {
+        let fraction = (shares / self.shares).min(1.0);
+        let part = OpenTrade {
+            shares,
+            commission: self.commission * fraction,
+            slippage: self.slippage * fraction,
+            borrow_cost: self.borrow_cost * fraction,
+            ..self.clone()
+        };
+        self.shares -= shares;
+        self.commission -= part.commission;
+        self.slippage -= part.slippage;
+        self.borrow_cost -= part.borrow_cost;
+        part
+    }
+
+    pub(super) fn close(
+        mut self,
+        exit_datetime: String,
+        exit_price: f32,
+        exit_bar: usize,
+        exit_reason: TradeExit,
+        commission: f64,
+        slippage: f64
+    ) -> Trade {
+        self.track(Some(exit_price), Some(exit_price));
+        let excursion = |price: f32| (100.0 * self.side.sign() * (price / self.entry_price - 1.0) as f64) as f32;
+        let (worst, best) = match self.side {
+            Side::Long => (self.lowest, self.highest),
+            Side::Short => (self.highest, self.lowest),
+        };
+        let cost_basis = self.shares * self.entry_price as f64 + self.commission;
+        let pnl = self.side.sign() * self.shares * (exit_price - self.entry_price) as f64
+            - self.commission - commission - self.borrow_cost;
+        Trade {
+            side: self.side,
+            pnl,
+            return_pct: (100.0 * pnl / cost_basis) as f32,
+            entry_datetime: self.entry_datetime,
+            entry_price: self.entry_price,
+            exit_datetime,
+            exit_price,
+            shares: self.shares,
+            commission: self.commission + commission,
+            slippage: self.slippage + slippage,
+            borrow_cost: self.borrow_cost,
+            bars_held: exit_bar - self.entry_bar,
+            mae_pct: excursion(worst).min(0.0),
+            mfe_pct: excursion(best).max(0.0),
+            exit_reason,
+            regime: self.regime,
+        }
+    }
+}
+
+/// One simulated run of a signal column
+#[derive(Debug, Clone)]
+pub struct BacktestRun {
+    pub signal: String,
+    pub initial_capital: f64,
+    pub final_equity: f64,
+    pub total_return_pct: f32,
+    /// `datetime`, `cash`, `shares` and `equity` of every bar, in the same row order as the input
+    pub equity_curve: DataFrame,
+    pub trades: Vec<Trade>,
+    pub sizing: Sizing,
+    pub regimes: Option<HashMap<String, RegimePerformance>>,
+}
+
+/// Simulates one position at a time in whole shares, filled at the open or close picked by the
+/// execution. Depending on the position mode a buy signal opens a long or covers a short and a
+/// sell signal closes a long or opens a short, in long-short mode an opposite signal reverses
+/// the position. Equity is marked to market on every bar and every fill pays the commission
+/// and slippage of the cost model.
+#[derive(Debug, Clone)]
 pub struct Backtest{
-    pub col_name: String,
-    pub shares_hold: i32,
-    pub results: HashMap<String, f32>
+    pub initial_capital: f64,
+    pub mode: PositionMode,
+    pub execution: Execution,
+    pub sizing: Sizing,
+    pub costs: CostModel,
+    pub metric: Metric,
+}
+
+impl Default for Backtest {
+    fn default() -> Self {
+        Backtest::with_capital(DEFAULT_CAPITAL)
+    }
 }
 
 impl Backtest {
     pub fn new() -> Self {
+        Backtest::default()
+    }
+
+    pub fn with_capital(initial_capital: f64) -> Self {
         Backtest {
-            col_name: String::from("Backtest"),
-            shares_hold: 0,
-            results: HashMap::new()
-        }
-    }
-
-    pub fn execute(&mut self, df: &DataFrame, sig_col: &str) {
-        let df_bt = df.clone()
-            .lazy()
-            .with_column(
-                (col("close") * col(sig_col)).alias(self.col_name.as_str())
-            )
-            .collect().ok();
-        if let Some(df_bt) = df_bt{
-            let mut cum_sum = 0.0;
-            self.shares_hold = 0;
-            for i in (0..df_bt.height()).rev() {
-                if let Some(exec_price) = self.anyvalue_to_float(df_bt.column("Backtest").unwrap().get(i).unwrap()) {
-                    let share = self.anyvalue_to_float(df_bt.column(sig_col).unwrap().get(i).unwrap()).unwrap();
-                    if ((exec_price < 0.0) & (self.shares_hold == 0) ) ||
-                        ((exec_price != 0.0) & (self.shares_hold > 0)) {
-                        cum_sum += exec_price;
-                        self.shares_hold -= share as i32;
+            initial_capital,
+            mode: PositionMode::default(),
+            execution: Execution::default(),
+            sizing: Sizing::default(),
+            costs: CostModel::default(),
+            metric: Metric::default(),
+        }
+    }
+
+    /// Reads `capital`, `position_mode`, `execution`, the sizing, the cost model and the `metric` to rank by from a query string
+    pub fn from_query(raw: &HashMap<String, String>) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut backtest = match raw.get("capital") {
+            Some(capital) => Backtest::with_capital(capital.parse().map_err(|e| format!("Invalid capital: {}", e))?),
+            None => Backtest::new(),
+        };
+        if !backtest.initial_capital.is_finite() || backtest.initial_capital <= 0.0 {
+            return Err("capital must be positive".into());
+        }
+        if let Some(mode) = raw.get("position_mode") {
+            backtest.mode = PositionMode::parse(mode)?;
+        }
+        if let Some(execution) = raw.get("execution") {
+            backtest.execution = Execution::parse(execution)?;
+        }
+        backtest.sizing = Sizing::from_query(raw)?;
+        backtest.costs = CostModel::from_query(raw)?;
+        if let Some(metric) = raw.get("metric") {
+            backtest.metric = Metric::parse(metric)?;
+        }
+        Ok(backtest)
+    }
+
+    pub fn with_metric(mut self, metric: Metric) -> Self {
+        self.metric = metric;
+        self
+    }
+
+    pub fn run(&self, df: &DataFrame, sig_col: &str) -> Result<BacktestRun, Box<dyn std::error::Error>> {
+        let datetime = df.column("datetime")?.str()?;
+        let open_price = indicators::column_f32(df, "open")?;
+        let close = indicators::column_f32(df, "close")?;
+        let high = indicators::column_f32(df, "high")?;
+        let low = indicators::column_f32(df, "low")?;
+        let volume = match df.column("volume") {
+            Ok(_) => indicators::column_f32(df, "volume")?,
+            Err(_) => vec![None; df.height()],
+        };
+        // Exits from a risk overlay fill intrabar at their own price instead of the close
+        let exit_price = match df.column(EXIT_PRICE_COL) {
+            Ok(_) => indicators::column_f32(df, EXIT_PRICE_COL)?,
+            Err(_) => vec![None; df.height()],
+        };
+        let atr = match self.sizing.atr_window() {
+            Some(window) => indicators::atr(df, window)?,
+            None => vec![None; df.height()],
+        };
+        let exit_reason = df.column(EXIT_REASON_COL).ok().and_then(|c| c.str().ok());
+        let signals = df.column(sig_col)?.cast(&DataType::Int32)?;
+        let signals = signals.i32()?;
+        let regimes = df.column(REGIME_COL).ok().and_then(|c| c.str().ok());
+        let datetime_at = |row: usize| datetime.get(row).unwrap_or_default().to_string();
+        let bar_at = |row: usize| FillBar { high: high[row], low: low[row], volume: volume[row] };
+
+        let mut cash = self.initial_capital;
+        let mut position: Option<OpenTrade> = None;
+        let mut trades = Vec::new();
+        let mut last_close: Option<(usize, usize, f32)> = None;
+        let mut regime_bars: HashMap<String, usize> = HashMap::new();
+        let mut cash_curve = vec![None; df.height()];
+        let mut shares_curve = vec![None; df.height()];
+        let mut equity_curve = vec![None; df.height()];
+
+        let rows = indicators::chronological_rows(df)?;
+        let is_stop = |row: usize| exit_reason
+            .and_then(|r| r.get(row))
+            .is_some_and(|reason| reason != ExitReason::Signal.as_str());
+        let regime_at = |row: usize| regimes.map(|r| r.get(row).unwrap_or(UNCLASSIFIED).to_string());
+        let steps = match self.execution {
+            // The open comes before anything that happens intrabar
+            Execution::NextOpen => [Step::Signal, Step::Stop],
+            Execution::NextClose | Execution::SameClose => [Step::Stop, Step::Signal],
+        };
+
+        for (t, row) in rows.iter().copied().enumerate() {
+            if let Some(regime) = regime_at(row) {
+                *regime_bars.entry(regime).or_default() += 1;
+            }
+            if let Some(price) = close[row] {
+                if let Some(open) = position.as_mut().filter(|open| open.side == Side::Short) {
+                    let fee = self.costs.borrow_fee(open.shares, price as f64);
+                    cash -= fee;
+                    open.borrow_cost += fee;
+                }
+                // Bar whose signal fills now and the price it fills at
+                let (signal_row, fill_price) = match self.execution {
+                    Execution::SameClose => (Some(row), price),
+                    Execution::NextClose => (t.checked_sub(1).map(|prev| rows[prev]), price),
+                    Execution::NextOpen => (t.checked_sub(1).map(|prev| rows[prev]), open_price[row].unwrap_or(price)),
+                };
+                for step in steps {
+                    match step {
+                        // Stops of the risk overlay fill on their own bar at their own price, their signal is
+                        // the opposite side of the position they close
+                        Step::Stop => {
+                            let stopped = Side::from_signal(signals.get(row).unwrap_or(0));
+                            if is_stop(row) && let Some(open) = position.take_if(|open| stopped.is_some_and(|side| side != open.side)) {
+                                let fill = exit_price[row].unwrap_or(price);
+                                let reason = TradeExit::from_overlay(exit_reason.and_then(|r| r.get(row)).unwrap_or_default());
+                                let (cash_flow, trade) = self.close_trade(open, fill, &bar_at(row), datetime_at(row), t, reason)?;
+                                cash += cash_flow;
+                                trades.push(trade);
+                            } else if let Some(open) = position.as_mut() {
+                                open.track(low[row], high[row]);
+                            }
+                        }
+                        Step::Signal => {
+                            let Some(signal_row) = signal_row.filter(|r| !is_stop(*r)) else { continue };
+                            let Some(side) = Side::from_signal(signals.get(signal_row).unwrap_or(0)) else { continue };
+                            if let Some(open) = position.take_if(|open| open.side != side) {
+                                let (cash_flow, trade) = self.close_trade(
+                                    open, fill_price, &bar_at(row), datetime_at(row), t, TradeExit::Signal
+                                )?;
+                                cash += cash_flow;
+                                trades.push(trade);
+                            }
+                            // Flat before entering, so the cash is the whole equity
+                            let target = self.sizing.shares(cash, fill_price as f64, atr[signal_row], &trades);
+                            if position.is_none() && self.mode.allows(side)
+                                && let Some((cash_flow, open)) = self.open_trade(
+                                    side, cash, target, fill_price, &bar_at(row), datetime_at(row), t, regime_at(signal_row)
+                                )? {
+                                cash += cash_flow;
+                                position = Some(open);
+                            }
+                        }
                     }
-                } else {
-                    debug!("Unsupported value type in backtest column at row {}", i);
                 }
+                last_close = Some((t, row, price));
             }
-            self.results.insert(sig_col.to_string(), cum_sum);
+            let shares = position.as_ref().map_or(0.0, |open| open.side.sign() * open.shares);
+            let mark = last_close.map_or(0.0, |(_, _, price)| price as f64);
+            cash_curve[row] = Some(cash);
+            shares_curve[row] = Some(shares);
+            equity_curve[row] = Some(cash + shares * mark);
+        }
+        // A position still open at the end of the data is closed at the last close
+        if let (Some(open), Some((t, row, price))) = (position.take(), last_close) {
+            let (cash_flow, trade) = self.close_trade(open, price, &bar_at(row), datetime_at(row), t, TradeExit::EndOfData)?;
+            cash += cash_flow;
+            trades.push(trade);
         }
+
+        let regimes = regimes.map(|_| {
+            let mut perf: HashMap<String, RegimePerformance> = regime_bars.into_iter()
+                .map(|(regime, bars)| (regime, RegimePerformance { bars, ..Default::default() }))
+                .collect();
+            for trade in &trades {
+                let entry = perf.entry(trade.regime.clone().unwrap_or(UNCLASSIFIED.to_string())).or_default();
+                entry.trades += 1;
+                entry.result += trade.pnl as f32;
+            }
+            perf
+        });
+        let equity_curve = DataFrame::new(vec![
+            df.column("datetime")?.clone(),
+            Series::new("cash".into(), cash_curve).into(),
+            Series::new("shares".into(), shares_curve).into(),
+            Series::new("equity".into(), equity_curve).into(),
+        ])?;
+
+        Ok(BacktestRun {
+            signal: sig_col.to_string(),
+            initial_capital: self.initial_capital,
+            final_equity: cash,
+            total_return_pct: (100.0 * (cash / self.initial_capital - 1.0)) as f32,
+            equity_curve,
+            trades,
+            sizing: self.sizing.clone(),
+            regimes,
+        })
     }
 
-    fn anyvalue_to_float(&self, av: AnyValue) -> Option<f32> {
-        match av {
-            AnyValue::Float64(bt_value) => Some(bt_value as f32),
-            AnyValue::Float32(bt_value) => Some(bt_value),
-            AnyValue::Int64(bt_value) => Some(bt_value as f32),
-            AnyValue::Int32(bt_value) => Some(bt_value as f32),
-            _ => None
+    /// Buys a long or sells a short of `target` shares, or as many as the cash allows,
+    /// returns the cash flow of the fill. Slippage is estimated for the size before costs,
+    /// which only overstates it.
+    #[allow(clippy::too_many_arguments)]
+    fn open_trade(
+        &self,
+        side: Side,
+        cash: f64,
+        target: f64,
+        price: f32,
+        bar: &FillBar,
+        datetime: String,
+        bar_index: usize,
+        regime: Option<String>
+    ) -> Result<Option<(f64, OpenTrade)>, Box<dyn std::error::Error>> {
+        let estimate = self.costs.affordable_shares(cash, price as f64).min(target);
+        let slippage = self.costs.slippage.per_share(price as f64, bar, estimate)?;
+        let fill = (price as f64 + side.sign() * slippage).max(0.0);
+        let shares = self.costs.affordable_shares(cash, fill).min(target);
+        if shares <= 0.0 {
+            return Ok(None);
         }
+        let commission = self.costs.commission.fee(shares, fill);
+        let cash_flow = -side.sign() * shares * fill - commission;
+        Ok(Some((cash_flow, OpenTrade {
+            side,
+            entry_datetime: datetime,
+            entry_price: fill as f32,
+            shares,
+            commission,
+            slippage: slippage * shares,
+            borrow_cost: 0.0,
+            regime,
+            entry_bar: bar_index,
+            lowest: fill as f32,
+            highest: fill as f32,
+        })))
+    }
+
+    /// Sells a long or covers a short at `price` with slippage, returns the cash flow net of commission
+    fn close_trade(
+        &self,
+        open: OpenTrade,
+        price: f32,
+        bar: &FillBar,
+        datetime: String,
+        bar_index: usize,
+        reason: TradeExit
+    ) -> Result<(f64, Trade), Box<dyn std::error::Error>> {
+        let slippage = self.costs.slippage.per_share(price as f64, bar, open.shares)?;
+        let fill = (price as f64 - open.side.sign() * slippage).max(0.0);
+        let commission = self.costs.commission.fee(open.shares, fill);
+        let cash_flow = open.side.sign() * open.shares * fill - commission;
+        let slippage_cost = slippage * open.shares;
+        let trade = open.close(datetime, fill as f32, bar_index, reason, commission, slippage_cost);
+        Ok((cash_flow, trade))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_fixtures::{bars, query, with_signals};
+    use super::*;
+
+    /// Column of the equity curve, oldest bar first
+    fn curve(run: &BacktestRun, name: &str) -> Vec<f64> {
+        let values: Vec<f64> = run.equity_curve.column(name).unwrap().f64().unwrap().into_iter().flatten().collect();
+        values.into_iter().rev().collect()
+    }
+
+    #[test]
+    fn equity_is_marked_to_the_close() {
+        // Buys at the open of 100 after the first bar and sells at the open of 120 after the third
+        let df = with_signals(bars(&[100.0, 100.0, 110.0, 120.0, 120.0]), "Sig", &[BUY_SIGNAL, 0, SELL_SIGNAL, 0, 0]);
+        let run = Backtest::new().run(&df, "Sig").unwrap();
+        assert_eq!(curve(&run, "cash"), [10_000.0, 0.0, 0.0, 12_000.0, 12_000.0]);
+        assert_eq!(curve(&run, "shares"), [0.0, 100.0, 100.0, 0.0, 0.0]);
+        assert_eq!(curve(&run, "equity"), [10_000.0, 10_000.0, 11_000.0, 12_000.0, 12_000.0]);
+        assert_eq!(run.final_equity, 12_000.0);
+        assert_eq!(run.total_return_pct, 20.0);
+        assert_eq!(run.trades.len(), 1);
+        assert_eq!(run.trades[0].pnl, 2_000.0);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn cash_left_over_from_whole_shares_stays_in_the_equity() {
+        // 333 shares of 30 leave 10 in cash
+        let df = with_signals(bars(&[30.0, 30.0, 40.0]), "Sig", &[BUY_SIGNAL, 0, 0]);
+        let run = Backtest::new().run(&df, "Sig").unwrap();
+        assert_eq!(curve(&run, "cash"), [10_000.0, 10.0, 10.0]);
+        assert_eq!(curve(&run, "equity"), [10_000.0, 10_000.0, 13_330.0]);
+        // The position still open on the last bar is sold at its close
+        assert_eq!(run.final_equity, 13_330.0);
+    }
+
+    #[test]
+    fn short_profits_from_a_fall_and_pays_borrow_fees() {
+        // Shorts at the open of 100 after the first bar and covers at the open of 80 after the third
+        let df = with_signals(bars(&[100.0, 100.0, 90.0, 80.0, 80.0]), "Sig", &[SELL_SIGNAL, 0, BUY_SIGNAL, 0, 0]);
+        // A tenth of a percent of the shorted value for each bar
+        let backtest = Backtest::from_query(&query(&[("position_mode", "short_only"), ("borrow_rate_pct", "25.2")])).unwrap();
+        let run = backtest.run(&df, "Sig").unwrap();
+        assert_eq!(curve(&run, "shares"), [0.0, -100.0, -100.0, 0.0, 0.0]);
+        // Fees are charged at the closes of 90 and 80, the bar that covers at its open pays one too
+        let trade = &run.trades[0];
+        assert_eq!(trade.side, Side::Short);
+        assert!((trade.borrow_cost - 17.0).abs() < 1e-6);
+        assert!((trade.pnl - 1_983.0).abs() < 1e-6);
+        assert!((run.final_equity - 11_983.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn long_only_ignores_sell_signals_when_flat() {
+        let df = with_signals(bars(&[100.0, 100.0, 90.0]), "Sig", &[SELL_SIGNAL, 0, 0]);
+        let run = Backtest::new().run(&df, "Sig").unwrap();
+        assert!(run.trades.is_empty());
+        assert_eq!(run.final_equity, 10_000.0);
+    }
+
+    #[test]
+    fn long_short_reverses_on_an_opposite_signal() {
+        let df = with_signals(bars(&[100.0, 100.0, 110.0, 120.0, 100.0]), "Sig", &[BUY_SIGNAL, 0, SELL_SIGNAL, 0, 0]);
+        let backtest = Backtest::from_query(&query(&[("position_mode", "long_short")])).unwrap();
+        let run = backtest.run(&df, "Sig").unwrap();
+        assert_eq!(curve(&run, "shares"), [0.0, 100.0, 100.0, -100.0, -100.0]);
+        // The long gains 2000 and the short taken at 120 gains another 2000 by the last close
+        let sides: Vec<(Side, f64, TradeExit)> = run.trades.iter().map(|t| (t.side, t.pnl, t.exit_reason)).collect();
+        assert_eq!(sides, [(Side::Long, 2_000.0, TradeExit::Signal), (Side::Short, 2_000.0, TradeExit::EndOfData)]);
+        assert_eq!(run.final_equity, 14_000.0);
+    }
+
+    #[test]
+    fn trades_record_how_long_and_how_far_they_went() {
+        // Held from the open of 100 on the second bar to the open of 110 on the fourth, the third bar dips to a low of 94
+        let df = with_signals(bars(&[100.0, 100.0, 95.0, 110.0, 110.0]), "Sig", &[BUY_SIGNAL, 0, SELL_SIGNAL, 0, 0]);
+        let trade = &Backtest::new().run(&df, "Sig").unwrap().trades[0];
+        assert_eq!(trade.bars_held, 2);
+        assert!((trade.mae_pct + 6.0).abs() < 1e-4);
+        assert!((trade.mfe_pct - 10.0).abs() < 1e-4);
+        assert_eq!(trade.exit_reason, TradeExit::Signal);
+    }
+
+    #[test]
+    fn excursions_of_a_short_are_mirrored() {
+        let df = with_signals(bars(&[100.0, 100.0, 95.0, 110.0]), "Sig", &[SELL_SIGNAL, 0, 0, 0]);
+        let backtest = Backtest::from_query(&query(&[("position_mode", "short_only")])).unwrap();
+        let trade = &backtest.run(&df, "Sig").unwrap().trades[0];
+        // Still open on the last bar, so it is covered at the last close
+        assert_eq!(trade.exit_reason, TradeExit::EndOfData);
+        assert_eq!(trade.bars_held, 2);
+        // The high of 111 on the last bar is the worst price and the low of 94 the best
+        assert!((trade.mae_pct + 11.0).abs() < 1e-4);
+        assert!((trade.mfe_pct - 6.0).abs() < 1e-4);
+    }
+}
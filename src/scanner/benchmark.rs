@@ -0,0 +1,136 @@
+use std::collections::HashMap;
+use polars::prelude::*;
+use serde::Serialize;
+
+use crate::strategy::indicators;
+use super::metrics::BARS_PER_YEAR;
+use super::BacktestRun;
+
+/// Backtest against buying a benchmark on the first bar and holding it to the last, before costs
+#[derive(Serialize, Debug, Clone)]
+pub struct BenchmarkComparison {
+    /// None when the benchmark is the traded symbol itself
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub symbol: Option<String>,
+    pub buy_and_hold_return_pct: f32,
+    /// Backtest return over the same bars minus the buy-and-hold return
+    pub excess_return_pct: f32,
+    /// Sensitivity of the bar returns to the benchmark bar returns
+    pub beta: Option<f32>,
+    /// Annualized return not explained by beta, without a risk-free rate
+    pub alpha_pct: Option<f32>,
+    pub correlation: Option<f32>,
+    /// Bars the backtest and the benchmark have in common
+    pub bars: usize,
+}
+
+fn mean(values: &[f64]) -> f64 {
+    values.iter().sum::<f64>() / values.len() as f64
+}
+
+impl BenchmarkComparison {
+    /// Compares on the bars whose datetime is in both the equity curve and `prices`
+    pub fn new(run: &BacktestRun, prices: &DataFrame, symbol: Option<String>) -> Result<Self, Box<dyn std::error::Error>> {
+        let bench_datetime = prices.column("datetime")?.str()?;
+        let bench_close = indicators::column_f32(prices, "close")?;
+        let closes: HashMap<&str, f64> = bench_datetime.into_iter()
+            .zip(bench_close)
+            .filter_map(|(dt, close)| Some((dt?, close? as f64)))
+            .collect();
+
+        let curve = &run.equity_curve;
+        let datetime = curve.column("datetime")?.str()?;
+        let equity_col = curve.column("equity")?.f64()?;
+        let (equity, benchmark): (Vec<f64>, Vec<f64>) = indicators::chronological_rows(curve)?
+            .into_iter()
+            .filter_map(|row| Some((equity_col.get(row)?, *closes.get(datetime.get(row)?)?)))
+            .unzip();
+        if equity.len() < 2 {
+            return Err(format!(
+                "Benchmark {} has fewer than 2 bars in common with the backtest",
+                symbol.as_deref().unwrap_or("buy and hold")
+            ).into());
+        }
+
+        let total_return = |values: &[f64]| (100.0 * (values[values.len() - 1] / values[0] - 1.0)) as f32;
+        let buy_and_hold_return_pct = total_return(&benchmark);
+        let returns = |values: &[f64]| -> Vec<f64> { values.windows(2).map(|w| w[1] / w[0] - 1.0).collect() };
+        let strategy_returns = returns(&equity);
+        let benchmark_returns = returns(&benchmark);
+        let (avg_s, avg_b) = (mean(&strategy_returns), mean(&benchmark_returns));
+        let covariance = mean(&strategy_returns.iter().zip(&benchmark_returns)
+            .map(|(s, b)| (s - avg_s) * (b - avg_b))
+            .collect::<Vec<f64>>());
+        let var_s = mean(&strategy_returns.iter().map(|s| (s - avg_s).powi(2)).collect::<Vec<f64>>());
+        let var_b = mean(&benchmark_returns.iter().map(|b| (b - avg_b).powi(2)).collect::<Vec<f64>>());
+
+        let beta = (var_b > 0.0).then(|| covariance / var_b);
+        Ok(BenchmarkComparison {
+            symbol,
+            buy_and_hold_return_pct,
+            excess_return_pct: total_return(&equity) - buy_and_hold_return_pct,
+            beta: beta.map(|b| b as f32),
+            alpha_pct: beta.map(|b| (100.0 * (avg_s - b * avg_b) * BARS_PER_YEAR as f64) as f32),
+            correlation: (var_s > 0.0 && var_b > 0.0).then(|| (covariance / (var_s * var_b).sqrt()) as f32),
+            bars: equity.len(),
+        })
+    }
+
+    /// Buy and hold of the traded symbol, plus the benchmark symbol when one is given
+    pub fn all(
+        run: &BacktestRun,
+        prices: &DataFrame,
+        benchmark: Option<&(String, DataFrame)>
+    ) -> Result<Vec<Self>, Box<dyn std::error::Error>> {
+        let mut comparisons = vec![BenchmarkComparison::new(run, prices, None)?];
+        if let Some((symbol, benchmark_prices)) = benchmark {
+            comparisons.push(BenchmarkComparison::new(run, benchmark_prices, Some(symbol.clone()))?);
+        }
+        Ok(comparisons)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::scanner::Backtest;
+    use crate::strategy::BUY_SIGNAL;
+    use crate::test_fixtures::{bars, with_signals};
+    use super::*;
+
+    const CLOSE: [f32; 4] = [100.0, 100.0, 110.0, 121.0];
+
+    fn run(signals: &[i32]) -> BacktestRun {
+        Backtest::new().run(&with_signals(bars(&CLOSE), "Sig", signals), "Sig").unwrap()
+    }
+
+    #[test]
+    fn holding_the_symbol_matches_buy_and_hold() {
+        // Filled at the open of the second bar, which is still 100
+        let comparison = BenchmarkComparison::new(&run(&[BUY_SIGNAL, 0, 0, 0]), &bars(&CLOSE), None).unwrap();
+        assert!((comparison.buy_and_hold_return_pct - 21.0).abs() < 1e-4);
+        assert!(comparison.excess_return_pct.abs() < 1e-4);
+        assert!((comparison.beta.unwrap() - 1.0).abs() < 1e-6);
+        assert!((comparison.correlation.unwrap() - 1.0).abs() < 1e-6);
+        assert!(comparison.alpha_pct.unwrap().abs() < 1e-4);
+        assert_eq!(comparison.bars, 4);
+    }
+
+    #[test]
+    fn staying_flat_misses_the_benchmark() {
+        let comparison = BenchmarkComparison::new(&run(&[0; 4]), &bars(&CLOSE), None).unwrap();
+        assert!((comparison.excess_return_pct + 21.0).abs() < 1e-4);
+        assert_eq!(comparison.beta, Some(0.0));
+        assert_eq!(comparison.correlation, None);
+    }
+
+    #[test]
+    fn compares_only_the_bars_in_common() {
+        let run = run(&[BUY_SIGNAL, 0, 0, 0]);
+        // The benchmark runs from the third day to a day after the backtest
+        let benchmark = bars(&[50.0, 100.0, 100.0, 50.0, 50.0]).slice(0, 3);
+        let comparison = BenchmarkComparison::new(&run, &benchmark, Some("SPY".to_string())).unwrap();
+        assert_eq!(comparison.bars, 2);
+        assert!((comparison.buy_and_hold_return_pct + 50.0).abs() < 1e-4);
+        assert!(BenchmarkComparison::new(&run, &benchmark.slice(0, 1), None).is_err());
+    }
+}
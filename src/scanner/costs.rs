@@ -0,0 +1,172 @@
+use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+
+use super::metrics::BARS_PER_YEAR;
+
+/// Broker fees charged on every fill, all parts add up
+#[derive(Deserialize, Serialize, Debug, Clone, Default, PartialEq)]
+pub struct Commission {
+    pub per_share: f64,
+    pub per_trade: f64,
+    pub pct: f64,
+}
+
+impl Commission {
+    pub fn fee(&self, shares: f64, price: f64) -> f64 {
+        if shares == 0.0 {
+            return 0.0;
+        }
+        self.per_trade + self.per_share * shares + self.pct / 100.0 * shares * price
+    }
+}
+
+/// How far a fill lands from the quoted price, always against the order
+#[derive(Deserialize, Serialize, Debug, Clone, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Slippage {
+    #[default]
+    None,
+    /// Basis points of the price
+    FixedBps(f64),
+    /// Fraction of the bar's high-low range
+    RangeFraction(f64),
+    /// Coefficient times the share of the bar's volume taken by the order, as a fraction of the price
+    VolumeParticipation(f64),
+}
+
+/// Prices of the bar an order fills on
+pub struct FillBar {
+    pub high: Option<f32>,
+    pub low: Option<f32>,
+    pub volume: Option<f32>,
+}
+
+impl Slippage {
+    /// Slippage per share of an order of `shares` at `price`
+    pub fn per_share(&self, price: f64, bar: &FillBar, shares: f64) -> Result<f64, Box<dyn std::error::Error>> {
+        match self {
+            Slippage::None => Ok(0.0),
+            Slippage::FixedBps(bps) => Ok(price * bps / 10_000.0),
+            Slippage::RangeFraction(fraction) => match (bar.high, bar.low) {
+                (Some(high), Some(low)) => Ok(fraction * (high - low) as f64),
+                _ => Err("Range slippage needs the bar's high and low".into()),
+            },
+            Slippage::VolumeParticipation(coefficient) => match bar.volume {
+                Some(volume) if volume > 0.0 => Ok(price * coefficient * shares / volume as f64),
+                _ => Err("Volume participation slippage needs a volume column".into()),
+            },
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default, PartialEq)]
+pub struct CostModel {
+    pub commission: Commission,
+    pub slippage: Slippage,
+    /// Yearly fee in percent of the value of shares borrowed for shorts
+    pub borrow_rate_pct: f64,
+}
+
+fn parse_f64(raw: &HashMap<String, String>, key: &str) -> Result<Option<f64>, Box<dyn std::error::Error>> {
+    match raw.get(key) {
+        Some(value) => {
+            let value: f64 = value.parse().map_err(|e| format!("Invalid {}: {}", key, e))?;
+            if !value.is_finite() || value < 0.0 {
+                return Err(format!("{} must not be negative", key).into());
+            }
+            Ok(Some(value))
+        }
+        None => Ok(None),
+    }
+}
+
+impl CostModel {
+    /// Reads `commission_{per_share,per_trade,pct}`, one of `slippage_{bps,range,participation}` and `borrow_rate_pct`
+    pub fn from_query(raw: &HashMap<String, String>) -> Result<Self, Box<dyn std::error::Error>> {
+        let commission = Commission {
+            per_share: parse_f64(raw, "commission_per_share")?.unwrap_or(0.0),
+            per_trade: parse_f64(raw, "commission_per_trade")?.unwrap_or(0.0),
+            pct: parse_f64(raw, "commission_pct")?.unwrap_or(0.0),
+        };
+        let mut slippages = Vec::new();
+        if let Some(bps) = parse_f64(raw, "slippage_bps")? {
+            slippages.push(Slippage::FixedBps(bps));
+        }
+        if let Some(fraction) = parse_f64(raw, "slippage_range")? {
+            slippages.push(Slippage::RangeFraction(fraction));
+        }
+        if let Some(coefficient) = parse_f64(raw, "slippage_participation")? {
+            slippages.push(Slippage::VolumeParticipation(coefficient));
+        }
+        if slippages.len() > 1 {
+            return Err("Only one slippage model can be used at a time".into());
+        }
+        Ok(CostModel {
+            commission,
+            slippage: slippages.pop().unwrap_or_default(),
+            borrow_rate_pct: parse_f64(raw, "borrow_rate_pct")?.unwrap_or(0.0),
+        })
+    }
+
+    /// Whole shares that `cash` buys at `price` once commissions are paid
+    pub fn affordable_shares(&self, cash: f64, price: f64) -> f64 {
+        let per_share = price + self.commission.per_share + self.commission.pct / 100.0 * price;
+        ((cash - self.commission.per_trade) / per_share).floor().max(0.0)
+    }
+
+    /// Borrow fee of one bar for a short of `shares` at `price`
+    pub fn borrow_fee(&self, shares: f64, price: f64) -> f64 {
+        shares * price * self.borrow_rate_pct / 100.0 / BARS_PER_YEAR as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::scanner::Backtest;
+    use crate::strategy::BUY_SIGNAL;
+    use crate::test_fixtures::{bars, query, with_prices, with_signals};
+    use super::*;
+
+    fn bar(volume: Option<f32>) -> FillBar {
+        FillBar { high: Some(101.0), low: Some(99.0), volume }
+    }
+
+    #[test]
+    fn participation_slippage_grows_with_the_share_of_volume() {
+        let slippage = Slippage::VolumeParticipation(0.1);
+        let volume = bar(Some(10_000.0));
+        assert!((slippage.per_share(50.0, &volume, 1_000.0).unwrap() - 0.5).abs() < 1e-9);
+        assert!((slippage.per_share(50.0, &volume, 2_000.0).unwrap() - 1.0).abs() < 1e-9);
+        assert_eq!(slippage.per_share(50.0, &volume, 0.0).unwrap(), 0.0);
+    }
+
+    #[test]
+    fn participation_slippage_needs_volume() {
+        let slippage = Slippage::VolumeParticipation(0.1);
+        assert!(slippage.per_share(50.0, &bar(None), 100.0).is_err());
+        assert!(slippage.per_share(50.0, &bar(Some(0.0)), 100.0).is_err());
+    }
+
+    #[test]
+    fn only_one_slippage_model_is_read() {
+        let model = CostModel::from_query(&query(&[("slippage_participation", "0.2")])).unwrap();
+        assert_eq!(model.slippage, Slippage::VolumeParticipation(0.2));
+        assert!(CostModel::from_query(&query(&[("slippage_participation", "0.2"), ("slippage_bps", "5")])).is_err());
+        assert!(CostModel::from_query(&query(&[("slippage_participation", "-1")])).is_err());
+    }
+
+    #[test]
+    fn backtest_entry_pays_participation_slippage() {
+        // A buy on the first bar fills at the next open of 100
+        let df = with_prices(bars(&[100.0; 3]), "volume", &[1_000.0; 3]);
+        let df = with_signals(df, "Sig", &[BUY_SIGNAL, 0, 0]);
+        let backtest = Backtest::from_query(&query(&[("capital", "10000"), ("slippage_participation", "0.1")])).unwrap();
+        let run = backtest.run(&df, "Sig").unwrap();
+        // 100 shares are 10% of the volume, so each pays 1% of the price and 99 shares fit at 101
+        let trade = &run.trades[0];
+        assert_eq!(trade.entry_price, 101.0);
+        assert_eq!(trade.shares, 99.0);
+        // The exit at the last close sells the 99 shares for 0.99 less each
+        assert!((trade.slippage - 99.0 - 99.0 * 0.99).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,769 @@
+use std::collections::{HashMap, VecDeque};
+use polars::prelude::*;
+use serde::Serialize;
+
+use crate::strategy::{indicators, StrategyParams, BUY_SIGNAL, SELL_SIGNAL};
+use super::backtest::{OpenTrade, PositionMode, Side, TradeExit, DEFAULT_CAPITAL};
+use super::{BacktestRun, CostModel, FillBar, Sizing, Trade};
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum OrderSide {
+    Buy,
+    Sell,
+}
+
+impl OrderSide {
+    fn of(side: Side) -> Self {
+        match side {
+            Side::Long => OrderSide::Buy,
+            Side::Short => OrderSide::Sell,
+        }
+    }
+
+    /// +1 for buys and -1 for sells
+    fn sign(&self) -> f64 {
+        match self {
+            OrderSide::Buy => 1.0,
+            OrderSide::Sell => -1.0,
+        }
+    }
+}
+
+/// Price condition of an order, checked against the OHLC of every bar it is working on
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum OrderType {
+    /// Fills at the open of the next bar
+    Market,
+    /// Fills at the limit or better
+    Limit { limit: f32 },
+    /// Becomes a market order once the price trades through the stop, a gap fills at the open
+    Stop { stop: f32 },
+    /// Becomes a limit order once the price trades through the stop
+    StopLimit { stop: f32, limit: f32 },
+}
+
+/// How long an order keeps working when it does not fill
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum TimeInForce {
+    /// Only on the bar after the one it was sent on
+    #[default]
+    Day,
+    /// Until it fills or is cancelled
+    Gtc,
+    /// On the given number of bars after the one it was sent on
+    Bars(usize),
+}
+
+impl TimeInForce {
+    /// `day`, `gtc` or a number of bars
+    pub fn parse(raw: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        match raw {
+            "day" => Ok(TimeInForce::Day),
+            "gtc" => Ok(TimeInForce::Gtc),
+            bars => match bars.parse() {
+                Ok(0) | Err(_) => Err(format!("Unknown time in force '{}'", raw).into()),
+                Ok(bars) => Ok(TimeInForce::Bars(bars)),
+            },
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Order {
+    pub side: OrderSide,
+    /// Whole shares, fills are cut to what the equity can carry
+    pub shares: f64,
+    #[serde(flatten)]
+    pub order_type: OrderType,
+    pub time_in_force: TimeInForce,
+    /// Why a position closed by this order was exited
+    pub exit_reason: TradeExit,
+}
+
+impl Order {
+    pub fn market(side: OrderSide, shares: f64) -> Self {
+        Order {
+            side,
+            shares: shares.floor(),
+            order_type: OrderType::Market,
+            time_in_force: TimeInForce::default(),
+            exit_reason: TradeExit::Signal,
+        }
+    }
+
+    pub fn limit(side: OrderSide, shares: f64, limit: f32) -> Self {
+        Order { order_type: OrderType::Limit { limit }, ..Order::market(side, shares) }
+    }
+
+    pub fn stop(side: OrderSide, shares: f64, stop: f32) -> Self {
+        Order { order_type: OrderType::Stop { stop }, ..Order::market(side, shares) }
+    }
+
+    pub fn stop_limit(side: OrderSide, shares: f64, stop: f32, limit: f32) -> Self {
+        Order { order_type: OrderType::StopLimit { stop, limit }, ..Order::market(side, shares) }
+    }
+
+    pub fn good_for(mut self, time_in_force: TimeInForce) -> Self {
+        self.time_in_force = time_in_force;
+        self
+    }
+
+    pub fn exiting(mut self, exit_reason: TradeExit) -> Self {
+        self.exit_reason = exit_reason;
+        self
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum OrderStatus {
+    /// Still working when the data ended
+    Pending,
+    Filled,
+    Expired,
+    Cancelled,
+    /// Its price was reached but the equity could not carry a single share
+    Rejected,
+}
+
+/// An order sent by a strategy and what became of it
+#[derive(Serialize, Debug, Clone)]
+pub struct OrderRecord {
+    pub submitted: String,
+    #[serde(flatten)]
+    pub order: Order,
+    pub status: OrderStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fill_datetime: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fill_price: Option<f32>,
+    /// Fewer than the order's shares when the fill was cut to the equity
+    pub filled_shares: f64,
+}
+
+/// One bar handed to a strategy, missing open, high or low fall back to the close
+#[derive(Debug, Clone)]
+pub struct Bar {
+    /// Position of the bar counted chronologically
+    pub index: usize,
+    /// Row of the bar in the input frame
+    pub row: usize,
+    pub datetime: String,
+    pub open: f32,
+    pub high: f32,
+    pub low: f32,
+    pub close: f32,
+    pub volume: Option<f32>,
+}
+
+/// Account state a strategy sees after a bar closes and the orders it sends back
+pub struct Context<'a> {
+    position: f64,
+    equity: f64,
+    sizing: &'a Sizing,
+    atr: Option<f32>,
+    trades: &'a [Trade],
+    orders: Vec<Order>,
+    cancel: bool,
+}
+
+impl Context<'_> {
+    /// Shares held, negative when short
+    pub fn position(&self) -> f64 {
+        self.position
+    }
+
+    /// Shares of a new position at `price` under the engine's sizing
+    pub fn target_shares(&self, price: f32) -> f64 {
+        self.sizing.shares(self.equity, price as f64, self.atr, self.trades)
+    }
+
+    /// Sends an order that starts working on the next bar
+    pub fn submit(&mut self, order: Order) {
+        if order.shares > 0.0 {
+            self.orders.push(order);
+        }
+    }
+
+    /// Cancels the orders still working from earlier bars, orders sent on this bar are kept
+    pub fn cancel_all(&mut self) {
+        self.cancel = true;
+    }
+}
+
+/// A strategy that reacts to bars one at a time by sending orders
+pub trait EventStrategy {
+    fn name(&self) -> String;
+    fn params(&self) -> StrategyParams;
+    /// Called once `bar` has closed and its fills are booked
+    fn on_bar(&mut self, bar: &Bar, ctx: &mut Context);
+}
+
+/// Order type an adapted signal is sent with
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+enum EntryType {
+    #[default]
+    Market,
+    Limit,
+    Stop,
+    StopLimit,
+}
+
+/// Runs the signal column of a vectorized strategy through the engine: a buy or sell signal
+/// replaces the working orders with one that moves the position to what the signal and position
+/// mode allow. Limit orders are placed below the signal bar's close for buys and above it for
+/// sells, stops the other way, by the order offset.
+pub struct SignalAdapter {
+    signal: String,
+    params: StrategyParams,
+    signals: Vec<Option<i32>>,
+    mode: PositionMode,
+    entry: EntryType,
+    offset_pct: f32,
+    /// Distance of a stop-limit's limit beyond its stop
+    limit_offset_pct: f32,
+    time_in_force: TimeInForce,
+}
+
+impl SignalAdapter {
+    pub fn new(df: &DataFrame, sig_col: &str, params: StrategyParams, mode: PositionMode) -> Result<Self, Box<dyn std::error::Error>> {
+        let signals = df.column(sig_col)?.cast(&DataType::Int32)?;
+        Ok(SignalAdapter {
+            signal: sig_col.to_string(),
+            params,
+            signals: signals.i32()?.into_iter().collect(),
+            mode,
+            entry: EntryType::default(),
+            offset_pct: 0.0,
+            limit_offset_pct: 0.0,
+            time_in_force: TimeInForce::default(),
+        })
+    }
+
+    /// Reads `position_mode`, `order_type` (market, limit, stop or stop_limit), `order_offset_pct`,
+    /// `limit_offset_pct` and `time_in_force` from a query string
+    pub fn from_query(
+        df: &DataFrame,
+        sig_col: &str,
+        params: StrategyParams,
+        raw: &HashMap<String, String>
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let mode = match raw.get("position_mode") {
+            Some(mode) => PositionMode::parse(mode)?,
+            None => PositionMode::default(),
+        };
+        let mut adapter = SignalAdapter::new(df, sig_col, params, mode)?;
+        if let Some(entry) = raw.get("order_type") {
+            adapter.entry = match entry.as_str() {
+                "market" => EntryType::Market,
+                "limit" => EntryType::Limit,
+                "stop" => EntryType::Stop,
+                "stop_limit" => EntryType::StopLimit,
+                other => return Err(format!("Unknown order type '{}'", other).into()),
+            };
+        }
+        let parse_pct = |key: &str| -> Result<f32, Box<dyn std::error::Error>> {
+            let value: f32 = match raw.get(key) {
+                Some(value) => value.parse().map_err(|e| format!("Invalid {}: {}", key, e))?,
+                None => 0.0,
+            };
+            if !value.is_finite() || !(0.0..100.0).contains(&value) {
+                return Err(format!("{} must be between 0 and 100", key).into());
+            }
+            Ok(value)
+        };
+        adapter.offset_pct = parse_pct("order_offset_pct")?;
+        adapter.limit_offset_pct = parse_pct("limit_offset_pct")?;
+        if let Some(time_in_force) = raw.get("time_in_force") {
+            adapter.time_in_force = TimeInForce::parse(time_in_force)?;
+        }
+        Ok(adapter)
+    }
+
+    fn order(&self, side: OrderSide, shares: f64, close: f32) -> Order {
+        // Price `pct` percent away from the close, towards worse fills for the order's side
+        let away = |price: f32, pct: f32| price * (1.0 + side.sign() as f32 * pct / 100.0);
+        let order = match self.entry {
+            EntryType::Market => Order::market(side, shares),
+            EntryType::Limit => Order::limit(side, shares, away(close, -self.offset_pct)),
+            EntryType::Stop => Order::stop(side, shares, away(close, self.offset_pct)),
+            EntryType::StopLimit => {
+                let stop = away(close, self.offset_pct);
+                Order::stop_limit(side, shares, stop, away(stop, self.limit_offset_pct))
+            }
+        };
+        // Whatever the order type, an exit or reversal follows the signal
+        order.good_for(self.time_in_force).exiting(TradeExit::Signal)
+    }
+}
+
+impl EventStrategy for SignalAdapter {
+    fn name(&self) -> String {
+        self.signal.clone()
+    }
+
+    fn params(&self) -> StrategyParams {
+        self.params.clone()
+    }
+
+    fn on_bar(&mut self, bar: &Bar, ctx: &mut Context) {
+        let side = match self.signals[bar.row] {
+            Some(BUY_SIGNAL) => Side::Long,
+            Some(SELL_SIGNAL) => Side::Short,
+            _ => return,
+        };
+        if ctx.position() * side.sign() > 0.0 {
+            return;
+        }
+        let target = match self.mode.allows(side) {
+            true => side.sign() * ctx.target_shares(bar.close),
+            false => 0.0,
+        };
+        let delta = target - ctx.position();
+        ctx.cancel_all();
+        ctx.submit(self.order(OrderSide::of(side), delta.abs(), bar.close));
+    }
+}
+
+/// Long breakouts of a Donchian channel: a buy stop rests at the highest high of `window` bars
+/// and, once long, a sell stop at the lowest low of `exit_window` bars, both renewed every bar
+pub struct DonchianBreakout {
+    window: usize,
+    exit_window: usize,
+    highs: VecDeque<f32>,
+    lows: VecDeque<f32>,
+}
+
+impl DonchianBreakout {
+    pub fn new(window: usize, exit_window: usize) -> Result<Self, Box<dyn std::error::Error>> {
+        if window == 0 || exit_window == 0 {
+            return Err("window and exit_window must be positive".into());
+        }
+        Ok(DonchianBreakout { window, exit_window, highs: VecDeque::new(), lows: VecDeque::new() })
+    }
+
+    /// Reads `window` and `exit_window`, 20 and 10 bars by default
+    pub fn from_query(raw: &HashMap<String, String>) -> Result<Self, Box<dyn std::error::Error>> {
+        let parse = |key: &str, default: usize| -> Result<usize, Box<dyn std::error::Error>> {
+            match raw.get(key) {
+                Some(value) => Ok(value.parse().map_err(|e| format!("Invalid {}: {}", key, e))?),
+                None => Ok(default),
+            }
+        };
+        DonchianBreakout::new(parse("window", 20)?, parse("exit_window", 10)?)
+    }
+}
+
+impl EventStrategy for DonchianBreakout {
+    fn name(&self) -> String {
+        format!("Donchian_{}_{}", self.window, self.exit_window)
+    }
+
+    fn params(&self) -> StrategyParams {
+        let mut params = StrategyParams::default();
+        params.set("window", self.window as f64);
+        params.set("exit_window", self.exit_window as f64);
+        params
+    }
+
+    fn on_bar(&mut self, bar: &Bar, ctx: &mut Context) {
+        self.highs.push_front(bar.high);
+        self.lows.push_front(bar.low);
+        self.highs.truncate(self.window);
+        self.lows.truncate(self.exit_window);
+        if self.highs.len() < self.window {
+            return;
+        }
+        ctx.cancel_all();
+        if ctx.position() > 0.0 {
+            let exit = self.lows.iter().copied().fold(f32::INFINITY, f32::min);
+            ctx.submit(Order::stop(OrderSide::Sell, ctx.position(), exit).exiting(TradeExit::TrailingStop));
+        } else {
+            let entry = self.highs.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+            ctx.submit(Order::stop(OrderSide::Buy, ctx.target_shares(entry), entry));
+        }
+    }
+}
+
+struct WorkingOrder {
+    record: usize,
+    order: Order,
+    /// Last chronological bar the order works on
+    expires: usize,
+    /// A stop-limit whose stop was reached and now works as a limit
+    triggered: bool,
+}
+
+impl WorkingOrder {
+    /// Price the order fills at on `bar`, if it does
+    fn fill_price(&mut self, bar: &Bar) -> Option<f32> {
+        let buy = self.order.side == OrderSide::Buy;
+        // The open when it is already through the level, else the level when the range reaches it
+        let through_stop = |stop: f32| match buy {
+            true if bar.open >= stop => Some(bar.open),
+            true => (bar.high >= stop).then_some(stop),
+            false if bar.open <= stop => Some(bar.open),
+            false => (bar.low <= stop).then_some(stop),
+        };
+        let at_limit = |limit: f32| match buy {
+            true if bar.open <= limit => Some(bar.open),
+            true => (bar.low <= limit).then_some(limit),
+            false if bar.open >= limit => Some(bar.open),
+            false => (bar.high >= limit).then_some(limit),
+        };
+        match self.order.order_type {
+            OrderType::Market => Some(bar.open),
+            OrderType::Limit { limit } => at_limit(limit),
+            OrderType::Stop { stop } => through_stop(stop),
+            OrderType::StopLimit { limit, .. } if self.triggered => at_limit(limit),
+            OrderType::StopLimit { stop, limit } => {
+                let trigger = through_stop(stop)?;
+                self.triggered = true;
+                // A trigger beyond the limit leaves the limit working from the next bar,
+                // the order of prices within the bar is unknown
+                let within = if buy { trigger <= limit } else { trigger >= limit };
+                within.then_some(trigger)
+            }
+        }
+    }
+}
+
+/// Cash, the open position and the trades it closed
+struct Account {
+    cash: f64,
+    holding: Option<OpenTrade>,
+    trades: Vec<Trade>,
+}
+
+impl Account {
+    /// Shares held, negative when short
+    fn position(&self) -> f64 {
+        self.holding.as_ref().map_or(0.0, |open| open.side.sign() * open.shares)
+    }
+}
+
+pub struct EventRun {
+    pub run: BacktestRun,
+    pub orders: Vec<OrderRecord>,
+}
+
+/// Walks the bars one at a time: orders sent after a bar closes work from the next bar on,
+/// in the order they were sent, and fill from the bar's OHLC. Market and stop fills pay the
+/// slippage of the cost model, limit and stop-limit fills are at their price. A fill first closes an opposite
+/// position and then opens or adds as far as the equity allows.
+#[derive(Debug, Clone)]
+pub struct EventBacktest {
+    pub initial_capital: f64,
+    pub sizing: Sizing,
+    pub costs: CostModel,
+}
+
+impl Default for EventBacktest {
+    fn default() -> Self {
+        EventBacktest { initial_capital: DEFAULT_CAPITAL, sizing: Sizing::default(), costs: CostModel::default() }
+    }
+}
+
+impl EventBacktest {
+    /// Reads `capital`, the sizing and the cost model from a query string
+    pub fn from_query(raw: &HashMap<String, String>) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut engine = EventBacktest::default();
+        if let Some(capital) = raw.get("capital") {
+            engine.initial_capital = capital.parse().map_err(|e| format!("Invalid capital: {}", e))?;
+        }
+        if !engine.initial_capital.is_finite() || engine.initial_capital <= 0.0 {
+            return Err("capital must be positive".into());
+        }
+        engine.sizing = Sizing::from_query(raw)?;
+        engine.costs = CostModel::from_query(raw)?;
+        Ok(engine)
+    }
+
+    pub fn run(&self, df: &DataFrame, strategy: &mut dyn EventStrategy) -> Result<EventRun, Box<dyn std::error::Error>> {
+        let datetime = df.column("datetime")?.str()?;
+        let open_price = indicators::column_f32(df, "open")?;
+        let close = indicators::column_f32(df, "close")?;
+        let high = indicators::column_f32(df, "high")?;
+        let low = indicators::column_f32(df, "low")?;
+        let volume = match df.column("volume") {
+            Ok(_) => indicators::column_f32(df, "volume")?,
+            Err(_) => vec![None; df.height()],
+        };
+        let atr = match self.sizing.atr_window() {
+            Some(window) => indicators::atr(df, window)?,
+            None => vec![None; df.height()],
+        };
+
+        let mut account = Account { cash: self.initial_capital, holding: None, trades: Vec::new() };
+        let mut records: Vec<OrderRecord> = Vec::new();
+        let mut working: Vec<WorkingOrder> = Vec::new();
+        let mut last_bar: Option<Bar> = None;
+        let mut cash_curve = vec![None; df.height()];
+        let mut shares_curve = vec![None; df.height()];
+        let mut equity_curve = vec![None; df.height()];
+
+        for (t, row) in indicators::chronological_rows(df)?.into_iter().enumerate() {
+            if let Some(price) = close[row] {
+                let bar = Bar {
+                    index: t,
+                    row,
+                    datetime: datetime.get(row).unwrap_or_default().to_string(),
+                    open: open_price[row].unwrap_or(price),
+                    high: high[row].unwrap_or(price),
+                    low: low[row].unwrap_or(price),
+                    close: price,
+                    volume: volume[row],
+                };
+                if let Some(open) = account.holding.as_mut().filter(|open| open.side == Side::Short) {
+                    let fee = self.costs.borrow_fee(open.shares, price as f64);
+                    account.cash -= fee;
+                    open.borrow_cost += fee;
+                }
+
+                let mut still_working = Vec::new();
+                for mut order in working.drain(..) {
+                    let Some(fill) = order.fill_price(&bar) else {
+                        still_working.push(order);
+                        continue;
+                    };
+                    let filled = self.fill(&mut account, &order.order, fill, &bar)?;
+                    let record = &mut records[order.record];
+                    record.status = if filled > 0.0 { OrderStatus::Filled } else { OrderStatus::Rejected };
+                    record.fill_datetime = Some(bar.datetime.clone());
+                    record.fill_price = Some(fill);
+                    record.filled_shares = filled;
+                }
+                for order in still_working.iter().filter(|order| order.expires <= t) {
+                    records[order.record].status = OrderStatus::Expired;
+                }
+                still_working.retain(|order| order.expires > t);
+                working = still_working;
+                // The bar a position is entered on only counts from its fill price
+                if let Some(open) = account.holding.as_mut().filter(|open| open.entry_bar < t) {
+                    open.track(Some(bar.low), Some(bar.high));
+                }
+
+                let position = account.position();
+                let equity = account.cash + position * price as f64;
+                let mut ctx = Context {
+                    position,
+                    equity,
+                    sizing: &self.sizing,
+                    atr: atr[row],
+                    trades: &account.trades,
+                    orders: Vec::new(),
+                    cancel: false,
+                };
+                strategy.on_bar(&bar, &mut ctx);
+                let (orders, cancel) = (ctx.orders, ctx.cancel);
+                if cancel {
+                    for order in working.drain(..) {
+                        records[order.record].status = OrderStatus::Cancelled;
+                    }
+                }
+                for order in orders {
+                    let expires = match order.time_in_force {
+                        TimeInForce::Day => t + 1,
+                        TimeInForce::Gtc => usize::MAX,
+                        TimeInForce::Bars(bars) => t + bars.max(1),
+                    };
+                    records.push(OrderRecord {
+                        submitted: bar.datetime.clone(),
+                        order: order.clone(),
+                        status: OrderStatus::Pending,
+                        fill_datetime: None,
+                        fill_price: None,
+                        filled_shares: 0.0,
+                    });
+                    working.push(WorkingOrder { record: records.len() - 1, order, expires, triggered: false });
+                }
+                last_bar = Some(bar);
+            }
+            let shares = account.position();
+            let mark = last_bar.as_ref().map_or(0.0, |bar| bar.close as f64);
+            cash_curve[row] = Some(account.cash);
+            shares_curve[row] = Some(shares);
+            equity_curve[row] = Some(account.cash + shares * mark);
+        }
+        // A position still open at the end of the data is closed at the last close
+        if let (Some(open), Some(bar)) = (account.holding.as_ref(), last_bar) {
+            let side = if open.side == Side::Long { OrderSide::Sell } else { OrderSide::Buy };
+            let order = Order::market(side, open.shares);
+            self.close_at(&mut account, &order, bar.close, &bar, TradeExit::EndOfData)?;
+        }
+
+        let equity_curve = DataFrame::new(vec![
+            df.column("datetime")?.clone(),
+            Series::new("cash".into(), cash_curve).into(),
+            Series::new("shares".into(), shares_curve).into(),
+            Series::new("equity".into(), equity_curve).into(),
+        ])?;
+        let cash = account.cash;
+        Ok(EventRun {
+            run: BacktestRun {
+                signal: strategy.name(),
+                initial_capital: self.initial_capital,
+                final_equity: cash,
+                total_return_pct: (100.0 * (cash / self.initial_capital - 1.0)) as f32,
+                equity_curve,
+                trades: account.trades,
+                sizing: self.sizing.clone(),
+                regimes: None,
+            },
+            orders: records,
+        })
+    }
+
+    /// Fills `order` at `price`, returns the shares filled
+    fn fill(&self, account: &mut Account, order: &Order, price: f32, bar: &Bar) -> Result<f64, Box<dyn std::error::Error>> {
+        let closed = self.close_at(account, order, price, bar, order.exit_reason)?;
+        let remaining = order.shares - closed;
+        if remaining <= 0.0 {
+            return Ok(closed);
+        }
+
+        let fill = self.fill_with_slippage(order, price, bar, remaining)?;
+        let held = account.holding.as_ref().map_or(0.0, |open| open.shares);
+        let equity = account.cash + account.position() * fill;
+        let shares = remaining.min(self.costs.affordable_shares(equity, fill) - held).floor();
+        if shares <= 0.0 {
+            return Ok(closed);
+        }
+        let commission = self.costs.commission.fee(shares, fill);
+        let slippage = (fill - price as f64).abs() * shares;
+        account.cash -= order.side.sign() * shares * fill + commission;
+        match account.holding.as_mut() {
+            Some(open) => open.add(shares, fill as f32, commission, slippage),
+            None => account.holding = Some(OpenTrade {
+                side: if order.side == OrderSide::Buy { Side::Long } else { Side::Short },
+                entry_datetime: bar.datetime.clone(),
+                entry_price: fill as f32,
+                shares,
+                commission,
+                slippage,
+                borrow_cost: 0.0,
+                regime: None,
+                entry_bar: bar.index,
+                lowest: fill as f32,
+                highest: fill as f32,
+            }),
+        }
+        Ok(closed + shares)
+    }
+
+    /// Closes as much of an opposite position as the order covers, returns the shares closed
+    fn close_at(
+        &self,
+        account: &mut Account,
+        order: &Order,
+        price: f32,
+        bar: &Bar,
+        reason: TradeExit
+    ) -> Result<f64, Box<dyn std::error::Error>> {
+        let Some(open) = account.holding.as_mut().filter(|open| open.side.sign() != order.side.sign()) else {
+            return Ok(0.0);
+        };
+        let shares = order.shares.min(open.shares);
+        let fill = self.fill_with_slippage(order, price, bar, shares)?;
+        let commission = self.costs.commission.fee(shares, fill);
+        account.cash -= order.side.sign() * shares * fill + commission;
+        let part = open.split_off(shares);
+        if open.shares <= 0.0 {
+            account.holding = None;
+        }
+        let slippage = (fill - price as f64).abs() * shares;
+        account.trades.push(part.close(bar.datetime.clone(), fill as f32, bar.index, reason, commission, slippage));
+        Ok(shares)
+    }
+
+    /// Limit and stop-limit orders fill at their price, others move against the order by the slippage model
+    fn fill_with_slippage(&self, order: &Order, price: f32, bar: &Bar, shares: f64) -> Result<f64, Box<dyn std::error::Error>> {
+        let slippage = match order.order_type {
+            OrderType::Limit { .. } | OrderType::StopLimit { .. } => 0.0,
+            OrderType::Market | OrderType::Stop { .. } => {
+                let fill_bar = FillBar { high: Some(bar.high), low: Some(bar.low), volume: bar.volume };
+                self.costs.slippage.per_share(price as f64, &fill_bar, shares)?
+            }
+        };
+        Ok((price as f64 + order.side.sign() * slippage).max(0.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_fixtures::{self, with_prices};
+    use super::*;
+
+    /// Sends each order after the close of its chronological bar
+    struct Script {
+        orders: Vec<(usize, Order)>,
+    }
+
+    impl EventStrategy for Script {
+        fn name(&self) -> String {
+            "script".to_string()
+        }
+
+        fn params(&self) -> StrategyParams {
+            StrategyParams::default()
+        }
+
+        fn on_bar(&mut self, bar: &Bar, ctx: &mut Context) {
+            for (_, order) in self.orders.iter().filter(|(index, _)| *index == bar.index) {
+                ctx.submit(order.clone());
+            }
+        }
+    }
+
+    /// Bars opening and closing at 100, reaching up to 101 and down to the lows given oldest first
+    fn bars(lows: &[f32]) -> DataFrame {
+        with_prices(test_fixtures::bars(&vec![100.0; lows.len()]), "low", lows)
+    }
+
+    fn run(capital: f64, lows: &[f32], orders: Vec<(usize, Order)>) -> EventRun {
+        let engine = EventBacktest { initial_capital: capital, ..EventBacktest::default() };
+        engine.run(&bars(lows), &mut Script { orders }).unwrap()
+    }
+
+    #[test]
+    fn fills_are_cut_to_the_equity() {
+        let events = run(1_000.0, &[99.0; 3], vec![(0, Order::market(OrderSide::Buy, 50.0))]);
+        let record = &events.orders[0];
+        assert_eq!(record.status, OrderStatus::Filled);
+        assert_eq!(record.filled_shares, 10.0);
+        assert_eq!(record.fill_price, Some(100.0));
+        assert_eq!(events.run.trades[0].shares, 10.0);
+    }
+
+    #[test]
+    fn orders_expire_with_their_time_in_force() {
+        // The limit is only reached on the fourth bar
+        let lows = [99.0, 99.0, 99.0, 85.0];
+        let limit = |time_in_force| vec![(0, Order::limit(OrderSide::Buy, 5.0, 90.0).good_for(time_in_force))];
+        for time_in_force in [TimeInForce::Day, TimeInForce::Bars(2)] {
+            let events = run(10_000.0, &lows, limit(time_in_force));
+            assert_eq!(events.orders[0].status, OrderStatus::Expired);
+            assert!(events.run.trades.is_empty());
+        }
+        let events = run(10_000.0, &lows, limit(TimeInForce::Bars(3)));
+        assert_eq!(events.orders[0].status, OrderStatus::Filled);
+        assert_eq!(events.orders[0].fill_price, Some(90.0));
+        let events = run(10_000.0, &lows, limit(TimeInForce::Gtc));
+        assert_eq!(events.orders[0].status, OrderStatus::Filled);
+    }
+
+    #[test]
+    fn shorts_are_limited_to_the_equity() {
+        let orders = vec![(0, Order::market(OrderSide::Sell, 50.0)), (1, Order::market(OrderSide::Sell, 5.0))];
+        let events = run(1_000.0, &[99.0; 4], orders);
+        assert_eq!(events.orders[0].status, OrderStatus::Filled);
+        assert_eq!(events.orders[0].filled_shares, 10.0);
+        // Adding to a short the equity already carries is rejected
+        assert_eq!(events.orders[1].status, OrderStatus::Rejected);
+        let trade = &events.run.trades[0];
+        assert_eq!((trade.side, trade.shares, trade.exit_reason), (Side::Short, 10.0, TradeExit::EndOfData));
+    }
+}
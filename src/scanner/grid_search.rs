@@ -0,0 +1,255 @@
+use std::{collections::HashMap, sync::OnceLock};
+use log::debug;
+use polars::prelude::*;
+use rayon::{prelude::*, ThreadPool, ThreadPoolBuilder};
+use serde::{Deserialize, Serialize};
+
+use crate::strategy::{ParamConstraint, ParamType, StrategyParams, StrategySpec};
+use super::progress::{cancelled, cancelled_error};
+use super::{Backtest, PerformanceMetrics, Progress};
+
+const MAX_COMBINATIONS: usize = 10_000;
+/// Polars plans recurse deeply, more than the default stack of rayon's threads holds in debug builds
+const WORKER_STACK_BYTES: usize = 16 * 1024 * 1024;
+
+/// Values of one parameter from `from` to `to`, both included, every `step`
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct ParamRange {
+    pub name: String,
+    pub from: f64,
+    pub to: f64,
+    pub step: f64,
+}
+
+impl ParamRange {
+    pub(super) fn values(&self) -> Vec<f64> {
+        let count = ((self.to - self.from) / self.step + 1e-9).floor() as usize + 1;
+        (0..count).map(|i| self.from + i as f64 * self.step).collect()
+    }
+}
+
+/// Parameter space of one strategy, searched as every combination of its ranges
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct ParamGrid {
+    pub strategy: String,
+    pub ranges: Vec<ParamRange>,
+    /// Combinations breaking one of these are never backtested
+    pub constraints: Vec<ParamConstraint>,
+}
+
+impl ParamGrid {
+    /// Every parameter of the spec over its whole range with its own step
+    pub fn full(spec: &StrategySpec) -> Self {
+        ParamGrid {
+            strategy: spec.name.clone(),
+            ranges: spec.params.iter()
+                .map(|p| ParamRange { name: p.name.clone(), from: p.min, to: p.max, step: p.step })
+                .collect(),
+            constraints: spec.constraints.clone(),
+        }
+    }
+
+    /// Narrows the full grid with `{param}_from`, `{param}_to` and `{param}_step`, a plain `{param}`
+    /// fixes the parameter to one value. Bounds are checked against the spec.
+    pub fn from_query(spec: &StrategySpec, raw: &HashMap<String, String>) -> Result<Self, Box<dyn std::error::Error>> {
+        let grid = ParamGrid::space_from_query(spec, raw)?;
+        let combinations = grid.size();
+        if combinations > MAX_COMBINATIONS {
+            return Err(format!("The grid has {} combinations, at most {} are allowed", combinations, MAX_COMBINATIONS).into());
+        }
+        Ok(grid)
+    }
+
+    /// Same ranges as `from_query` without a limit on the combinations, for optimizers that only
+    /// backtest a sample of them
+    pub fn space_from_query(spec: &StrategySpec, raw: &HashMap<String, String>) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut grid = ParamGrid::full(spec);
+        for (param, range) in spec.params.iter().zip(grid.ranges.iter_mut()) {
+            if let Some(value) = raw.get(&param.name) {
+                let value = param.parse(value)?;
+                (range.from, range.to) = (value, value);
+                continue;
+            }
+            if let Some(from) = raw.get(&format!("{}_from", param.name)) {
+                range.from = param.parse(from)?;
+            }
+            if let Some(to) = raw.get(&format!("{}_to", param.name)) {
+                range.to = param.parse(to)?;
+            }
+            if let Some(step) = raw.get(&format!("{}_step", param.name)) {
+                range.step = step.parse().map_err(|e| format!("Invalid {}_step: {}", param.name, e))?;
+            }
+            if range.from > range.to {
+                return Err(format!("{}_from must not be above {}_to", param.name, param.name).into());
+            }
+            if !range.step.is_finite() || range.step <= 0.0 || (param.param_type == ParamType::Int && range.step.fract() != 0.0) {
+                return Err(format!("{}_step must be a positive {}", param.name, match param.param_type {
+                    ParamType::Int => "integer",
+                    ParamType::Float => "number",
+                }).into());
+            }
+        }
+        Ok(grid)
+    }
+
+    pub fn size(&self) -> usize {
+        self.ranges.iter().fold(1, |size, range| size.saturating_mul(range.values().len()))
+    }
+
+    /// Combinations of the ranges, without the ones breaking a constraint, the last parameter
+    /// changing fastest
+    pub fn combinations(&self) -> Vec<StrategyParams> {
+        let mut combinations = self.ranges.iter().fold(vec![StrategyParams::default()], |combinations, range| {
+            combinations.iter()
+                .flat_map(|params| range.values().into_iter().map(move |value| {
+                    let mut params = params.clone();
+                    params.set(&range.name, value);
+                    params
+                }))
+                .collect()
+        });
+        combinations.retain(|params| self.constraints.iter().all(|c| c.allows(params)));
+        combinations
+    }
+}
+
+/// One combination of the grid and how its backtest scored
+#[derive(Serialize, Debug, Clone)]
+pub struct GridResult {
+    /// 1 for the best score of the chosen metric
+    pub rank: usize,
+    pub params: StrategyParams,
+    pub score: f32,
+    pub metrics: PerformanceMetrics,
+}
+
+/// Backtests every combination of a parameter grid and ranks them by the backtest's metric
+#[derive(Debug, Clone)]
+pub struct GridSearch {
+    pub grid: ParamGrid,
+    pub backtest: Backtest,
+}
+
+impl GridSearch {
+    pub fn new(grid: ParamGrid, backtest: Backtest) -> Self {
+        GridSearch { grid, backtest }
+    }
+
+    /// Results of every combination that could be built and backtested, best first.
+    /// Combinations the strategy rejects, such as a short MA above the long one, are skipped.
+    /// Backtests are counted on the thread's current progress, which can also cancel the search.
+    pub fn run(&self, df: &DataFrame, spec: &StrategySpec) -> Result<Vec<GridResult>, Box<dyn std::error::Error>> {
+        let combinations = self.grid.combinations();
+        let progress = Progress::current();
+        if let Some(progress) = &progress {
+            progress.claim(combinations.len());
+        }
+        let mut results: Vec<GridResult> = worker_pool()?.install(|| combinations
+            .into_par_iter()
+            .filter_map(|params| {
+                if cancelled(progress.as_ref()) {
+                    return None;
+                }
+                let outcome = self.evaluate(df, spec, &params);
+                if let Some(progress) = &progress {
+                    progress.advance();
+                }
+                match outcome {
+                    Ok((score, metrics)) => Some(GridResult { rank: 0, params, score, metrics }),
+                    Err(e) => {
+                        debug!("Skipping {} with {:?}: {}", spec.name, params, e);
+                        None
+                    }
+                }
+            })
+            .collect());
+        if cancelled(progress.as_ref()) {
+            return Err(cancelled_error());
+        }
+        rank(&mut results, spec)?;
+        Ok(results)
+    }
+
+    /// Best combination and its score
+    pub fn best(&self, df: &DataFrame, spec: &StrategySpec) -> Result<(StrategyParams, f32), Box<dyn std::error::Error>> {
+        let best = self.run(df, spec)?.swap_remove(0);
+        Ok((best.params, best.score))
+    }
+
+    pub(super) fn evaluate(&self, df: &DataFrame, spec: &StrategySpec, params: &StrategyParams) -> Result<(f32, PerformanceMetrics), Box<dyn std::error::Error>> {
+        let mut strategy = spec.build(df.clone(), params)?;
+        let signals = strategy.calc_signal()?;
+        let run = self.backtest.run(&signals, &strategy.signal_col())?;
+        let metrics = PerformanceMetrics::from_run(&run)?;
+        Ok((self.backtest.metric.score(&metrics), metrics))
+    }
+}
+
+static WORKER_POOL: OnceLock<ThreadPool> = OnceLock::new();
+
+/// Pool shared by every search, built on first use
+pub(super) fn worker_pool() -> Result<&'static ThreadPool, Box<dyn std::error::Error>> {
+    if let Some(pool) = WORKER_POOL.get() {
+        return Ok(pool);
+    }
+    let pool = ThreadPoolBuilder::new().stack_size(WORKER_STACK_BYTES).build()?;
+    Ok(WORKER_POOL.get_or_init(|| pool))
+}
+
+/// Sorts results best first and numbers them, NaN scores rank last
+pub(super) fn rank(results: &mut [GridResult], spec: &StrategySpec) -> Result<(), Box<dyn std::error::Error>> {
+    if results.is_empty() {
+        return Err(format!("No parameter combination of {} could be backtested", spec.name).into());
+    }
+    results.sort_by(|a, b| a.score.is_nan().cmp(&b.score.is_nan()).then(b.score.total_cmp(&a.score)));
+    for (i, result) in results.iter_mut().enumerate() {
+        result.rank = i + 1;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::strategy::StrategyRegistry;
+    use crate::test_fixtures::query;
+    use super::*;
+
+    fn result(score: f32) -> GridResult {
+        GridResult { rank: 0, params: StrategyParams::default(), score, metrics: PerformanceMetrics::default() }
+    }
+
+    #[test]
+    fn nan_scores_rank_last() {
+        let spec = StrategyRegistry::global().get("sma").unwrap();
+        let mut results: Vec<GridResult> = [1.0, f32::NAN, 3.0, -f32::NAN, -2.0].into_iter().map(result).collect();
+        rank(&mut results, spec).unwrap();
+        let scores: Vec<f32> = results.iter().take(3).map(|r| r.score).collect();
+        assert_eq!(scores, vec![3.0, 1.0, -2.0]);
+        assert!(results[3..].iter().all(|r| r.score.is_nan()));
+        assert_eq!(results.iter().map(|r| r.rank).collect::<Vec<usize>>(), vec![1, 2, 3, 4, 5]);
+        assert!(rank(&mut [], spec).is_err());
+    }
+
+    #[test]
+    fn combinations_skip_broken_constraints() {
+        let spec = StrategyRegistry::global().get("sma").unwrap();
+        let grid = ParamGrid::from_query(spec, &query(&[("short_ma_to", "30"), ("long_ma_to", "30")])).unwrap();
+        assert_eq!(grid.size(), 9);
+        let pairs: Vec<(f64, f64)> = grid.combinations().iter()
+            .map(|params| (params.get("short_ma").unwrap(), params.get("long_ma").unwrap()))
+            .collect();
+        assert_eq!(pairs, vec![(10.0, 20.0), (10.0, 30.0), (20.0, 30.0)]);
+    }
+
+    #[test]
+    fn grid_defaults_to_the_whole_spec_range() {
+        let spec = StrategyRegistry::global().get("sma").unwrap();
+        let grid = ParamGrid::from_query(spec, &HashMap::new()).unwrap();
+        for range in &grid.ranges {
+            let values = range.values();
+            assert_eq!((values.len(), values[0], values[values.len() - 1]), (20, 10.0, 200.0));
+        }
+        let grid = ParamGrid::from_query(spec, &query(&[("long_ma_step", "95")])).unwrap();
+        assert_eq!(grid.ranges[1].values(), vec![10.0, 105.0, 200.0]);
+    }
+}
@@ -0,0 +1,247 @@
+use std::collections::HashMap;
+use log::debug;
+use polars::prelude::*;
+use serde::Serialize;
+
+use crate::strategy::{indicators, StrategyParams, StrategySpec};
+use super::progress::{cancelled, cancelled_error};
+use super::screener::{latest_row, Trigger};
+use super::{Backtest, ParamGrid, PerformanceMetrics, Progress, SymbolError, TrainTestSplit};
+
+pub const LEADERBOARD_TABLE: &str = "leaderboard";
+pub const DEFAULT_TEST_RATIO: f32 = 0.3;
+
+/// One symbol of a leaderboard, ranked by its out-of-sample score
+#[derive(Serialize, Debug, Clone)]
+pub struct LeaderboardEntry {
+    pub rank: usize,
+    /// Rank in the last run of an earlier day with the same strategy and metric
+    pub previous_rank: Option<usize>,
+    pub symbol: String,
+    /// Best parameters on the in-sample bars
+    pub params: StrategyParams,
+    pub in_sample_score: f32,
+    pub out_of_sample_score: f32,
+    pub out_of_sample: PerformanceMetrics,
+    /// Trigger of the best parameters on the latest bar
+    pub signal: Trigger,
+    pub signal_datetime: String,
+}
+
+/// One stored leaderboard row
+#[derive(Serialize, Debug, Clone)]
+pub struct LeaderboardRecord {
+    pub run_at: String,
+    pub strategy: String,
+    pub metric: String,
+    pub symbol: String,
+    pub rank: usize,
+    pub params: StrategyParams,
+    pub in_sample_score: Option<f32>,
+    pub out_of_sample_score: Option<f32>,
+    pub out_of_sample: serde_json::Value,
+    pub signal: String,
+    pub signal_datetime: String,
+}
+
+impl LeaderboardRecord {
+    /// Rows of the leaderboard table, oldest run first
+    pub fn from_df(df: &DataFrame) -> Result<Vec<Self>, Box<dyn std::error::Error>> {
+        let text = |name: &str| -> Result<Vec<String>, Box<dyn std::error::Error>> {
+            Ok(df.column(name)?.str()?.into_iter().map(|v| v.unwrap_or_default().to_string()).collect())
+        };
+        let (run_at, strategy, metric, symbol) = (text("run_at")?, text("strategy")?, text("metric")?, text("symbol")?);
+        let (params, out_of_sample, signal, signal_datetime) = (text("params")?, text("out_of_sample")?, text("signal")?, text("signal_datetime")?);
+        let rank = df.column("rank")?.cast(&DataType::Int32)?;
+        let in_sample_score = indicators::column_f32(df, "in_sample_score")?;
+        let out_of_sample_score = indicators::column_f32(df, "out_of_sample_score")?;
+        let mut records = (0..df.height())
+            .map(|i| Ok(LeaderboardRecord {
+                run_at: run_at[i].clone(),
+                strategy: strategy[i].clone(),
+                metric: metric[i].clone(),
+                symbol: symbol[i].clone(),
+                rank: rank.i32()?.get(i).unwrap_or_default() as usize,
+                params: serde_json::from_str(&params[i])?,
+                in_sample_score: in_sample_score[i],
+                out_of_sample_score: out_of_sample_score[i],
+                out_of_sample: serde_json::from_str(&out_of_sample[i])?,
+                signal: signal[i].clone(),
+                signal_datetime: signal_datetime[i].clone(),
+            }))
+            .collect::<Result<Vec<_>, Box<dyn std::error::Error>>>()?;
+        records.sort_by(|a, b| a.run_at.cmp(&b.run_at).then(a.rank.cmp(&b.rank)));
+        Ok(records)
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct LeaderboardRun {
+    pub entries: Vec<LeaderboardEntry>,
+    pub errors: Vec<SymbolError>,
+}
+
+/// Ranks a universe of symbols by how the best parameters of each of them did out of sample
+#[derive(Serialize, Debug, Clone)]
+pub struct Leaderboard {
+    pub split: TrainTestSplit,
+    #[serde(skip)]
+    pub backtest: Backtest,
+}
+
+impl Leaderboard {
+    pub fn new(split: TrainTestSplit, backtest: Backtest) -> Self {
+        Leaderboard { split, backtest }
+    }
+
+    /// Searches `grid` on the in-sample bars of every symbol one after the other, each search runs
+    /// in parallel. Symbols that fail are listed as errors. A cancelled progress stops the run
+    /// before the next symbol.
+    pub fn run(&self, prices: Vec<(String, DataFrame)>, spec: &StrategySpec, grid: &ParamGrid) -> Result<LeaderboardRun, Box<dyn std::error::Error>> {
+        let progress = Progress::current();
+        if let Some(progress) = &progress {
+            progress.reserve(prices.len() * grid.combinations().len());
+        }
+        let mut entries = Vec::new();
+        let mut errors = Vec::new();
+        for (symbol, df) in prices {
+            if cancelled(progress.as_ref()) {
+                return Err(cancelled_error());
+            }
+            match self.entry(&symbol, &df, spec, grid) {
+                Ok(entry) => entries.push(entry),
+                Err(e) => {
+                    debug!("Leaving {} out of the leaderboard: {}", symbol, e);
+                    errors.push(SymbolError { symbol, error: e.to_string() });
+                }
+            }
+        }
+        entries.sort_by(|a, b| b.out_of_sample_score.total_cmp(&a.out_of_sample_score).then_with(|| a.symbol.cmp(&b.symbol)));
+        for (i, entry) in entries.iter_mut().enumerate() {
+            entry.rank = i + 1;
+        }
+        Ok(LeaderboardRun { entries, errors })
+    }
+
+    fn entry(&self, symbol: &str, df: &DataFrame, spec: &StrategySpec, grid: &ParamGrid) -> Result<LeaderboardEntry, Box<dyn std::error::Error>> {
+        if df.height() == 0 {
+            return Err("No prices".into());
+        }
+        let split = self.split.run(df, spec, &self.backtest, grid)?;
+        let mut strategy = spec.build(df.clone(), &split.params)?;
+        let latest = latest_row(symbol, &strategy.calc_signal()?, &strategy.signal_col())?;
+        Ok(LeaderboardEntry {
+            rank: 0,
+            previous_rank: None,
+            symbol: symbol.to_string(),
+            in_sample_score: self.backtest.metric.score(&split.in_sample),
+            out_of_sample_score: self.backtest.metric.score(&split.out_of_sample),
+            params: split.params,
+            out_of_sample: split.out_of_sample,
+            signal: latest.trigger,
+            signal_datetime: latest.datetime,
+        })
+    }
+}
+
+impl LeaderboardRun {
+    /// Rows of the leaderboard table, parameters and metrics are stored as JSON
+    pub fn to_df(&self, run_at: &str, strategy: &str, metric: &str) -> Result<DataFrame, Box<dyn std::error::Error>> {
+        let rows = self.entries.len();
+        let df = df!(
+            "run_at" => vec![run_at; rows],
+            "strategy" => vec![strategy; rows],
+            "metric" => vec![metric; rows],
+            "symbol" => self.entries.iter().map(|e| e.symbol.as_str()).collect::<Vec<_>>(),
+            "rank" => self.entries.iter().map(|e| e.rank as i32).collect::<Vec<_>>(),
+            "params" => self.entries.iter().map(|e| to_json(&e.params)).collect::<Vec<_>>(),
+            "in_sample_score" => self.entries.iter().map(|e| e.in_sample_score).collect::<Vec<_>>(),
+            "out_of_sample_score" => self.entries.iter().map(|e| e.out_of_sample_score).collect::<Vec<_>>(),
+            "out_of_sample" => self.entries.iter().map(|e| to_json(&e.out_of_sample)).collect::<Vec<_>>(),
+            "signal" => self.entries.iter().map(|e| e.signal.as_str()).collect::<Vec<_>>(),
+            "signal_datetime" => self.entries.iter().map(|e| e.signal_datetime.as_str()).collect::<Vec<_>>(),
+        )?;
+        Ok(df)
+    }
+
+    /// Fills `previous_rank` from the last stored run of an earlier day than `run_at`
+    pub fn compare_with(&mut self, history: &[LeaderboardRecord], run_at: &str, strategy: &str, metric: &str) {
+        let day = &run_at[..run_at.len().min(10)];
+        let earlier = history.iter()
+            .filter(|r| r.strategy == strategy && r.metric == metric && r.run_at.get(..10).is_some_and(|d| d < day));
+        let Some(last_run) = earlier.clone().map(|r| r.run_at.as_str()).max() else {
+            return;
+        };
+        let ranks: HashMap<&str, usize> = earlier
+            .filter(|r| r.run_at == last_run)
+            .map(|r| (r.symbol.as_str(), r.rank))
+            .collect();
+        for entry in &mut self.entries {
+            entry.previous_rank = ranks.get(entry.symbol.as_str()).copied();
+        }
+    }
+}
+
+fn to_json<T: Serialize>(value: &T) -> String {
+    serde_json::to_string(value).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Run whose symbols are ranked in the order given
+    fn run(symbols: &[&str]) -> LeaderboardRun {
+        let entries = symbols.iter().enumerate()
+            .map(|(i, symbol)| LeaderboardEntry {
+                rank: i + 1,
+                previous_rank: None,
+                symbol: symbol.to_string(),
+                params: StrategyParams::default(),
+                in_sample_score: 0.0,
+                out_of_sample_score: 0.0,
+                out_of_sample: PerformanceMetrics::default(),
+                signal: Trigger::None,
+                signal_datetime: String::new(),
+            })
+            .collect();
+        LeaderboardRun { entries, errors: Vec::new() }
+    }
+
+    /// Stored rows of the runs, read back from the table
+    fn history(runs: &[(&str, &str, &[&str])]) -> Vec<LeaderboardRecord> {
+        let mut df = DataFrame::empty();
+        for (run_at, strategy, symbols) in runs {
+            df.vstack_mut(&run(symbols).to_df(run_at, strategy, "total_return").unwrap()).unwrap();
+        }
+        LeaderboardRecord::from_df(&df).unwrap()
+    }
+
+    fn previous_ranks(run: &LeaderboardRun) -> Vec<Option<usize>> {
+        run.entries.iter().map(|e| e.previous_rank).collect()
+    }
+
+    #[test]
+    fn ranks_come_from_the_last_run_of_an_earlier_day() {
+        let history = history(&[
+            ("2024-03-01 10:00:00", "sma", &["AAA", "BBB"]),
+            ("2024-03-02 09:00:00", "sma", &["BBB", "AAA", "CCC"]),
+            ("2024-03-02 20:00:00", "rsi", &["CCC", "BBB", "AAA"]),
+            ("2024-03-03 08:00:00", "sma", &["CCC", "DDD", "AAA"]),
+        ]);
+        assert_eq!(history.len(), 11);
+        assert_eq!(history[0].run_at, "2024-03-01 10:00:00");
+        let mut today = run(&["AAA", "BBB", "DDD"]);
+        today.compare_with(&history, "2024-03-03 12:00:00", "sma", "total_return");
+        assert_eq!(previous_ranks(&today), [Some(2), Some(1), None]);
+    }
+
+    #[test]
+    fn nothing_to_compare_on_the_first_day() {
+        let history = history(&[("2024-03-03 08:00:00", "sma", &["AAA"])]);
+        let mut today = run(&["AAA"]);
+        today.compare_with(&history, "2024-03-03 12:00:00", "sma", "total_return");
+        today.compare_with(&history, "2024-03-04 12:00:00", "sma", "sharpe");
+        assert_eq!(previous_ranks(&today), [None]);
+    }
+}
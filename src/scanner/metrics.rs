@@ -0,0 +1,213 @@
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+
+use crate::strategy::indicators;
+use super::BacktestRun;
+
+/// Daily bars from the price source
+pub const BARS_PER_YEAR: f32 = 252.0;
+
+/// Metric a scanner maximizes, drawdowns are ranked from smallest to largest
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Metric {
+    #[default]
+    TotalReturn,
+    Cagr,
+    Sharpe,
+    Sortino,
+    Calmar,
+    MaxDrawdown,
+    WinRate,
+    ProfitFactor,
+}
+
+impl Metric {
+    pub fn parse(raw: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        match raw {
+            "total_return" => Ok(Metric::TotalReturn),
+            "cagr" => Ok(Metric::Cagr),
+            "sharpe" => Ok(Metric::Sharpe),
+            "sortino" => Ok(Metric::Sortino),
+            "calmar" => Ok(Metric::Calmar),
+            "max_drawdown" => Ok(Metric::MaxDrawdown),
+            "win_rate" => Ok(Metric::WinRate),
+            "profit_factor" => Ok(Metric::ProfitFactor),
+            other => Err(format!("Unknown metric '{}'", other).into()),
+        }
+    }
+
+    /// Score where higher is better, undefined ratios rank last
+    pub fn score(&self, metrics: &PerformanceMetrics) -> f32 {
+        let value = match self {
+            Metric::TotalReturn => Some(metrics.total_return_pct),
+            Metric::Cagr => Some(metrics.cagr_pct),
+            Metric::Sharpe => metrics.sharpe,
+            Metric::Sortino => metrics.sortino,
+            Metric::Calmar => metrics.calmar,
+            Metric::MaxDrawdown => Some(-metrics.max_drawdown_pct),
+            Metric::WinRate => metrics.win_rate_pct,
+            Metric::ProfitFactor => metrics.profit_factor,
+        };
+        value.filter(|v| v.is_finite()).unwrap_or(f32::NEG_INFINITY)
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct PerformanceMetrics {
+    pub total_return_pct: f32,
+    pub cagr_pct: f32,
+    /// Annualized standard deviation of bar returns
+    pub volatility_pct: f32,
+    pub sharpe: Option<f32>,
+    pub sortino: Option<f32>,
+    pub calmar: Option<f32>,
+    pub max_drawdown_pct: f32,
+    /// Longest stretch of bars spent below a previous equity peak
+    pub max_drawdown_bars: usize,
+    pub win_rate_pct: Option<f32>,
+    pub profit_factor: Option<f32>,
+    pub avg_win: Option<f32>,
+    pub avg_loss: Option<f32>,
+    /// Share of bars with an open position
+    pub exposure_pct: f32,
+    pub trades: usize,
+}
+
+fn mean(values: &[f32]) -> f32 {
+    values.iter().sum::<f32>() / values.len() as f32
+}
+
+fn years_between(first: &str, last: &str) -> Option<f32> {
+    let parse = |dt: &str| NaiveDate::parse_from_str(dt.get(..10)?, "%Y-%m-%d").ok();
+    let days = (parse(last)? - parse(first)?).num_days();
+    (days > 0).then_some(days as f32 / 365.25)
+}
+
+impl PerformanceMetrics {
+    pub fn from_run(run: &BacktestRun) -> Result<Self, Box<dyn std::error::Error>> {
+        let curve = &run.equity_curve;
+        let datetime = curve.column("datetime")?.str()?;
+        let equity_col = curve.column("equity")?.f64()?;
+        let shares_col = curve.column("shares")?.f64()?;
+        let rows: Vec<usize> = indicators::chronological_rows(curve)?
+            .into_iter()
+            .filter(|row| equity_col.get(*row).is_some())
+            .collect();
+        let equity: Vec<f64> = rows.iter().map(|row| equity_col.get(*row).unwrap()).collect();
+        let returns: Vec<f32> = equity.windows(2).map(|w| (w[1] / w[0] - 1.0) as f32).collect();
+
+        let mut metrics = PerformanceMetrics {
+            total_return_pct: run.total_return_pct,
+            trades: run.trades.len(),
+            ..Default::default()
+        };
+        if rows.is_empty() {
+            return Ok(metrics);
+        }
+
+        let growth = (run.final_equity / run.initial_capital) as f32;
+        let years = years_between(
+            datetime.get(rows[0]).unwrap_or_default(),
+            datetime.get(rows[rows.len() - 1]).unwrap_or_default()
+        );
+        metrics.cagr_pct = match years {
+            Some(years) if growth > 0.0 => 100.0 * (growth.powf(1.0 / years) - 1.0),
+            _ => metrics.total_return_pct,
+        };
+
+        if !returns.is_empty() {
+            let avg = mean(&returns);
+            let std = (returns.iter().map(|r| (r - avg).powi(2)).sum::<f32>() / returns.len() as f32).sqrt();
+            let downside = (returns.iter().map(|r| r.min(0.0).powi(2)).sum::<f32>() / returns.len() as f32).sqrt();
+            metrics.volatility_pct = 100.0 * std * BARS_PER_YEAR.sqrt();
+            metrics.sharpe = (std > 0.0).then(|| avg / std * BARS_PER_YEAR.sqrt());
+            metrics.sortino = (downside > 0.0).then(|| avg / downside * BARS_PER_YEAR.sqrt());
+        }
+
+        let mut peak = equity[0];
+        let mut underwater = 0;
+        for value in &equity {
+            if *value >= peak {
+                peak = *value;
+                underwater = 0;
+            } else {
+                underwater += 1;
+                metrics.max_drawdown_bars = metrics.max_drawdown_bars.max(underwater);
+                metrics.max_drawdown_pct = metrics.max_drawdown_pct.max((100.0 * (1.0 - value / peak)) as f32);
+            }
+        }
+        metrics.calmar = (metrics.max_drawdown_pct > 0.0).then(|| metrics.cagr_pct / metrics.max_drawdown_pct);
+
+        let wins: Vec<f32> = run.trades.iter().filter(|t| t.pnl > 0.0).map(|t| t.pnl as f32).collect();
+        let losses: Vec<f32> = run.trades.iter().filter(|t| t.pnl <= 0.0).map(|t| t.pnl as f32).collect();
+        let gross_loss = -losses.iter().sum::<f32>();
+        metrics.win_rate_pct = (!run.trades.is_empty()).then(|| 100.0 * wins.len() as f32 / run.trades.len() as f32);
+        metrics.profit_factor = (gross_loss > 0.0).then(|| wins.iter().sum::<f32>() / gross_loss);
+        metrics.avg_win = (!wins.is_empty()).then(|| mean(&wins));
+        metrics.avg_loss = (!losses.is_empty()).then(|| mean(&losses));
+
+        let exposed = rows.iter().filter(|row| shares_col.get(**row).is_some_and(|s| s != 0.0)).count();
+        metrics.exposure_pct = 100.0 * exposed as f32 / rows.len() as f32;
+        Ok(metrics)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use polars::prelude::df;
+    use crate::scanner::Sizing;
+    use super::*;
+
+    /// Run without trades over an equity curve given oldest first
+    fn run(datetime: &[&str], equity: &[f64], shares: &[f64]) -> BacktestRun {
+        let newest_first = |values: &[f64]| values.iter().rev().copied().collect::<Vec<f64>>();
+        let equity_curve = df!(
+            "datetime" => datetime.iter().rev().copied().collect::<Vec<&str>>(),
+            "cash" => newest_first(equity),
+            "shares" => newest_first(shares),
+            "equity" => newest_first(equity)
+        ).unwrap();
+        let (initial_capital, final_equity) = (equity[0], equity[equity.len() - 1]);
+        BacktestRun {
+            signal: "Sig".to_string(),
+            initial_capital,
+            final_equity,
+            total_return_pct: (100.0 * (final_equity / initial_capital - 1.0)) as f32,
+            equity_curve,
+            trades: Vec::new(),
+            sizing: Sizing::default(),
+            regimes: None,
+        }
+    }
+
+    #[test]
+    fn metrics_follow_the_equity_curve() {
+        let datetime = ["2020-01-01", "2020-05-01", "2020-09-01", "2022-01-01"];
+        let metrics = PerformanceMetrics::from_run(&run(&datetime, &[100.0, 110.0, 99.0, 121.0], &[0.0, 1.0, 1.0, 0.0])).unwrap();
+        assert!((metrics.total_return_pct - 21.0).abs() < 1e-4);
+        // 21% over the 731 days of two years, one of them a leap year
+        assert!((metrics.cagr_pct - 10.0).abs() < 0.05);
+        assert!((metrics.max_drawdown_pct - 10.0).abs() < 1e-4);
+        assert_eq!(metrics.max_drawdown_bars, 1);
+        assert!((metrics.calmar.unwrap() - metrics.cagr_pct / metrics.max_drawdown_pct).abs() < 1e-6);
+        assert_eq!(metrics.exposure_pct, 50.0);
+
+        let returns = [0.1f32, -0.1, 2.0 / 9.0];
+        let avg = mean(&returns);
+        let std = (returns.iter().map(|r| (r - avg).powi(2)).sum::<f32>() / 3.0).sqrt();
+        let downside = (0.01f32 / 3.0).sqrt();
+        assert!((metrics.sharpe.unwrap() - avg / std * BARS_PER_YEAR.sqrt()).abs() < 1e-3);
+        assert!((metrics.sortino.unwrap() - avg / downside * BARS_PER_YEAR.sqrt()).abs() < 1e-3);
+        assert!(metrics.sortino.unwrap() > metrics.sharpe.unwrap());
+    }
+
+    #[test]
+    fn flat_equity_leaves_the_ratios_undefined() {
+        let metrics = PerformanceMetrics::from_run(&run(&["2024-01-01", "2024-01-02", "2024-01-03"], &[100.0; 3], &[0.0; 3])).unwrap();
+        assert_eq!(metrics.max_drawdown_pct, 0.0);
+        assert_eq!((metrics.sharpe, metrics.sortino, metrics.calmar), (None, None, None));
+        assert_eq!(metrics.win_rate_pct, None);
+        assert_eq!(Metric::Sharpe.score(&metrics), f32::NEG_INFINITY);
+    }
+}
@@ -1,17 +1,35 @@
 mod backtest;
-mod scanner_ma;
-mod scanner_rsi;
-mod scanner_bb;
+mod benchmark;
+mod costs;
+mod engine;
+mod grid_search;
+mod leaderboard;
+mod metrics;
+mod monte_carlo;
+mod optimizer;
+mod portfolio;
+mod progress;
+mod scan_history;
+mod screener;
+mod sensitivity;
+mod sizing;
+mod split;
+mod walk_forward;
 
-use polars::prelude::*;
-
-pub use backtest::Backtest;
-pub use scanner_ma::ScannerCrossingMA;
-pub use scanner_rsi::ScannerRSI;
-pub use scanner_bb::ScannerBollingerBands;
-
-pub trait ScannerPerformance {
-    fn scan_performance(&mut self) -> Result<(), Box<dyn std::error::Error>>;
-    fn best_performance(&mut self) -> Option<(&String, &f32)>;
-    fn get_best_performance_df(&mut self) -> Option<DataFrame>;
-}
\ No newline at end of file
+pub use backtest::{Backtest, BacktestRun, Execution, PositionMode, RegimePerformance, Side, Trade};
+pub use benchmark::BenchmarkComparison;
+pub use costs::{CostModel, FillBar};
+pub use engine::{DonchianBreakout, EventBacktest, EventStrategy, OrderRecord, SignalAdapter};
+pub use grid_search::{GridResult, GridSearch, ParamGrid, ParamRange};
+pub use leaderboard::{Leaderboard, LeaderboardRecord, LeaderboardRun, DEFAULT_TEST_RATIO, LEADERBOARD_TABLE};
+pub use metrics::{Metric, PerformanceMetrics};
+pub use monte_carlo::{MonteCarlo, MonteCarloRun};
+pub use optimizer::{Optimizer, OptimizerRun, SearchMethod};
+pub use portfolio::{Fill, Portfolio, PortfolioLeg, PortfolioRun, SymbolContribution};
+pub use progress::{Progress, ProgressSnapshot};
+pub use scan_history::{ScanKey, ScanRecord, SCAN_RESULTS_TABLE};
+pub use screener::{Screener, SymbolError, ScreenerRun};
+pub use sensitivity::{Heatmap, Robustness, Selection, Sensitivity, DEFAULT_RADIUS};
+pub use sizing::Sizing;
+pub use split::{Degradation, SplitRun, TrainTestSplit};
+pub use walk_forward::{WalkForward, WalkForwardMode, WalkForwardRun, WalkForwardWindow, DEFAULT_TEST_BARS, DEFAULT_TRAIN_BARS};
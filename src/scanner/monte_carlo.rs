@@ -0,0 +1,283 @@
+use std::collections::HashMap;
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use super::grid_search::worker_pool;
+use super::{BacktestRun, Trade};
+
+const MAX_SIMULATIONS: usize = 100_000;
+
+/// How a simulated trade sequence is drawn from the backtest trades
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Resampling {
+    /// Same trades in a random order, the final return never changes, only the path
+    Shuffle,
+    /// As many trades as the backtest, drawn with replacement
+    Bootstrap,
+    /// Every trade is dropped with the skip probability, as if the signal had been missed
+    Skip,
+}
+
+impl Resampling {
+    pub fn parse(raw: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        match raw {
+            "shuffle" => Ok(Resampling::Shuffle),
+            "bootstrap" => Ok(Resampling::Bootstrap),
+            "skip" => Ok(Resampling::Skip),
+            other => Err(format!("Unknown resampling '{}'", other).into()),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Distribution {
+    pub mean: f32,
+    pub std: f32,
+    pub min: f32,
+    pub p5: f32,
+    pub median: f32,
+    pub p95: f32,
+    pub max: f32,
+    /// Bounds of the central interval holding the confidence level of the simulations
+    pub ci_low: f32,
+    pub ci_high: f32,
+}
+
+/// Nearest-rank percentile of sorted values
+fn percentile(sorted: &[f32], pct: f64) -> f32 {
+    let rank = (pct / 100.0 * (sorted.len() - 1) as f64).round() as usize;
+    sorted[rank.min(sorted.len() - 1)]
+}
+
+impl Distribution {
+    fn new(mut values: Vec<f32>, confidence_pct: f64) -> Self {
+        values.sort_by(|a, b| a.total_cmp(b));
+        let mean = values.iter().map(|v| *v as f64).sum::<f64>() / values.len() as f64;
+        let std = (values.iter().map(|v| (*v as f64 - mean).powi(2)).sum::<f64>() / values.len() as f64).sqrt();
+        let tail = (100.0 - confidence_pct) / 2.0;
+        Distribution {
+            mean: mean as f32,
+            std: std as f32,
+            min: values[0],
+            p5: percentile(&values, 5.0),
+            median: percentile(&values, 50.0),
+            p95: percentile(&values, 95.0),
+            max: values[values.len() - 1],
+            ci_low: percentile(&values, tail),
+            ci_high: percentile(&values, 100.0 - tail),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Simulation {
+    pub resampling: Resampling,
+    pub final_return_pct: Distribution,
+    pub max_drawdown_pct: Distribution,
+    /// Share of the simulations that end below the initial capital
+    pub loss_probability_pct: f32,
+}
+
+/// Drawdowns are measured on the equity after each closed trade, so they are shallower than the
+/// bar by bar drawdown of the backtest
+#[derive(Serialize, Debug, Clone)]
+pub struct MonteCarloRun {
+    pub simulations: usize,
+    /// Seed that reproduces these results
+    pub seed: u64,
+    pub confidence_pct: f64,
+    pub skip_pct: f64,
+    pub trades: usize,
+    /// Final return and max drawdown of the trades in their real order
+    pub final_return_pct: f32,
+    pub max_drawdown_pct: f32,
+    pub results: Vec<Simulation>,
+}
+
+#[derive(Debug, Clone)]
+pub struct MonteCarlo {
+    pub simulations: usize,
+    pub seed: u64,
+    pub confidence_pct: f64,
+    pub skip_pct: f64,
+    pub resamplings: Vec<Resampling>,
+}
+
+impl Default for MonteCarlo {
+    fn default() -> Self {
+        MonteCarlo {
+            simulations: 1000,
+            seed: rand::random(),
+            confidence_pct: 95.0,
+            skip_pct: 10.0,
+            resamplings: vec![Resampling::Shuffle, Resampling::Bootstrap, Resampling::Skip],
+        }
+    }
+}
+
+fn parse_pct(raw: &HashMap<String, String>, key: &str, default: f64) -> Result<f64, Box<dyn std::error::Error>> {
+    let value = match raw.get(key) {
+        Some(value) => value.parse().map_err(|e| format!("Invalid {}: {}", key, e))?,
+        None => default,
+    };
+    if value.is_nan() || value <= 0.0 || value >= 100.0 {
+        return Err(format!("{} must be between 0 and 100", key).into());
+    }
+    Ok(value)
+}
+
+/// Return of each trade on the equity it was entered with, the trades compound in order
+fn trade_returns(initial_capital: f64, trades: &[Trade]) -> Vec<f64> {
+    let mut equity = initial_capital;
+    trades.iter()
+        .map(|trade| {
+            let trade_return = trade.pnl / equity;
+            equity += trade.pnl;
+            trade_return
+        })
+        .collect()
+}
+
+/// Final return and max drawdown, both in percent, of trades compounding one after the other
+fn path_stats(returns: impl Iterator<Item = f64>) -> (f32, f32) {
+    let (mut equity, mut peak, mut max_drawdown) = (1.0f64, 1.0f64, 0.0f64);
+    for trade_return in returns {
+        equity *= 1.0 + trade_return;
+        peak = peak.max(equity);
+        max_drawdown = max_drawdown.max(1.0 - equity / peak);
+    }
+    ((100.0 * (equity - 1.0)) as f32, (100.0 * max_drawdown) as f32)
+}
+
+impl MonteCarlo {
+    /// Reads `mc_simulations`, `mc_seed`, `mc_confidence_pct`, `mc_skip_pct` and a comma separated
+    /// `mc_resampling`, an unseeded run draws a seed and reports it
+    pub fn from_query(raw: &HashMap<String, String>) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut monte_carlo = MonteCarlo::default();
+        if let Some(simulations) = raw.get("mc_simulations") {
+            monte_carlo.simulations = simulations.parse().map_err(|e| format!("Invalid mc_simulations: {}", e))?;
+            if monte_carlo.simulations == 0 || monte_carlo.simulations > MAX_SIMULATIONS {
+                return Err(format!("mc_simulations must be between 1 and {}", MAX_SIMULATIONS).into());
+            }
+        }
+        if let Some(seed) = raw.get("mc_seed") {
+            monte_carlo.seed = seed.parse().map_err(|e| format!("Invalid mc_seed: {}", e))?;
+        }
+        monte_carlo.confidence_pct = parse_pct(raw, "mc_confidence_pct", monte_carlo.confidence_pct)?;
+        monte_carlo.skip_pct = parse_pct(raw, "mc_skip_pct", monte_carlo.skip_pct)?;
+        if let Some(resamplings) = raw.get("mc_resampling") {
+            monte_carlo.resamplings = resamplings.split(',')
+                .map(|r| Resampling::parse(r.trim()))
+                .collect::<Result<_, _>>()?;
+        }
+        Ok(monte_carlo)
+    }
+
+    pub fn run(&self, backtest: &BacktestRun) -> Result<MonteCarloRun, Box<dyn std::error::Error>> {
+        if backtest.trades.is_empty() {
+            return Err("Monte Carlo needs a backtest with at least one trade".into());
+        }
+        let returns = trade_returns(backtest.initial_capital, &backtest.trades);
+        let (final_return_pct, max_drawdown_pct) = path_stats(returns.iter().copied());
+        let pool = worker_pool()?;
+        let results = self.resamplings.iter()
+            .map(|resampling| pool.install(|| self.simulate(*resampling, &returns)))
+            .collect();
+        Ok(MonteCarloRun {
+            simulations: self.simulations,
+            seed: self.seed,
+            confidence_pct: self.confidence_pct,
+            skip_pct: self.skip_pct,
+            trades: returns.len(),
+            final_return_pct,
+            max_drawdown_pct,
+            results,
+        })
+    }
+
+    /// Every simulation has its own generator seeded from the run seed and its index, so results
+    /// do not depend on how rayon schedules them
+    fn simulate(&self, resampling: Resampling, returns: &[f64]) -> Simulation {
+        let paths: Vec<(f32, f32)> = (0..self.simulations)
+            .into_par_iter()
+            .map(|i| {
+                let mut rng = StdRng::seed_from_u64(self.seed.wrapping_add(i as u64));
+                match resampling {
+                    Resampling::Shuffle => {
+                        let mut shuffled = returns.to_vec();
+                        shuffled.shuffle(&mut rng);
+                        path_stats(shuffled.into_iter())
+                    }
+                    Resampling::Bootstrap => path_stats(
+                        (0..returns.len()).map(|_| returns[rng.random_range(0..returns.len())])
+                    ),
+                    Resampling::Skip => path_stats(
+                        returns.iter().copied().filter(|_| !rng.random_bool(self.skip_pct / 100.0))
+                    ),
+                }
+            })
+            .collect();
+        let (final_returns, drawdowns): (Vec<f32>, Vec<f32>) = paths.into_iter().unzip();
+        let losses = final_returns.iter().filter(|r| **r < 0.0).count();
+        Simulation {
+            resampling,
+            loss_probability_pct: 100.0 * losses as f32 / self.simulations as f32,
+            final_return_pct: Distribution::new(final_returns, self.confidence_pct),
+            max_drawdown_pct: Distribution::new(drawdowns, self.confidence_pct),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::scanner::Backtest;
+    use crate::strategy::{BUY_SIGNAL, SELL_SIGNAL};
+    use crate::test_fixtures::{bars, query, with_signals};
+    use super::*;
+
+    /// Four round trips of three bars each, two winners and two losers of different sizes
+    fn backtest_run() -> BacktestRun {
+        let close = [100.0, 104.0, 110.0, 108.0, 101.0, 97.0, 99.0, 103.0, 106.0, 104.0, 98.0, 93.0];
+        let round_trip = [BUY_SIGNAL, 0, SELL_SIGNAL];
+        let df = with_signals(bars(&close), "Sig", &round_trip.repeat(4));
+        Backtest::new().run(&df, "Sig").unwrap()
+    }
+
+    fn monte_carlo(seed: u64) -> MonteCarlo {
+        MonteCarlo { simulations: 200, seed, ..MonteCarlo::default() }
+    }
+
+    fn results_json(run: &MonteCarloRun) -> String {
+        serde_json::to_string(&run.results).unwrap()
+    }
+
+    #[test]
+    fn same_seed_reproduces_the_simulations() {
+        let backtest = backtest_run();
+        assert_eq!(backtest.trades.len(), 4);
+        let first = monte_carlo(42).run(&backtest).unwrap();
+        let second = monte_carlo(42).run(&backtest).unwrap();
+        assert_eq!(first.seed, 42);
+        assert_eq!(results_json(&first), results_json(&second));
+        let other = monte_carlo(43).run(&backtest).unwrap();
+        assert_ne!(results_json(&first), results_json(&other));
+    }
+
+    #[test]
+    fn shuffling_keeps_the_final_return() {
+        let backtest = backtest_run();
+        let run = MonteCarlo { resamplings: vec![Resampling::Shuffle], ..monte_carlo(7) }.run(&backtest).unwrap();
+        let final_return = &run.results[0].final_return_pct;
+        assert!((final_return.min - run.final_return_pct).abs() < 1e-3);
+        assert!((final_return.max - run.final_return_pct).abs() < 1e-3);
+    }
+
+    #[test]
+    fn seed_is_read_from_the_query() {
+        assert_eq!(MonteCarlo::from_query(&query(&[("mc_seed", "1234")])).unwrap().seed, 1234);
+        assert!(MonteCarlo::from_query(&query(&[("mc_seed", "-1")])).is_err());
+        assert!(MonteCarlo::from_query(&query(&[("mc_simulations", "0")])).is_err());
+    }
+}
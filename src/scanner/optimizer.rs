@@ -0,0 +1,549 @@
+use std::{collections::{HashMap, HashSet}, sync::Arc};
+use log::debug;
+use polars::prelude::*;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::strategy::{StrategyParams, StrategySpec};
+use super::grid_search::{rank, worker_pool};
+use super::progress::{cancelled, cancelled_error};
+use super::{GridResult, GridSearch, Progress};
+
+const MAX_BUDGET: usize = 10_000;
+/// Combinations backtested together, fixed so results do not depend on the number of threads
+const BATCH_SIZE: usize = 16;
+/// Random draws before giving up on finding an unvisited combination and scanning for one
+const RANDOM_TRIES: usize = 64;
+/// Genetic parents are the best of this many random members of the population
+const TOURNAMENT_SIZE: usize = 3;
+/// Members of the population carried over to the next generation unchanged
+const ELITES: usize = 2;
+/// Share of the mutations that draw a new value instead of moving the old one
+const RESET_RATE: f64 = 0.2;
+/// Share of the TPE observations modelled as good
+const TPE_GAMMA: f64 = 0.25;
+/// Combinations drawn from the good model before picking the one most likely to be good
+const TPE_CANDIDATES: usize = 24;
+
+/// How an optimizer picks the combinations of a grid it backtests
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum SearchMethod {
+    /// Every combination, the budget and early stopping do not apply
+    #[default]
+    Grid,
+    /// Combinations drawn uniformly without repetition
+    Random,
+    /// A population evolved by tournament selection, uniform crossover and mutation
+    Genetic,
+    /// Tree-structured Parzen estimator, draws where good scores are more likely than bad ones
+    Tpe,
+}
+
+impl SearchMethod {
+    pub fn parse(raw: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        match raw {
+            "grid" => Ok(SearchMethod::Grid),
+            "random" => Ok(SearchMethod::Random),
+            "genetic" => Ok(SearchMethod::Genetic),
+            "tpe" | "bayesian" => Ok(SearchMethod::Tpe),
+            other => Err(format!("Unknown search method '{}'", other).into()),
+        }
+    }
+}
+
+/// Results of an optimizer ranked like a grid search on the same metric
+#[derive(Serialize, Debug, Clone)]
+pub struct OptimizerRun {
+    pub method: SearchMethod,
+    /// Seed that reproduces these results
+    pub seed: u64,
+    pub budget: usize,
+    /// Combinations backtested, combinations the strategy rejected are not counted
+    pub evaluations: usize,
+    pub rejected: usize,
+    /// Whether the patience ran out before the budget
+    pub stopped_early: bool,
+    pub results: Vec<GridResult>,
+}
+
+/// Searches the combinations of a grid search's parameter grid with a limited number of backtests
+#[derive(Debug, Clone)]
+pub struct Optimizer {
+    pub method: SearchMethod,
+    /// Most combinations backtested
+    pub budget: usize,
+    /// Stops after this many backtests in a row without a better score
+    pub patience: Option<usize>,
+    pub seed: u64,
+    /// Size of the genetic population, also the number of random combinations TPE starts from
+    pub population: usize,
+}
+
+impl Default for Optimizer {
+    fn default() -> Self {
+        Optimizer {
+            method: SearchMethod::Grid,
+            budget: 200,
+            patience: None,
+            seed: rand::random(),
+            population: 20,
+        }
+    }
+}
+
+impl Optimizer {
+    /// Reads `method`, `budget`, `patience`, `seed` and `population`, an unseeded run draws a seed
+    /// and reports it
+    pub fn from_query(raw: &HashMap<String, String>) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut optimizer = Optimizer::default();
+        if let Some(method) = raw.get("method") {
+            optimizer.method = SearchMethod::parse(method)?;
+        }
+        if let Some(budget) = raw.get("budget") {
+            optimizer.budget = budget.parse().map_err(|e| format!("Invalid budget: {}", e))?;
+            if optimizer.budget == 0 || optimizer.budget > MAX_BUDGET {
+                return Err(format!("budget must be between 1 and {}", MAX_BUDGET).into());
+            }
+        }
+        if let Some(patience) = raw.get("patience") {
+            let patience: usize = patience.parse().map_err(|e| format!("Invalid patience: {}", e))?;
+            if patience == 0 {
+                return Err("patience must be at least 1".into());
+            }
+            optimizer.patience = Some(patience);
+        }
+        if let Some(seed) = raw.get("seed") {
+            optimizer.seed = seed.parse().map_err(|e| format!("Invalid seed: {}", e))?;
+        }
+        if let Some(population) = raw.get("population") {
+            optimizer.population = population.parse().map_err(|e| format!("Invalid population: {}", e))?;
+            if optimizer.population < 2 {
+                return Err("population must be at least 2".into());
+            }
+        }
+        Ok(optimizer)
+    }
+
+    /// Backtests combinations of the search's grid chosen by the method. Batches are backtested in
+    /// parallel but their results are taken in order, so a seed always gives the same results.
+    pub fn run(&self, search: &GridSearch, df: &DataFrame, spec: &StrategySpec) -> Result<OptimizerRun, Box<dyn std::error::Error>> {
+        let sample: Sampler = match self.method {
+            SearchMethod::Grid => return self.run_grid(search, df, spec),
+            SearchMethod::Random => |trials, rng, _| trials.random(rng),
+            SearchMethod::Genetic => |trials, rng, population| trials.genetic(rng, population),
+            SearchMethod::Tpe => |trials, rng, population| trials.tpe(rng, population),
+        };
+        let mut trials = Trials::new(self, search, df, spec)?;
+        sample(&mut trials, &mut StdRng::seed_from_u64(self.seed), self.population);
+        if cancelled(trials.progress.as_ref()) {
+            return Err(cancelled_error());
+        }
+        let stopped_early = trials.patience_ran_out();
+        let (evaluations, rejected) = (trials.results.len(), trials.rejected);
+        let mut results = trials.results;
+        rank(&mut results, spec)?;
+        Ok(OptimizerRun {
+            method: self.method,
+            seed: self.seed,
+            budget: self.budget,
+            evaluations,
+            rejected,
+            stopped_early,
+            results,
+        })
+    }
+
+    fn run_grid(&self, search: &GridSearch, df: &DataFrame, spec: &StrategySpec) -> Result<OptimizerRun, Box<dyn std::error::Error>> {
+        let results = search.run(df, spec)?;
+        let combinations = search.grid.combinations().len();
+        Ok(OptimizerRun {
+            method: self.method,
+            seed: self.seed,
+            budget: combinations,
+            evaluations: results.len(),
+            rejected: combinations - results.len(),
+            stopped_early: false,
+            results,
+        })
+    }
+}
+
+/// A combination as the index of its value in each range of the grid
+type Point = Vec<usize>;
+
+/// Picks and backtests points until the trials are done, given the population size
+type Sampler = fn(&mut Trials, &mut StdRng, usize);
+
+/// Combinations tried so far and how they scored
+struct Trials<'a> {
+    search: &'a GridSearch,
+    df: &'a DataFrame,
+    spec: &'a StrategySpec,
+    pool: &'static rayon::ThreadPool,
+    /// Current progress of the thread that runs the optimizer
+    progress: Option<Arc<Progress>>,
+    values: Vec<Vec<f64>>,
+    size: usize,
+    budget: usize,
+    patience: Option<usize>,
+    visited: HashSet<Point>,
+    /// Point of each result, in the order they were backtested
+    points: Vec<Point>,
+    results: Vec<GridResult>,
+    rejected: usize,
+    best: f32,
+    since_best: usize,
+}
+
+impl<'a> Trials<'a> {
+    fn new(optimizer: &Optimizer, search: &'a GridSearch, df: &'a DataFrame, spec: &'a StrategySpec) -> Result<Self, Box<dyn std::error::Error>> {
+        let progress = Progress::current();
+        if let Some(progress) = &progress {
+            progress.claim(optimizer.budget.min(search.grid.combinations().len()));
+        }
+        Ok(Trials {
+            search,
+            df,
+            spec,
+            pool: worker_pool()?,
+            progress,
+            values: search.grid.ranges.iter().map(|range| range.values()).collect(),
+            size: search.grid.size(),
+            budget: optimizer.budget,
+            patience: optimizer.patience,
+            visited: HashSet::new(),
+            points: Vec::new(),
+            results: Vec::new(),
+            rejected: 0,
+            best: f32::NEG_INFINITY,
+            since_best: 0,
+        })
+    }
+
+    fn patience_ran_out(&self) -> bool {
+        self.patience.is_some_and(|patience| self.since_best >= patience)
+    }
+
+    fn done(&self) -> bool {
+        self.results.len() >= self.budget || self.patience_ran_out() || self.visited.len() >= self.size
+            || cancelled(self.progress.as_ref())
+    }
+
+    fn remaining(&self) -> usize {
+        self.budget - self.results.len()
+    }
+
+    /// Marks a point as visited, false if it already was
+    fn visit(&mut self, point: &Point) -> bool {
+        self.visited.insert(point.clone())
+    }
+
+    /// An unvisited point drawn uniformly, marked as visited, None once every point was visited
+    fn random_point(&mut self, rng: &mut StdRng) -> Option<Point> {
+        for _ in 0..RANDOM_TRIES {
+            let point: Point = self.values.iter().map(|values| rng.random_range(0..values.len())).collect();
+            if self.visit(&point) {
+                return Some(point);
+            }
+        }
+        // Most points were visited, walk the grid from a random combination
+        let start = rng.random_range(0..self.size);
+        (0..self.size).find_map(|i| {
+            let point = self.decode((start + i) % self.size);
+            self.visit(&point).then_some(point)
+        })
+    }
+
+    /// Point of the i-th combination, the last parameter changing fastest
+    fn decode(&self, mut i: usize) -> Point {
+        let mut point = vec![0; self.values.len()];
+        for (d, values) in self.values.iter().enumerate().rev() {
+            point[d] = i % values.len();
+            i /= values.len();
+        }
+        point
+    }
+
+    fn params(&self, point: &Point) -> StrategyParams {
+        let mut params = StrategyParams::default();
+        for ((range, values), i) in self.search.grid.ranges.iter().zip(&self.values).zip(point) {
+            params.set(&range.name, values[*i]);
+        }
+        params
+    }
+
+    /// Backtests visited points in parallel and records them in order until the budget or the
+    /// patience runs out, later results of the batch are dropped. Points breaking a constraint of
+    /// the grid are never backtested, progress only counts the recorded results.
+    fn evaluate(&mut self, batch: Vec<Point>) {
+        if cancelled(self.progress.as_ref()) {
+            return;
+        }
+        let batch: Vec<(Point, StrategyParams)> = batch.into_iter()
+            .map(|point| {
+                let params = self.params(&point);
+                (point, params)
+            })
+            .filter(|(_, params)| self.search.grid.constraints.iter().all(|c| c.allows(params)))
+            .collect();
+        let evaluated: Vec<_> = self.pool.install(|| batch.into_par_iter()
+            .map(|(point, params)| {
+                let outcome = self.search.evaluate(self.df, self.spec, &params).map_err(|e| e.to_string());
+                (point, params, outcome)
+            })
+            .collect());
+        for (point, params, outcome) in evaluated {
+            if self.results.len() >= self.budget || self.patience_ran_out() {
+                break;
+            }
+            match outcome {
+                Ok((score, metrics)) => {
+                    if score > self.best {
+                        (self.best, self.since_best) = (score, 0);
+                    } else {
+                        self.since_best += 1;
+                    }
+                    self.points.push(point);
+                    self.results.push(GridResult { rank: 0, params, score, metrics });
+                    if let Some(progress) = &self.progress {
+                        progress.advance();
+                    }
+                }
+                Err(e) => {
+                    debug!("Skipping {} with {:?}: {}", self.spec.name, params, e);
+                    self.rejected += 1;
+                }
+            }
+        }
+    }
+
+    /// Random unvisited points, at most what is left of the budget
+    fn random_batch(&mut self, rng: &mut StdRng, count: usize) -> Vec<Point> {
+        (0..count.min(self.remaining()))
+            .map_while(|_| self.random_point(rng))
+            .collect()
+    }
+
+    fn random(&mut self, rng: &mut StdRng) {
+        while !self.done() {
+            let batch = self.random_batch(rng, BATCH_SIZE);
+            self.evaluate(batch);
+        }
+    }
+
+    /// Each generation breeds children from the population, the best of the population and the
+    /// children survive. Children that were already tried are replaced by random points.
+    fn genetic(&mut self, rng: &mut StdRng, population_size: usize) {
+        let batch = self.random_batch(rng, population_size);
+        self.evaluate(batch);
+        let mut population: Vec<usize> = (0..self.results.len()).collect();
+        while !self.done() {
+            if population.is_empty() {
+                let batch = self.random_batch(rng, population_size);
+                let first = self.results.len();
+                self.evaluate(batch);
+                population = (first..self.results.len()).collect();
+                continue;
+            }
+            population.sort_by(|a, b| self.results[*b].score.total_cmp(&self.results[*a].score));
+            let children = (population_size.saturating_sub(ELITES)).max(1).min(self.remaining());
+            let mut batch = Vec::with_capacity(children);
+            for _ in 0..children {
+                let mother = self.tournament(rng, &population);
+                let father = self.tournament(rng, &population);
+                let mut child: Point = mother.iter().zip(&father)
+                    .map(|(m, f)| if rng.random_bool(0.5) { *m } else { *f })
+                    .collect();
+                self.mutate(rng, &mut child);
+                if !self.visit(&child) {
+                    self.mutate(rng, &mut child);
+                    if !self.visit(&child) {
+                        match self.random_point(rng) {
+                            Some(point) => child = point,
+                            None => break,
+                        }
+                    }
+                }
+                batch.push(child);
+            }
+            if batch.is_empty() {
+                break;
+            }
+            let first = self.results.len();
+            self.evaluate(batch);
+            population.extend(first..self.results.len());
+            population.sort_by(|a, b| self.results[*b].score.total_cmp(&self.results[*a].score));
+            population.truncate(population_size);
+        }
+    }
+
+    /// Point of the best of a few random members of the population
+    fn tournament(&self, rng: &mut StdRng, population: &[usize]) -> Point {
+        let winner = (0..TOURNAMENT_SIZE)
+            .map(|_| population[rng.random_range(0..population.len())])
+            .max_by(|a, b| self.results[*a].score.total_cmp(&self.results[*b].score))
+            .unwrap();
+        self.points[winner].clone()
+    }
+
+    /// Changes each parameter with a probability of one over the number of parameters, mostly by
+    /// up to a quarter of its range and sometimes to any of its values
+    fn mutate(&self, rng: &mut StdRng, point: &mut Point) {
+        let rate = 1.0 / self.values.len() as f64;
+        for (i, values) in point.iter_mut().zip(&self.values) {
+            if values.len() < 2 || !rng.random_bool(rate) {
+                continue;
+            }
+            if rng.random_bool(RESET_RATE) {
+                *i = rng.random_range(0..values.len());
+                continue;
+            }
+            let reach = (values.len() / 4).max(1) as i64;
+            let shift = rng.random_range(1..=reach) * if rng.random_bool(0.5) { 1 } else { -1 };
+            *i = (*i as i64 + shift).clamp(0, values.len() as i64 - 1) as usize;
+        }
+    }
+
+    /// Starts from random points, then splits the results into the best quarter and the rest and
+    /// models each parameter of both with a Parzen window over its values. Of the candidates drawn
+    /// from the good model, the one with the highest ratio of good to bad density is backtested.
+    fn tpe(&mut self, rng: &mut StdRng, startup: usize) {
+        let batch = self.random_batch(rng, startup);
+        self.evaluate(batch);
+        while !self.done() {
+            if self.results.len() < 2 {
+                let batch = self.random_batch(rng, BATCH_SIZE);
+                self.evaluate(batch);
+                continue;
+            }
+            let mut order: Vec<usize> = (0..self.results.len()).collect();
+            order.sort_by(|a, b| self.results[*b].score.total_cmp(&self.results[*a].score));
+            let good_count = ((order.len() as f64 * TPE_GAMMA).ceil() as usize).max(1);
+            let (good, bad) = order.split_at(good_count);
+            let good = self.parzen(good);
+            let bad = self.parzen(bad);
+            let candidate = (0..TPE_CANDIDATES)
+                .map(|_| good.iter().map(|density| sample(rng, density)).collect::<Point>())
+                .filter(|point| !self.visited.contains(point))
+                .map(|point| {
+                    let ratio: f64 = point.iter().enumerate()
+                        .map(|(d, i)| good[d][*i].ln() - bad[d][*i].ln())
+                        .sum();
+                    (point, ratio)
+                })
+                .max_by(|a, b| a.1.total_cmp(&b.1))
+                .map(|(point, _)| point);
+            let point = match candidate {
+                Some(point) => {
+                    self.visit(&point);
+                    point
+                }
+                None => match self.random_point(rng) {
+                    Some(point) => point,
+                    None => break,
+                },
+            };
+            self.evaluate(vec![point]);
+        }
+    }
+
+    /// Density over the values of each parameter of the given results, a Gaussian kernel on the
+    /// value index around each result plus a uniform prior
+    fn parzen(&self, results: &[usize]) -> Vec<Vec<f64>> {
+        self.values.iter().enumerate()
+            .map(|(d, values)| {
+                let n = values.len();
+                let bandwidth = (n as f64 / 5.0 / (results.len().max(1) as f64).powf(0.2)).max(1.0);
+                let mut density: Vec<f64> = (0..n)
+                    .map(|i| 1.0 / n as f64 + results.iter()
+                        .map(|r| (-0.5 * ((i as f64 - self.points[*r][d] as f64) / bandwidth).powi(2)).exp())
+                        .sum::<f64>())
+                    .collect();
+                let total: f64 = density.iter().sum();
+                density.iter_mut().for_each(|p| *p /= total);
+                density
+            })
+            .collect()
+    }
+}
+
+/// Index drawn with the probabilities of a density
+fn sample(rng: &mut StdRng, density: &[f64]) -> usize {
+    let mut target = rng.random::<f64>();
+    for (i, p) in density.iter().enumerate() {
+        target -= p;
+        if target < 0.0 {
+            return i;
+        }
+    }
+    density.len() - 1
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::scanner::{Backtest, ParamGrid};
+    use crate::strategy::StrategyRegistry;
+    use crate::test_fixtures::{bars, query};
+    use super::*;
+
+    /// 120 daily closes swinging 10 points around a line rising a tenth of a point a day
+    fn prices() -> DataFrame {
+        let close: Vec<f32> = (0..120).map(|i| 100.0 + 10.0 * (i as f32 / 8.0).sin() + 0.1 * i as f32).collect();
+        bars(&close)
+    }
+
+    /// Moving average crossings over 3 short and 5 long windows
+    fn search(spec: &StrategySpec) -> GridSearch {
+        let raw = query(&[("short_ma_to", "30"), ("long_ma_from", "20"), ("long_ma_to", "60")]);
+        GridSearch::new(ParamGrid::space_from_query(spec, &raw).unwrap(), Backtest::new())
+    }
+
+    fn optimize(method: SearchMethod, budget: usize, seed: u64) -> OptimizerRun {
+        let spec = StrategyRegistry::global().get("sma").unwrap();
+        let optimizer = Optimizer { method, budget, seed, population: 4, ..Optimizer::default() };
+        optimizer.run(&search(spec), &prices(), spec).unwrap()
+    }
+
+    fn tried(run: &OptimizerRun) -> Vec<(StrategyParams, f32)> {
+        run.results.iter().map(|r| (r.params.clone(), r.score)).collect()
+    }
+
+    #[test]
+    fn same_seed_gives_the_same_results() {
+        for method in [SearchMethod::Random, SearchMethod::Genetic, SearchMethod::Tpe] {
+            let (first, second) = (optimize(method, 8, 42), optimize(method, 8, 42));
+            assert_eq!(first.seed, 42);
+            assert_eq!(tried(&first), tried(&second), "{:?}", method);
+        }
+    }
+
+    #[test]
+    fn budget_bounds_the_backtests() {
+        for method in [SearchMethod::Random, SearchMethod::Genetic, SearchMethod::Tpe] {
+            let run = optimize(method, 8, 7);
+            assert_eq!(run.evaluations, 8, "{:?}", method);
+            assert_eq!(run.results.len(), run.evaluations);
+            let unique: HashSet<String> = run.results.iter().map(|r| format!("{:?}", r.params)).collect();
+            assert_eq!(unique.len(), run.evaluations, "{:?} tried a combination twice", method);
+        }
+        // A budget beyond the grid stops once every combination was tried, three of them break short_ma < long_ma
+        let run = optimize(SearchMethod::Random, 100, 7);
+        assert_eq!(run.evaluations + run.rejected, 12);
+        assert!(run.results.iter().all(|r| r.params.get("short_ma") < r.params.get("long_ma")));
+    }
+
+    #[test]
+    fn progress_counts_the_recorded_backtests() {
+        for (budget, claimed) in [(8, 8), (100, 12)] {
+            let progress = Arc::new(Progress::default());
+            let run = {
+                let _guard = progress.enter();
+                optimize(SearchMethod::Genetic, budget, 7)
+            };
+            let snapshot = progress.snapshot();
+            assert_eq!((snapshot.done, snapshot.total), (run.evaluations, claimed));
+        }
+    }
+}
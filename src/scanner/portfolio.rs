@@ -0,0 +1,463 @@
+use std::collections::{HashMap, HashSet};
+use polars::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::strategy::{indicators, BUY_SIGNAL, SELL_SIGNAL};
+use super::backtest::{Execution, DEFAULT_CAPITAL};
+use super::{BacktestRun, CostModel, FillBar, Sizing};
+
+/// How the equity is split between the symbols that have an open signal
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Allocation {
+    #[default]
+    EqualWeight,
+    /// Weights proportional to the inverse of the recent volatility of daily returns
+    InverseVolatility,
+    /// Weights proportional to the share of a symbol's strategies that are long
+    SignalStrength,
+}
+
+impl Allocation {
+    pub fn parse(raw: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        match raw {
+            "equal_weight" => Ok(Allocation::EqualWeight),
+            "inverse_volatility" => Ok(Allocation::InverseVolatility),
+            "signal_strength" => Ok(Allocation::SignalStrength),
+            other => Err(format!("Unknown allocation '{}'", other).into()),
+        }
+    }
+}
+
+/// One symbol of the basket and the signals of every strategy run on it
+pub struct PortfolioLeg {
+    pub symbol: String,
+    /// Signal frames with their signal column, each frame keeps the symbol's price columns
+    pub signals: Vec<(DataFrame, String)>,
+}
+
+/// Shares bought, positive, or sold, negative, on one bar
+#[derive(Serialize, Debug, Clone)]
+pub struct Fill {
+    pub datetime: String,
+    pub symbol: String,
+    pub shares: f64,
+    pub price: f64,
+    pub commission: f64,
+    pub slippage: f64,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct SymbolContribution {
+    pub symbol: String,
+    pub signals: Vec<String>,
+    /// Realized and unrealized profit after costs
+    pub pnl: f64,
+    /// Profit as a percentage of the portfolio's initial capital
+    pub contribution_pct: f32,
+    pub fills: usize,
+    /// Share of bars with shares held
+    pub exposure_pct: f32,
+    /// Average share of the portfolio equity held in the symbol
+    pub avg_weight_pct: f32,
+    pub final_shares: f64,
+}
+
+pub struct PortfolioRun {
+    /// Portfolio equity curve, its `shares` column counts the shares held across all symbols
+    pub run: BacktestRun,
+    pub fills: Vec<Fill>,
+    pub symbols: Vec<SymbolContribution>,
+}
+
+/// Runs every leg from one shared cash pool, long only and in whole shares, on the bars all the
+/// symbols have in common. A symbol is held while at least one of its strategies is long, a new
+/// signal buys it at its target weight with the cash available and a symbol whose strategies are
+/// all out is sold. Every `rebalance_bars` all holdings are traded back to their target weights.
+#[derive(Serialize, Debug, Clone)]
+pub struct Portfolio {
+    pub initial_capital: f64,
+    pub allocation: Allocation,
+    /// Bars of daily returns behind the inverse volatility weights
+    pub vol_window: usize,
+    /// Bars between rebalances, 0 only trades on signals
+    pub rebalance_bars: usize,
+    /// Largest share of the equity one symbol gets, the rest stays in cash
+    pub max_weight_pct: f64,
+    /// Most symbols held at once, the strongest signals are kept and holdings win ties
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_positions: Option<usize>,
+    pub execution: Execution,
+    pub costs: CostModel,
+}
+
+impl Default for Portfolio {
+    fn default() -> Self {
+        Portfolio {
+            initial_capital: DEFAULT_CAPITAL,
+            allocation: Allocation::default(),
+            vol_window: 20,
+            rebalance_bars: 0,
+            max_weight_pct: 100.0,
+            max_positions: None,
+            execution: Execution::default(),
+            costs: CostModel::default(),
+        }
+    }
+}
+
+fn parse_usize(raw: &HashMap<String, String>, key: &str) -> Result<Option<usize>, Box<dyn std::error::Error>> {
+    raw.get(key)
+        .map(|value| value.parse().map_err(|e| format!("Invalid {}: {}", key, e).into()))
+        .transpose()
+}
+
+/// Prices and long state of a leg on the common bars, in chronological order
+struct LegSeries {
+    open: Vec<f64>,
+    close: Vec<f64>,
+    bars: Vec<FillBar>,
+    /// Share of the leg's strategies that are long after each bar
+    strength: Vec<f64>,
+}
+
+impl LegSeries {
+    fn new(leg: &PortfolioLeg, calendar: &[String]) -> Result<Self, Box<dyn std::error::Error>> {
+        let (prices, _) = leg.signals.first().ok_or_else(|| format!("{} has no strategy", leg.symbol))?;
+        let rows = datetime_rows(prices)?;
+        let at = |values: &[Option<f32>], date: &String| values[rows[date]];
+        let (open, close, high, low) = (
+            indicators::column_f32(prices, "open")?,
+            indicators::column_f32(prices, "close")?,
+            indicators::column_f32(prices, "high")?,
+            indicators::column_f32(prices, "low")?,
+        );
+        let volume = match prices.column("volume") {
+            Ok(_) => indicators::column_f32(prices, "volume")?,
+            Err(_) => vec![None; prices.height()],
+        };
+
+        let mut strength = vec![0.0; calendar.len()];
+        for (df, sig_col) in &leg.signals {
+            let long = long_state(df, sig_col)?;
+            let rows = datetime_rows(df)?;
+            for (t, date) in calendar.iter().enumerate() {
+                if rows.get(date).is_some_and(|row| long[*row]) {
+                    strength[t] += 1.0 / leg.signals.len() as f64;
+                }
+            }
+        }
+        let price = |values: &[Option<f32>], date: &String| -> Result<f64, Box<dyn std::error::Error>> {
+            at(values, date).map(|v| v as f64).ok_or_else(|| format!("{} has no price on {}", leg.symbol, date).into())
+        };
+        Ok(LegSeries {
+            open: calendar.iter().map(|date| price(&open, date)).collect::<Result<_, _>>()?,
+            close: calendar.iter().map(|date| price(&close, date)).collect::<Result<_, _>>()?,
+            bars: calendar.iter()
+                .map(|date| FillBar { high: at(&high, date), low: at(&low, date), volume: at(&volume, date) })
+                .collect(),
+            strength,
+        })
+    }
+
+    /// Standard deviation of the daily returns of the `window` bars up to `t`
+    fn volatility(&self, t: usize, window: usize) -> Option<f64> {
+        let start = (t + 1).saturating_sub(window + 1);
+        let returns: Vec<f64> = self.close[start..=t].windows(2).map(|w| w[1] / w[0] - 1.0).collect();
+        if returns.len() < 2 {
+            return None;
+        }
+        let mean = returns.iter().sum::<f64>() / returns.len() as f64;
+        Some((returns.iter().map(|r| (r - mean).powi(2)).sum::<f64>() / returns.len() as f64).sqrt())
+    }
+}
+
+fn datetime_rows(df: &DataFrame) -> Result<HashMap<String, usize>, Box<dyn std::error::Error>> {
+    let datetime = df.column("datetime")?.str()?;
+    Ok(datetime.into_iter()
+        .enumerate()
+        .filter_map(|(row, dt)| Some((dt?.to_string(), row)))
+        .collect())
+}
+
+/// Whether the strategy is long after each row, a buy opens and a sell closes
+fn long_state(df: &DataFrame, sig_col: &str) -> Result<Vec<bool>, Box<dyn std::error::Error>> {
+    let signals = df.column(sig_col)?.cast(&DataType::Int32)?;
+    let signals = signals.i32()?;
+    let mut long = vec![false; df.height()];
+    let mut is_long = false;
+    for row in indicators::chronological_rows(df)? {
+        match signals.get(row) {
+            Some(BUY_SIGNAL) => is_long = true,
+            Some(SELL_SIGNAL) => is_long = false,
+            _ => {}
+        }
+        long[row] = is_long;
+    }
+    Ok(long)
+}
+
+impl Portfolio {
+    /// Reads `capital`, `allocation`, `vol_window`, `rebalance_bars`, `max_weight_pct`,
+    /// `max_positions`, `execution` and the cost model from a query string
+    pub fn from_query(raw: &HashMap<String, String>) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut portfolio = Portfolio::default();
+        if let Some(capital) = raw.get("capital") {
+            portfolio.initial_capital = capital.parse().map_err(|e| format!("Invalid capital: {}", e))?;
+            if !portfolio.initial_capital.is_finite() || portfolio.initial_capital <= 0.0 {
+                return Err("capital must be positive".into());
+            }
+        }
+        if let Some(allocation) = raw.get("allocation") {
+            portfolio.allocation = Allocation::parse(allocation)?;
+        }
+        if let Some(vol_window) = parse_usize(raw, "vol_window")? {
+            if vol_window < 2 {
+                return Err("vol_window must be at least 2".into());
+            }
+            portfolio.vol_window = vol_window;
+        }
+        portfolio.rebalance_bars = parse_usize(raw, "rebalance_bars")?.unwrap_or(0);
+        if let Some(max_weight) = raw.get("max_weight_pct") {
+            portfolio.max_weight_pct = max_weight.parse().map_err(|e| format!("Invalid max_weight_pct: {}", e))?;
+            if portfolio.max_weight_pct.is_nan() || portfolio.max_weight_pct <= 0.0 || portfolio.max_weight_pct > 100.0 {
+                return Err("max_weight_pct must be within (0, 100]".into());
+            }
+        }
+        portfolio.max_positions = parse_usize(raw, "max_positions")?;
+        if portfolio.max_positions == Some(0) {
+            return Err("max_positions must be positive".into());
+        }
+        if let Some(execution) = raw.get("execution") {
+            portfolio.execution = Execution::parse(execution)?;
+        }
+        portfolio.costs = CostModel::from_query(raw)?;
+        Ok(portfolio)
+    }
+
+    /// Target weight of every leg, zero for the legs that should not be held
+    fn target_weights(&self, legs: &[LegSeries], held: &[bool], t: usize) -> Vec<f64> {
+        let mut eligible: Vec<usize> = (0..legs.len()).filter(|i| legs[*i].strength[t] > 0.0).collect();
+        if let Some(max_positions) = self.max_positions {
+            eligible.sort_by(|a, b| {
+                legs[*b].strength[t].total_cmp(&legs[*a].strength[t]).then(held[*b].cmp(&held[*a]))
+            });
+            eligible.truncate(max_positions);
+        }
+
+        let volatilities: Option<Vec<f64>> = eligible.iter()
+            .map(|i| legs[*i].volatility(t, self.vol_window).filter(|vol| *vol > 0.0))
+            .collect();
+        let raw_weights: Vec<f64> = match (self.allocation, volatilities) {
+            (Allocation::InverseVolatility, Some(volatilities)) => volatilities.iter().map(|vol| 1.0 / vol).collect(),
+            (Allocation::SignalStrength, _) => eligible.iter().map(|i| legs[*i].strength[t]).collect(),
+            // Too few bars for a volatility falls back to equal weights
+            _ => vec![1.0; eligible.len()],
+        };
+        let total: f64 = raw_weights.iter().sum();
+        let mut weights = vec![0.0; legs.len()];
+        for (i, weight) in eligible.iter().zip(raw_weights) {
+            weights[*i] = (weight / total).min(self.max_weight_pct / 100.0);
+        }
+        weights
+    }
+
+    pub fn run(&self, legs: &[PortfolioLeg]) -> Result<PortfolioRun, Box<dyn std::error::Error>> {
+        if legs.is_empty() {
+            return Err("A portfolio needs at least one symbol".into());
+        }
+        let mut common: Option<HashSet<String>> = None;
+        for leg in legs {
+            let (prices, _) = leg.signals.first().ok_or_else(|| format!("{} has no strategy", leg.symbol))?;
+            let dates: HashSet<String> = datetime_rows(prices)?.into_keys().collect();
+            common = Some(match common {
+                Some(common) => common.intersection(&dates).cloned().collect(),
+                None => dates,
+            });
+        }
+        let mut calendar: Vec<String> = common.unwrap_or_default().into_iter().collect();
+        calendar.sort();
+        if calendar.is_empty() {
+            return Err("The symbols have no bars in common".into());
+        }
+        let series: Vec<LegSeries> = legs.iter().map(|leg| LegSeries::new(leg, &calendar)).collect::<Result<_, _>>()?;
+
+        let n = legs.len();
+        let mut cash = self.initial_capital;
+        let mut shares = vec![0.0f64; n];
+        let mut flows = vec![0.0f64; n];
+        let mut fill_counts = vec![0usize; n];
+        let mut held_bars = vec![0usize; n];
+        let mut weight_sums = vec![0.0f64; n];
+        let mut fills = Vec::new();
+        let mut previous_active: Option<Vec<bool>> = None;
+        let (mut curve_cash, mut curve_shares, mut curve_equity) = (Vec::new(), Vec::new(), Vec::new());
+
+        for (t, datetime) in calendar.iter().enumerate() {
+            let decision = match self.execution {
+                Execution::SameClose => Some(t),
+                _ => t.checked_sub(1),
+            };
+            if let Some(d) = decision {
+                let active: Vec<bool> = series.iter().map(|leg| leg.strength[d] > 0.0).collect();
+                let signal_changed = previous_active.as_ref() != Some(&active);
+                let rebalance = self.rebalance_bars > 0 && t % self.rebalance_bars == 0;
+                if signal_changed || rebalance {
+                    let price: Vec<f64> = series.iter()
+                        .map(|leg| match self.execution {
+                            Execution::NextOpen => leg.open[t],
+                            _ => leg.close[t],
+                        })
+                        .collect();
+                    let held: Vec<bool> = shares.iter().map(|s| *s > 0.0).collect();
+                    let weights = self.target_weights(&series, &held, d);
+                    let equity = cash + (0..n).map(|i| shares[i] * price[i]).sum::<f64>();
+                    let targets: Vec<f64> = (0..n).map(|i| {
+                        let wanted = (weights[i] * equity / price[i]).floor();
+                        match (rebalance, held[i], weights[i] > 0.0) {
+                            (true, _, _) | (false, false, true) | (false, true, false) => wanted,
+                            _ => shares[i],
+                        }
+                    }).collect();
+
+                    // Sells first so their cash is there for the buys
+                    let mut order: Vec<usize> = (0..n).collect();
+                    order.sort_by_key(|i| targets[*i] > shares[*i]);
+                    for i in order {
+                        let delta = targets[i] - shares[i];
+                        if delta == 0.0 {
+                            continue;
+                        }
+                        let slippage = self.costs.slippage.per_share(price[i], &series[i].bars[t], delta.abs())?;
+                        let fill_price = (price[i] + delta.signum() * slippage).max(0.0);
+                        let quantity = if delta > 0.0 {
+                            delta.min(self.costs.affordable_shares(cash, fill_price))
+                        } else {
+                            delta
+                        };
+                        if quantity == 0.0 {
+                            continue;
+                        }
+                        let commission = self.costs.commission.fee(quantity.abs(), fill_price);
+                        let cash_flow = -quantity * fill_price - commission;
+                        cash += cash_flow;
+                        flows[i] += cash_flow;
+                        shares[i] += quantity;
+                        fill_counts[i] += 1;
+                        fills.push(Fill {
+                            datetime: datetime.clone(),
+                            symbol: legs[i].symbol.clone(),
+                            shares: quantity,
+                            price: fill_price,
+                            commission,
+                            slippage: slippage * quantity.abs(),
+                        });
+                    }
+                }
+                previous_active = Some(active);
+            }
+
+            let equity = cash + (0..n).map(|i| shares[i] * series[i].close[t]).sum::<f64>();
+            for i in 0..n {
+                if shares[i] > 0.0 {
+                    held_bars[i] += 1;
+                    weight_sums[i] += shares[i] * series[i].close[t] / equity;
+                }
+            }
+            curve_cash.push(cash);
+            curve_shares.push(shares.iter().sum::<f64>());
+            curve_equity.push(equity);
+        }
+
+        let last = calendar.len() - 1;
+        let final_equity = curve_equity[last];
+        let symbols = (0..n).map(|i| {
+            let pnl = flows[i] + shares[i] * series[i].close[last];
+            SymbolContribution {
+                symbol: legs[i].symbol.clone(),
+                signals: legs[i].signals.iter().map(|(_, sig_col)| sig_col.clone()).collect(),
+                pnl,
+                contribution_pct: (100.0 * pnl / self.initial_capital) as f32,
+                fills: fill_counts[i],
+                exposure_pct: 100.0 * held_bars[i] as f32 / calendar.len() as f32,
+                avg_weight_pct: (100.0 * weight_sums[i] / calendar.len() as f64) as f32,
+                final_shares: shares[i],
+            }
+        }).collect();
+
+        // Newest first like the cached prices
+        let equity_curve = df!(
+            "datetime" => calendar.iter().rev().cloned().collect::<Vec<String>>(),
+            "cash" => curve_cash.into_iter().rev().collect::<Vec<f64>>(),
+            "shares" => curve_shares.into_iter().rev().collect::<Vec<f64>>(),
+            "equity" => curve_equity.into_iter().rev().collect::<Vec<f64>>()
+        )?;
+        Ok(PortfolioRun {
+            run: BacktestRun {
+                signal: "portfolio".to_string(),
+                initial_capital: self.initial_capital,
+                final_equity,
+                total_return_pct: (100.0 * (final_equity / self.initial_capital - 1.0)) as f32,
+                equity_curve,
+                trades: Vec::new(),
+                sizing: Sizing::default(),
+                regimes: None,
+            },
+            fills,
+            symbols,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_fixtures::{bars, with_signals};
+    use super::*;
+
+    fn series(strength: f64) -> LegSeries {
+        LegSeries {
+            open: vec![100.0],
+            close: vec![100.0],
+            bars: vec![FillBar { high: Some(100.0), low: Some(100.0), volume: None }],
+            strength: vec![strength],
+        }
+    }
+
+    /// A leg closing at 100 on every bar with one strategy's signals given oldest first
+    fn leg(symbol: &str, signals: &[i32]) -> PortfolioLeg {
+        let df = with_signals(bars(&vec![100.0; signals.len()]), "Sig", signals);
+        PortfolioLeg { symbol: symbol.to_string(), signals: vec![(df, "Sig".to_string())] }
+    }
+
+    #[test]
+    fn target_weights_are_capped() {
+        let portfolio = Portfolio { max_weight_pct: 30.0, ..Portfolio::default() };
+        let legs = [series(1.0), series(1.0), series(0.0)];
+        assert_eq!(portfolio.target_weights(&legs, &[false; 3], 0), vec![0.3, 0.3, 0.0]);
+        let uncapped = Portfolio::default().target_weights(&legs, &[false; 3], 0);
+        assert_eq!(uncapped, vec![0.5, 0.5, 0.0]);
+    }
+
+    #[test]
+    fn max_positions_keeps_the_strongest_and_then_the_held() {
+        let portfolio = Portfolio { max_positions: Some(2), allocation: Allocation::SignalStrength, ..Portfolio::default() };
+        let legs = [series(0.5), series(1.0), series(0.5)];
+        let weights = portfolio.target_weights(&legs, &[false, false, true], 0);
+        assert_eq!(weights[0], 0.0);
+        assert!(weights[1] > 0.0 && weights[2] > 0.0);
+        let weights = portfolio.target_weights(&legs, &[true, false, false], 0);
+        assert!(weights[0] > 0.0 && weights[1] > 0.0);
+        assert_eq!(weights[2], 0.0);
+    }
+
+    #[test]
+    fn sells_fill_before_buys() {
+        // All the cash is in AAA when it is sold on the bar BBB is bought
+        let portfolio = Portfolio { initial_capital: 1_000.0, execution: Execution::SameClose, ..Portfolio::default() };
+        let legs = [leg("BBB", &[0, BUY_SIGNAL, 0]), leg("AAA", &[BUY_SIGNAL, SELL_SIGNAL, 0])];
+        let run = portfolio.run(&legs).unwrap();
+        let fills: Vec<(&str, f64)> = run.fills.iter().map(|fill| (fill.symbol.as_str(), fill.shares)).collect();
+        assert_eq!(fills, vec![("AAA", 10.0), ("AAA", -10.0), ("BBB", 10.0)]);
+        assert_eq!(run.symbols[0].final_shares, 10.0);
+    }
+}
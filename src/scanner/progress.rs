@@ -0,0 +1,96 @@
+use std::{cell::RefCell, sync::{atomic::{AtomicBool, AtomicUsize, Ordering}, Arc}};
+use serde::Serialize;
+
+thread_local! {
+    static CURRENT: RefCell<Option<Arc<Progress>>> = const { RefCell::new(None) };
+}
+
+/// Backtests done out of the ones planned so far
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Default)]
+pub struct ProgressSnapshot {
+    pub done: usize,
+    pub total: usize,
+    pub cancelled: bool,
+}
+
+/// Backtests done by a long running scan and whether it should stop. Scans pick up the progress
+/// entered on the thread that runs them, scans without one run as usual.
+#[derive(Debug, Default)]
+pub struct Progress {
+    done: AtomicUsize,
+    total: AtomicUsize,
+    /// Planned backtests not claimed by a scan yet
+    reserved: AtomicUsize,
+    cancelled: AtomicBool,
+}
+
+impl Progress {
+    /// Progress entered on this thread, if any
+    pub fn current() -> Option<Arc<Progress>> {
+        CURRENT.with(|current| current.borrow().clone())
+    }
+
+    /// Makes this the current progress of the thread until the guard is dropped
+    pub fn enter(self: &Arc<Self>) -> ProgressGuard {
+        let previous = CURRENT.with(|current| current.replace(Some(self.clone())));
+        ProgressGuard { previous }
+    }
+
+    /// Plans backtests ahead of the scans that will claim them, so the total does not grow as
+    /// they start one after the other
+    pub fn reserve(&self, backtests: usize) {
+        self.total.fetch_add(backtests, Ordering::Relaxed);
+        self.reserved.fetch_add(backtests, Ordering::Relaxed);
+    }
+
+    /// Backtests a scan is about to run, taken from the reserved ones first
+    pub fn claim(&self, backtests: usize) {
+        let reserved = self.reserved.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |r| Some(r.saturating_sub(backtests))).unwrap();
+        self.total.fetch_add(backtests.saturating_sub(reserved), Ordering::Relaxed);
+    }
+
+    pub fn advance(&self) {
+        self.done.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Marks every planned backtest as done, for scans that finished early
+    pub fn complete(&self) {
+        self.done.fetch_max(self.total.load(Ordering::Relaxed), Ordering::Relaxed);
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    pub fn snapshot(&self) -> ProgressSnapshot {
+        ProgressSnapshot {
+            done: self.done.load(Ordering::Relaxed),
+            total: self.total.load(Ordering::Relaxed),
+            cancelled: self.is_cancelled(),
+        }
+    }
+}
+
+/// Restores the progress the thread had before `Progress::enter`
+pub struct ProgressGuard {
+    previous: Option<Arc<Progress>>,
+}
+
+impl Drop for ProgressGuard {
+    fn drop(&mut self) {
+        CURRENT.with(|current| current.replace(self.previous.take()));
+    }
+}
+
+/// Whether the progress of a scan was cancelled
+pub(super) fn cancelled(progress: Option<&Arc<Progress>>) -> bool {
+    progress.is_some_and(|progress| progress.is_cancelled())
+}
+
+pub(super) fn cancelled_error() -> Box<dyn std::error::Error> {
+    "The scan was cancelled".into()
+}
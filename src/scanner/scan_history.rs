@@ -0,0 +1,178 @@
+use polars::prelude::*;
+use serde::Serialize;
+
+use crate::strategy::{indicators, StrategyParams};
+use super::walk_forward::datetime_at;
+use super::{GridResult, Metric, ParamGrid, ParamRange, Selection};
+
+/// One row per bestperf scan holding the combination it selected
+pub const SCAN_RESULTS_TABLE: &str = "scan_results";
+
+/// Inputs that decide the outcome of a scan, a stored scan with the same key is reused
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct ScanKey {
+    pub strategy: String,
+    pub symbol: String,
+    /// First and last datetime of the scanned prices
+    pub data_start: String,
+    pub data_end: String,
+    pub metric: Metric,
+    pub selection: Selection,
+    pub radius: usize,
+    /// Parameter ranges that were scanned
+    pub grid: Vec<ParamRange>,
+}
+
+impl ScanKey {
+    pub fn new(strategy: &str, symbol: &str, df: &DataFrame, metric: Metric, selection: Selection, radius: usize, grid: &ParamGrid) -> Result<Self, Box<dyn std::error::Error>> {
+        if df.height() == 0 {
+            return Err(format!("No prices for {}", symbol).into());
+        }
+        Ok(ScanKey {
+            strategy: strategy.to_string(),
+            symbol: symbol.to_string(),
+            data_start: datetime_at(df, 0)?,
+            data_end: datetime_at(df, df.height() - 1)?,
+            metric,
+            selection,
+            radius,
+            grid: grid.ranges.clone(),
+        })
+    }
+}
+
+/// Selected combination of one scan and when it ran. Only the selected combination is kept on
+/// purpose: a repeat request rebuilds its signals from it, the ranked results of a grid are what
+/// `/optimize` returns.
+#[derive(Serialize, Debug, Clone)]
+pub struct ScanRecord {
+    pub run_at: String,
+    #[serde(flatten)]
+    pub key: ScanKey,
+    pub params: StrategyParams,
+    pub score: Option<f32>,
+    pub metrics: serde_json::Value,
+}
+
+impl ScanRecord {
+    pub fn new(run_at: String, key: ScanKey, result: &GridResult) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(ScanRecord {
+            run_at,
+            key,
+            params: result.params.clone(),
+            score: Some(result.score),
+            metrics: serde_json::to_value(&result.metrics)?,
+        })
+    }
+
+    /// Row of the scan results table, parameters and metrics are stored as JSON
+    pub fn to_df(&self) -> Result<DataFrame, Box<dyn std::error::Error>> {
+        let df = df!(
+            "run_at" => [self.run_at.as_str()],
+            "strategy" => [self.key.strategy.as_str()],
+            "symbol" => [self.key.symbol.as_str()],
+            "data_start" => [self.key.data_start.as_str()],
+            "data_end" => [self.key.data_end.as_str()],
+            "metric" => [name(&self.key.metric)?],
+            "selection" => [name(&self.key.selection)?],
+            "radius" => [self.key.radius as i32],
+            "grid" => [serde_json::to_string(&self.key.grid)?],
+            "params" => [serde_json::to_string(&self.params)?],
+            "score" => [self.score],
+            "metrics" => [self.metrics.to_string()],
+        )?;
+        Ok(df)
+    }
+
+    /// Rows of the scan results table, oldest first
+    pub fn from_df(df: &DataFrame) -> Result<Vec<Self>, Box<dyn std::error::Error>> {
+        let text = |name: &str| -> Result<Vec<String>, Box<dyn std::error::Error>> {
+            Ok(df.column(name)?.str()?.into_iter().map(|v| v.unwrap_or_default().to_string()).collect())
+        };
+        let (run_at, strategy, symbol) = (text("run_at")?, text("strategy")?, text("symbol")?);
+        let (data_start, data_end, metric, selection) = (text("data_start")?, text("data_end")?, text("metric")?, text("selection")?);
+        let (grid, params, metrics) = (text("grid")?, text("params")?, text("metrics")?);
+        let radius = df.column("radius")?.cast(&DataType::Int32)?;
+        let score = indicators::column_f32(df, "score")?;
+        let mut records = (0..df.height())
+            .map(|i| Ok(ScanRecord {
+                run_at: run_at[i].clone(),
+                key: ScanKey {
+                    strategy: strategy[i].clone(),
+                    symbol: symbol[i].clone(),
+                    data_start: data_start[i].clone(),
+                    data_end: data_end[i].clone(),
+                    metric: serde_json::from_value(serde_json::Value::String(metric[i].clone()))?,
+                    selection: serde_json::from_value(serde_json::Value::String(selection[i].clone()))?,
+                    radius: radius.i32()?.get(i).unwrap_or_default() as usize,
+                    grid: serde_json::from_str(&grid[i])?,
+                },
+                params: serde_json::from_str(&params[i])?,
+                score: score[i],
+                metrics: serde_json::from_str(&metrics[i])?,
+            }))
+            .collect::<Result<Vec<_>, Box<dyn std::error::Error>>>()?;
+        records.sort_by(|a, b| a.run_at.cmp(&b.run_at));
+        Ok(records)
+    }
+
+    /// Latest stored scan with the same key
+    pub fn find(records: Vec<Self>, key: &ScanKey) -> Option<Self> {
+        records.into_iter().rev().find(|record| &record.key == key)
+    }
+}
+
+/// Serialized name of a unit enum variant, such as `total_return`
+fn name<T: Serialize>(value: &T) -> Result<String, Box<dyn std::error::Error>> {
+    Ok(serde_json::to_value(value)?.as_str().ok_or("Not a unit variant")?.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::scanner::PerformanceMetrics;
+    use crate::strategy::StrategyRegistry;
+    use crate::test_fixtures::{bars, day, query};
+    use super::*;
+
+    fn key(raw: &[(&str, &str)]) -> ScanKey {
+        let grid = ParamGrid::from_query(StrategyRegistry::global().get("sma").unwrap(), &query(raw)).unwrap();
+        ScanKey::new("sma", "AAPL", &bars(&[100.0; 5]), Metric::Sharpe, Selection::Stable, 2, &grid).unwrap()
+    }
+
+    fn record(run_at: &str, key: ScanKey, score: f32) -> ScanRecord {
+        let mut params = StrategyParams::default();
+        params.set("short_ma", 10.0);
+        params.set("long_ma", 20.0);
+        let metrics = PerformanceMetrics { trades: 4, sharpe: Some(1.5), ..Default::default() };
+        ScanRecord::new(run_at.to_string(), key, &GridResult { rank: 1, params, score, metrics }).unwrap()
+    }
+
+    #[test]
+    fn records_survive_the_table() {
+        let key = key(&[("short_ma_to", "30")]);
+        assert_eq!((key.data_start.as_str(), key.data_end.as_str()), (day(0).as_str(), day(4).as_str()));
+        let mut df = record("2024-03-02 10:00:00", key.clone(), 2.0).to_df().unwrap();
+        df.vstack_mut(&record("2024-03-01 10:00:00", key.clone(), 1.0).to_df().unwrap()).unwrap();
+
+        let records = ScanRecord::from_df(&df).unwrap();
+        assert_eq!(records.len(), 2);
+        let first = &records[0];
+        assert_eq!(first.run_at, "2024-03-01 10:00:00");
+        assert_eq!(first.key, key);
+        assert_eq!((first.params.get("short_ma"), first.params.get("long_ma")), (Some(10.0), Some(20.0)));
+        assert_eq!(first.score, Some(1.0));
+        assert_eq!(first.metrics["trades"], 4);
+        assert_eq!(first.metrics["sharpe"], 1.5);
+    }
+
+    #[test]
+    fn find_takes_the_latest_scan_of_the_same_grid() {
+        let key = key(&[("short_ma_to", "30")]);
+        let records = vec![
+            record("2024-03-01 10:00:00", key.clone(), 1.0),
+            record("2024-03-02 10:00:00", key.clone(), 2.0),
+        ];
+        assert_eq!(ScanRecord::find(records.clone(), &key).unwrap().score, Some(2.0));
+        assert!(ScanRecord::find(records, &self::key(&[("short_ma_to", "40")])).is_none());
+    }
+}
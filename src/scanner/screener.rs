@@ -0,0 +1,328 @@
+use std::{cmp::Ordering, collections::HashMap};
+use indexmap::IndexMap;
+use polars::prelude::*;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::strategy::{indicators, Strategy, BUY_SIGNAL, SELL_SIGNAL};
+use super::grid_search::worker_pool;
+
+/// Price columns and intermediate results of the strategies, never listed as indicators
+const HIDDEN_COLUMNS: [&str; 11] = ["open", "high", "low", "close", "volume", "delta", "gain", "loss", "avg_", "RS_", "Std"];
+
+/// What the strategy signalled on the latest bar of a symbol
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Trigger {
+    Buy,
+    Sell,
+    None,
+}
+
+impl Trigger {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Trigger::Buy => "buy",
+            Trigger::Sell => "sell",
+            Trigger::None => "none",
+        }
+    }
+
+    fn from_signal(signal: Option<i32>) -> Self {
+        match signal {
+            Some(BUY_SIGNAL) => Trigger::Buy,
+            Some(SELL_SIGNAL) => Trigger::Sell,
+            _ => Trigger::None,
+        }
+    }
+}
+
+/// Which triggers the screener keeps
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum SignalFilter {
+    Buy,
+    Sell,
+    /// A buy or a sell
+    #[default]
+    Triggered,
+    /// Every symbol, triggered or not
+    Any,
+}
+
+impl SignalFilter {
+    pub fn parse(raw: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        match raw {
+            "buy" => Ok(SignalFilter::Buy),
+            "sell" => Ok(SignalFilter::Sell),
+            "triggered" => Ok(SignalFilter::Triggered),
+            "any" => Ok(SignalFilter::Any),
+            other => Err(format!("Unknown signal filter '{}'", other).into()),
+        }
+    }
+
+    fn keeps(&self, trigger: Trigger) -> bool {
+        match self {
+            SignalFilter::Buy => trigger == Trigger::Buy,
+            SignalFilter::Sell => trigger == Trigger::Sell,
+            SignalFilter::Triggered => trigger != Trigger::None,
+            SignalFilter::Any => true,
+        }
+    }
+}
+
+/// Bound on a value of the latest bar, such as `RSI_14<30`
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct ValueFilter {
+    pub column: String,
+    pub op: String,
+    pub value: f32,
+}
+
+impl ValueFilter {
+    fn parse(raw: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        for op in ["<=", ">=", "<", ">"] {
+            if let Some((column, value)) = raw.split_once(op) {
+                let value = value.trim().parse().map_err(|e| format!("Invalid filter value in '{}': {}", raw, e))?;
+                return Ok(ValueFilter { column: column.trim().to_string(), op: op.to_string(), value });
+            }
+        }
+        Err(format!("Filters look like column<value or column>=value, not '{}'", raw).into())
+    }
+
+    /// Symbols without the column fail the filter
+    fn keeps(&self, row: &ScreenerRow) -> bool {
+        row.value(&self.column).is_some_and(|v| match self.op.as_str() {
+            "<=" => v <= self.value,
+            ">=" => v >= self.value,
+            "<" => v < self.value,
+            _ => v > self.value,
+        })
+    }
+}
+
+/// Latest bar of one symbol
+#[derive(Serialize, Debug, Clone)]
+pub struct ScreenerRow {
+    pub symbol: String,
+    pub datetime: String,
+    pub close: Option<f32>,
+    pub trigger: Trigger,
+    /// Indicator values of the strategy on the latest bar
+    pub indicators: IndexMap<String, Option<f32>>,
+}
+
+impl ScreenerRow {
+    fn value(&self, column: &str) -> Option<f32> {
+        match column {
+            "close" => self.close,
+            _ => self.indicators.get(column).copied().flatten(),
+        }
+    }
+}
+
+/// A symbol whose prices or results could not be computed
+#[derive(Serialize, Debug, Clone)]
+pub struct SymbolError {
+    pub symbol: String,
+    pub error: String,
+}
+
+/// Symbols the screener kept, in order, and the ones it could not screen
+#[derive(Serialize, Debug, Clone)]
+pub struct ScreenerRun {
+    pub screened: usize,
+    pub rows: Vec<ScreenerRow>,
+    pub errors: Vec<SymbolError>,
+}
+
+/// Runs a strategy on many symbols and keeps the ones whose latest bar passes the filters
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct Screener {
+    pub signal: SignalFilter,
+    pub filters: Vec<ValueFilter>,
+    /// `symbol`, `close` or an indicator column, by symbol when not set
+    pub sort: Option<String>,
+    pub descending: bool,
+    pub limit: Option<usize>,
+}
+
+impl Screener {
+    /// Reads `signal`, comma separated `filter` bounds like `RSI_14<30,close>=5`, `sort`,
+    /// `order=asc|desc` and `limit`
+    pub fn from_query(raw: &HashMap<String, String>) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut screener = Screener::default();
+        if let Some(signal) = raw.get("signal") {
+            screener.signal = SignalFilter::parse(signal)?;
+        }
+        if let Some(filters) = raw.get("filter") {
+            screener.filters = filters.split(',')
+                .filter(|f| !f.trim().is_empty())
+                .map(ValueFilter::parse)
+                .collect::<Result<_, _>>()?;
+        }
+        screener.sort = raw.get("sort").cloned();
+        if let Some(order) = raw.get("order") {
+            screener.descending = match order.as_str() {
+                "asc" => false,
+                "desc" => true,
+                other => return Err(format!("Unknown order '{}'", other).into()),
+            };
+        }
+        if let Some(limit) = raw.get("limit") {
+            screener.limit = Some(limit.parse().map_err(|e| format!("Invalid limit: {}", e))?);
+        }
+        Ok(screener)
+    }
+
+    /// Builds and runs the strategy on every symbol's prices in parallel
+    pub fn run<F>(&self, prices: Vec<(String, DataFrame)>, build: F) -> Result<ScreenerRun, Box<dyn std::error::Error>>
+    where
+        F: Fn(DataFrame) -> Result<Box<dyn Strategy + Send>, Box<dyn std::error::Error>> + Sync
+    {
+        let screened = prices.len();
+        let outcomes: Vec<(String, Result<ScreenerRow, String>)> = worker_pool()?.install(|| prices
+            .into_par_iter()
+            .map(|(symbol, df)| {
+                if df.height() == 0 {
+                    return (symbol, Err("No prices".to_string()));
+                }
+                let row = build(df)
+                    .and_then(|mut strategy| {
+                        let signals = strategy.calc_signal()?;
+                        latest_row(&symbol, &signals, &strategy.signal_col())
+                    })
+                    .map_err(|e| e.to_string());
+                (symbol, row)
+            })
+            .collect());
+
+        let mut rows = Vec::new();
+        let mut errors = Vec::new();
+        for (symbol, outcome) in outcomes {
+            match outcome {
+                Ok(row) => rows.push(row),
+                Err(error) => errors.push(SymbolError { symbol, error }),
+            }
+        }
+        rows.retain(|row| self.signal.keeps(row.trigger) && self.filters.iter().all(|f| f.keeps(row)));
+        self.sort_rows(&mut rows);
+        if let Some(limit) = self.limit {
+            rows.truncate(limit);
+        }
+        Ok(ScreenerRun { screened, rows, errors })
+    }
+
+    /// Rows without a value for the sort column come last whatever the order
+    fn sort_rows(&self, rows: &mut [ScreenerRow]) {
+        let order = |ordering: Ordering| if self.descending { ordering.reverse() } else { ordering };
+        match self.sort.as_deref() {
+            None | Some("symbol") => rows.sort_by(|a, b| order(a.symbol.cmp(&b.symbol))),
+            Some(column) => rows.sort_by(|a, b| match (a.value(column), b.value(column)) {
+                (Some(x), Some(y)) => order(x.total_cmp(&y)),
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (None, None) => Ordering::Equal,
+            }.then_with(|| a.symbol.cmp(&b.symbol))),
+        }
+    }
+}
+
+/// Trigger, close and indicator values of the newest bar of a strategy's signals
+pub(super) fn latest_row(symbol: &str, df: &DataFrame, sig_col: &str) -> Result<ScreenerRow, Box<dyn std::error::Error>> {
+    let latest = *indicators::chronological_rows(df)?.last().ok_or("No prices")?;
+    let signal = df.column(sig_col)?.cast(&DataType::Int32)?.i32()?.get(latest);
+    let mut values = IndexMap::new();
+    for column in df.get_columns() {
+        let name = column.name().as_str();
+        if name == sig_col || name == "datetime" || !column.dtype().is_primitive_numeric()
+            || HIDDEN_COLUMNS.iter().any(|hidden| name.starts_with(hidden)) {
+            continue;
+        }
+        values.insert(name.to_string(), indicators::column_f32(df, name)?[latest]);
+    }
+    Ok(ScreenerRow {
+        symbol: symbol.to_string(),
+        datetime: df.column("datetime")?.str()?.get(latest).unwrap_or_default().to_string(),
+        close: indicators::column_f32(df, "close")?[latest],
+        trigger: Trigger::from_signal(signal),
+        indicators: values,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_fixtures::{bars, query, with_prices, with_signals};
+    use super::*;
+
+    /// Strategy whose signals were computed beforehand
+    struct Preset(DataFrame);
+
+    impl Strategy for Preset {
+        fn calc_signal(&mut self) -> Result<DataFrame, Box<dyn std::error::Error>> {
+            Ok(self.0.clone())
+        }
+
+        fn signal_col(&self) -> String {
+            "Sig".to_string()
+        }
+    }
+
+    /// Three bars at `close` whose last one has `signal`, with an `RSI_14` column when `rsi` is given
+    fn prices(close: f32, signal: i32, rsi: Option<f32>) -> DataFrame {
+        let df = with_signals(bars(&[close; 3]), "Sig", &[0, 0, signal]);
+        match rsi {
+            Some(rsi) => with_prices(df, "RSI_14", &[50.0, 50.0, rsi]),
+            None => df,
+        }
+    }
+
+    fn screen(pairs: &[(&str, &str)]) -> ScreenerRun {
+        let symbols = vec![
+            ("CCC".to_string(), prices(30.0, BUY_SIGNAL, Some(40.0))),
+            ("AAA".to_string(), prices(10.0, BUY_SIGNAL, Some(25.0))),
+            ("DDD".to_string(), prices(40.0, 0, None)),
+            ("BBB".to_string(), prices(20.0, SELL_SIGNAL, Some(75.0))),
+            ("EEE".to_string(), DataFrame::empty()),
+        ];
+        Screener::from_query(&query(pairs)).unwrap()
+            .run(symbols, |df| Ok(Box::new(Preset(df))))
+            .unwrap()
+    }
+
+    fn symbols(run: &ScreenerRun) -> Vec<&str> {
+        run.rows.iter().map(|row| row.symbol.as_str()).collect()
+    }
+
+    #[test]
+    fn triggered_symbols_are_kept_by_default() {
+        let run = screen(&[]);
+        assert_eq!(symbols(&run), ["AAA", "BBB", "CCC"]);
+        assert_eq!(run.screened, 5);
+        assert_eq!(run.errors.iter().map(|e| e.symbol.as_str()).collect::<Vec<&str>>(), ["EEE"]);
+        assert_eq!(run.rows[0].indicators.keys().collect::<Vec<&String>>(), ["RSI_14"]);
+        assert_eq!(run.rows[1].trigger, Trigger::Sell);
+    }
+
+    #[test]
+    fn filters_apply_to_the_latest_bar() {
+        assert_eq!(symbols(&screen(&[("signal", "buy"), ("filter", "RSI_14<30")])), ["AAA"]);
+        // DDD has no RSI_14 and fails any bound on it
+        assert_eq!(symbols(&screen(&[("signal", "any"), ("filter", "RSI_14<=40")])), ["AAA", "CCC"]);
+        assert_eq!(symbols(&screen(&[("signal", "any"), ("filter", "close>10, close<40")])), ["BBB", "CCC"]);
+    }
+
+    #[test]
+    fn missing_values_sort_last_in_either_order() {
+        assert_eq!(symbols(&screen(&[("signal", "any"), ("sort", "RSI_14")])), ["AAA", "CCC", "BBB", "DDD"]);
+        assert_eq!(symbols(&screen(&[("signal", "any"), ("sort", "RSI_14"), ("order", "desc")])), ["BBB", "CCC", "AAA", "DDD"]);
+        assert_eq!(symbols(&screen(&[("signal", "any"), ("sort", "close"), ("order", "desc"), ("limit", "2")])), ["DDD", "CCC"]);
+    }
+
+    #[test]
+    fn settings_are_checked() {
+        for pairs in [[("order", "up")], [("filter", "RSI_14=30")], [("signal", "hold")], [("limit", "-1")]] {
+            assert!(Screener::from_query(&query(&pairs)).is_err(), "{:?}", pairs);
+        }
+    }
+}
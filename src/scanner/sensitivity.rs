@@ -0,0 +1,233 @@
+use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+
+use crate::strategy::StrategyParams;
+use super::{GridResult, ParamGrid};
+
+pub const DEFAULT_RADIUS: usize = 1;
+const MAX_RADIUS: usize = 5;
+
+/// Which combination of a scan is picked as the best one
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Selection {
+    /// Highest score, even when it is an isolated spike
+    #[default]
+    Peak,
+    /// Highest robustness, the center of the best scoring region
+    Stable,
+}
+
+/// Scores of two parameters against each other, rows follow `y` and columns follow `x`
+#[derive(Serialize, Debug, Clone)]
+pub struct Heatmap {
+    pub x: String,
+    pub y: String,
+    pub x_values: Vec<f64>,
+    pub y_values: Vec<f64>,
+    /// Best score over the other parameters, None where no combination could be backtested
+    pub scores: Vec<Vec<Option<f32>>>,
+}
+
+/// How a combination and the combinations around it scored
+#[derive(Serialize, Debug, Clone)]
+pub struct Robustness {
+    /// 1 for the highest robustness
+    pub rank: usize,
+    pub params: StrategyParams,
+    pub score: f32,
+    /// Mean score of the neighborhood, the combination included
+    pub robustness: f32,
+    /// Worst score of the neighborhood
+    pub worst: f32,
+    /// Backtested combinations in the neighborhood, the combination included
+    pub neighbors: usize,
+}
+
+/// Neighborhoods of a scan are the combinations at most `radius` steps away on every parameter
+#[derive(Debug, Clone)]
+pub struct Sensitivity {
+    pub grid: ParamGrid,
+    pub radius: usize,
+}
+
+impl Sensitivity {
+    pub fn new(grid: ParamGrid, radius: usize) -> Self {
+        Sensitivity { grid, radius }
+    }
+
+    /// Reads `radius`, at most 5 steps
+    pub fn from_query(grid: ParamGrid, raw: &HashMap<String, String>) -> Result<Self, Box<dyn std::error::Error>> {
+        let radius = match raw.get("radius") {
+            Some(radius) => radius.parse().map_err(|e| format!("Invalid radius: {}", e))?,
+            None => DEFAULT_RADIUS,
+        };
+        if radius > MAX_RADIUS {
+            return Err(format!("radius must be at most {}", MAX_RADIUS).into());
+        }
+        Ok(Sensitivity::new(grid, radius))
+    }
+
+    /// Step of each parameter's value in its range
+    fn point(&self, params: &StrategyParams) -> Vec<usize> {
+        self.grid.ranges.iter()
+            .map(|range| ((params.get(&range.name).unwrap_or(range.from) - range.from) / range.step).round() as usize)
+            .collect()
+    }
+
+    /// Heatmap of parameters `x` and `y`, by default the first two of the grid
+    pub fn heatmap(&self, results: &[GridResult], x: Option<&str>, y: Option<&str>) -> Result<Heatmap, Box<dyn std::error::Error>> {
+        let axis = |name: Option<&str>, default: usize| match name {
+            Some(name) => self.grid.ranges.iter().position(|range| range.name == name)
+                .ok_or_else(|| format!("{} has no parameter {}", self.grid.strategy, name)),
+            None => self.grid.ranges.get(default).map(|_| default)
+                .ok_or_else(|| format!("{} has fewer than two parameters", self.grid.strategy)),
+        };
+        let (x, y) = (axis(x, 0)?, axis(y, 1)?);
+        if x == y {
+            return Err("x and y must be different parameters".into());
+        }
+        let (x_values, y_values) = (self.grid.ranges[x].values(), self.grid.ranges[y].values());
+        let mut scores = vec![vec![None; x_values.len()]; y_values.len()];
+        for result in results {
+            let point = self.point(&result.params);
+            let cell: &mut Option<f32> = &mut scores[point[y]][point[x]];
+            if cell.is_none_or(|best| result.score > best) {
+                *cell = Some(result.score);
+            }
+        }
+        Ok(Heatmap {
+            x: self.grid.ranges[x].name.clone(),
+            y: self.grid.ranges[y].name.clone(),
+            x_values,
+            y_values,
+            scores,
+        })
+    }
+
+    /// Every result with the mean and worst score of its neighborhood, most robust first. Combinations
+    /// the strategy rejected are not part of any neighborhood.
+    pub fn robustness(&self, results: &[GridResult]) -> Vec<Robustness> {
+        let scores: HashMap<Vec<usize>, f32> = results.iter()
+            .map(|result| (self.point(&result.params), result.score))
+            .collect();
+        let radius = self.radius as i64;
+        let offsets = self.grid.ranges.iter().fold(vec![vec![]], |offsets: Vec<Vec<i64>>, _| {
+            offsets.iter()
+                .flat_map(|offset| (-radius..=radius).map(move |step| {
+                    let mut offset = offset.clone();
+                    offset.push(step);
+                    offset
+                }))
+                .collect()
+        });
+        let mut robustness: Vec<Robustness> = results.iter()
+            .map(|result| {
+                let point = self.point(&result.params);
+                let neighborhood: Vec<f32> = offsets.iter()
+                    .filter_map(|offset| {
+                        let neighbor = point.iter().zip(offset)
+                            .map(|(i, step)| usize::try_from(*i as i64 + step).ok())
+                            .collect::<Option<Vec<usize>>>()?;
+                        scores.get(&neighbor).copied()
+                    })
+                    .collect();
+                Robustness {
+                    rank: 0,
+                    params: result.params.clone(),
+                    score: result.score,
+                    robustness: neighborhood.iter().sum::<f32>() / neighborhood.len() as f32,
+                    worst: neighborhood.iter().copied().fold(f32::INFINITY, f32::min),
+                    neighbors: neighborhood.len(),
+                }
+            })
+            .collect();
+        robustness.sort_by(|a, b| b.robustness.total_cmp(&a.robustness).then(b.score.total_cmp(&a.score)));
+        for (i, result) in robustness.iter_mut().enumerate() {
+            result.rank = i + 1;
+        }
+        robustness
+    }
+
+    /// Selected combination of results ranked best first
+    pub fn select(&self, results: &[GridResult], selection: Selection) -> Result<GridResult, Box<dyn std::error::Error>> {
+        let selected = match selection {
+            Selection::Peak => results.first(),
+            Selection::Stable => self.robustness(results).into_iter().next()
+                .and_then(|stable| results.iter().find(|result| result.params == stable.params)),
+        };
+        selected.cloned().ok_or_else(|| format!("No parameter combination of {} could be backtested", self.grid.strategy).into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::scanner::{ParamRange, PerformanceMetrics};
+    use crate::test_fixtures::query;
+    use super::*;
+
+    /// Scores of `a` and `b` from 1 to 3, a lone spike at (1, 1) and a plateau at (3, 3)
+    const SCORES: [[f32; 3]; 3] = [
+        [10.0, 0.0, 0.0],
+        [0.0, 5.0, 5.0],
+        [0.0, 5.0, 5.0],
+    ];
+
+    fn sensitivity(radius: usize) -> Sensitivity {
+        let range = |name: &str| ParamRange { name: name.to_string(), from: 1.0, to: 3.0, step: 1.0 };
+        Sensitivity::new(ParamGrid { strategy: "test".to_string(), ranges: vec![range("a"), range("b")], constraints: vec![] }, radius)
+    }
+
+    fn params(a: usize, b: usize) -> StrategyParams {
+        let mut params = StrategyParams::default();
+        params.set("a", a as f64);
+        params.set("b", b as f64);
+        params
+    }
+
+    /// Results of every combination but the skipped ones, best score first
+    fn results(skipped: &[(usize, usize)]) -> Vec<GridResult> {
+        let mut results: Vec<GridResult> = (1..=3)
+            .flat_map(|a| (1..=3).map(move |b| (a, b)))
+            .filter(|point| !skipped.contains(point))
+            .map(|(a, b)| GridResult { rank: 0, params: params(a, b), score: SCORES[a - 1][b - 1], metrics: PerformanceMetrics::default() })
+            .collect();
+        results.sort_by(|x, y| y.score.total_cmp(&x.score));
+        results
+    }
+
+    fn find(robustness: &[Robustness], a: usize, b: usize) -> &Robustness {
+        robustness.iter().find(|r| r.params == params(a, b)).unwrap()
+    }
+
+    #[test]
+    fn plateau_is_more_robust_than_a_spike() {
+        let sensitivity = sensitivity(1);
+        let robustness = sensitivity.robustness(&results(&[]));
+        let (plateau, spike, center) = (find(&robustness, 3, 3), find(&robustness, 1, 1), find(&robustness, 2, 2));
+        assert_eq!((plateau.rank, plateau.robustness, plateau.worst, plateau.neighbors), (1, 5.0, 5.0, 4));
+        assert_eq!((spike.rank, spike.robustness, spike.worst, spike.neighbors), (2, 3.75, 0.0, 4));
+        assert_eq!((center.neighbors, center.worst), (9, 0.0));
+
+        assert_eq!(sensitivity.select(&results(&[]), Selection::Peak).unwrap().params, params(1, 1));
+        assert_eq!(sensitivity.select(&results(&[]), Selection::Stable).unwrap().params, params(3, 3));
+    }
+
+    #[test]
+    fn rejected_combinations_are_left_out_of_neighborhoods() {
+        let robustness = sensitivity(1).robustness(&results(&[(1, 2)]));
+        let spike = find(&robustness, 1, 1);
+        assert_eq!((spike.robustness, spike.neighbors), (5.0, 3));
+        // Ties on robustness go to the higher score
+        assert_eq!(robustness[0].params, params(1, 1));
+    }
+
+    #[test]
+    fn radius_zero_scores_each_combination_alone() {
+        let robustness = sensitivity(0).robustness(&results(&[]));
+        assert!(robustness.iter().all(|r| r.neighbors == 1 && r.robustness == r.score));
+        let grid = sensitivity(0).grid;
+        assert_eq!(Sensitivity::from_query(grid.clone(), &query(&[])).unwrap().radius, DEFAULT_RADIUS);
+        assert!(Sensitivity::from_query(grid, &query(&[("radius", "6")])).is_err());
+    }
+}
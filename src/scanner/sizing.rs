@@ -0,0 +1,185 @@
+use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+
+use super::Trade;
+
+/// How many shares a new position gets, always capped by the cash available
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(tag = "model", rename_all = "snake_case")]
+pub enum Sizing {
+    FixedShares { shares: f64 },
+    FixedNotional { notional: f64 },
+    PercentEquity { pct: f64 },
+    /// Risks `risk_pct` of equity per ATR of adverse move, so volatile stretches trade smaller
+    Atr { risk_pct: f64, window: usize },
+    /// `fraction` of the Kelly stake estimated from the last `lookback` trades
+    Kelly { fraction: f64, lookback: usize },
+}
+
+impl Default for Sizing {
+    fn default() -> Self {
+        Sizing::PercentEquity { pct: 100.0 }
+    }
+}
+
+fn parse_positive(raw: &HashMap<String, String>, key: &str, default: f64) -> Result<f64, Box<dyn std::error::Error>> {
+    let value = match raw.get(key) {
+        Some(value) => value.parse().map_err(|e| format!("Invalid {}: {}", key, e))?,
+        None => default,
+    };
+    if value <= 0.0 || !value.is_finite() {
+        return Err(format!("{} must be positive", key).into());
+    }
+    Ok(value)
+}
+
+impl Sizing {
+    /// Reads `sizing=fixed_shares|fixed_notional|percent_equity|atr|kelly` and its parameters
+    pub fn from_query(raw: &HashMap<String, String>) -> Result<Self, Box<dyn std::error::Error>> {
+        let window = |key: &str| -> Result<usize, Box<dyn std::error::Error>> {
+            Ok(parse_positive(raw, key, 14.0)? as usize)
+        };
+        match raw.get("sizing").map(|s| s.as_str()) {
+            None => Ok(Sizing::default()),
+            Some("fixed_shares") => Ok(Sizing::FixedShares { shares: parse_positive(raw, "size_shares", 100.0)? }),
+            Some("fixed_notional") => Ok(Sizing::FixedNotional { notional: parse_positive(raw, "size_notional", 10_000.0)? }),
+            Some("percent_equity") => Ok(Sizing::PercentEquity { pct: parse_positive(raw, "size_pct", 100.0)? }),
+            Some("atr") => Ok(Sizing::Atr {
+                risk_pct: parse_positive(raw, "size_risk_pct", 1.0)?,
+                window: window("size_atr_window")?,
+            }),
+            Some("kelly") => Ok(Sizing::Kelly {
+                fraction: parse_positive(raw, "kelly_fraction", 0.5)?,
+                lookback: window("kelly_lookback")?,
+            }),
+            Some(other) => Err(format!("Unknown sizing '{}'", other).into()),
+        }
+    }
+
+    pub fn atr_window(&self) -> Option<usize> {
+        match self {
+            Sizing::Atr { window, .. } => Some(*window),
+            _ => None,
+        }
+    }
+
+    /// Shares wanted for a position at `price`, before the cash cap.
+    /// `atr` is the ATR when the signal fired and `trades` the trades closed so far.
+    pub fn shares(&self, equity: f64, price: f64, atr: Option<f32>, trades: &[Trade]) -> f64 {
+        let shares = match self {
+            Sizing::FixedShares { shares } => *shares,
+            Sizing::FixedNotional { notional } => notional / price,
+            Sizing::PercentEquity { pct } => equity * pct / 100.0 / price,
+            Sizing::Atr { risk_pct, .. } => match atr {
+                Some(atr) if atr > 0.0 => equity * risk_pct / 100.0 / atr as f64,
+                _ => 0.0,
+            },
+            Sizing::Kelly { fraction, lookback } => {
+                let recent = &trades[trades.len().saturating_sub(*lookback)..];
+                equity * (fraction * kelly_stake(recent)).clamp(0.0, 1.0) / price
+            }
+        };
+        shares.floor().max(0.0)
+    }
+}
+
+/// Kelly stake `W - (1 - W) / R` from the win rate and the ratio of average win to average loss.
+/// Trades without a win stake nothing and trades without a loss stake the win rate. Before the
+/// first trade closes there is nothing to estimate from, so the full stake is used to warm up.
+fn kelly_stake(trades: &[Trade]) -> f64 {
+    if trades.is_empty() {
+        return 1.0;
+    }
+    let wins: Vec<f64> = trades.iter().filter(|t| t.pnl > 0.0).map(|t| t.return_pct as f64).collect();
+    let losses: Vec<f64> = trades.iter().filter(|t| t.pnl <= 0.0).map(|t| -t.return_pct as f64).collect();
+    if wins.is_empty() {
+        return 0.0;
+    }
+    let win_rate = wins.len() as f64 / trades.len() as f64;
+    let avg_loss = losses.iter().sum::<f64>() / losses.len().max(1) as f64;
+    if avg_loss <= 0.0 {
+        return win_rate;
+    }
+    let avg_win = wins.iter().sum::<f64>() / wins.len() as f64;
+    win_rate - (1.0 - win_rate) / (avg_win / avg_loss)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::scanner::backtest::TradeExit;
+    use crate::scanner::Side;
+    use crate::test_fixtures::query;
+    use super::*;
+
+    fn trade(return_pct: f32) -> Trade {
+        Trade {
+            side: Side::Long,
+            entry_datetime: "2024-01-01".to_string(),
+            entry_price: 100.0,
+            exit_datetime: "2024-01-02".to_string(),
+            exit_price: 100.0 * (1.0 + return_pct / 100.0),
+            shares: 1.0,
+            commission: 0.0,
+            slippage: 0.0,
+            borrow_cost: 0.0,
+            pnl: return_pct as f64,
+            return_pct,
+            bars_held: 1,
+            mae_pct: 0.0,
+            mfe_pct: 0.0,
+            exit_reason: TradeExit::Signal,
+            regime: None,
+        }
+    }
+
+    #[test]
+    fn kelly_stakes_a_fraction_of_the_estimated_edge() {
+        let sizing = Sizing::Kelly { fraction: 0.5, lookback: 10 };
+        // Three 10% wins and one 5% loss: 0.75 - 0.25 / 2 = 0.625, halved
+        let trades = [trade(10.0), trade(10.0), trade(-5.0), trade(10.0)];
+        assert_eq!(sizing.shares(10_000.0, 10.0, None, &trades), 312.0);
+        // Without a loss the stake is the win rate of one, halved
+        assert_eq!(sizing.shares(10_000.0, 10.0, None, &[trade(10.0)]), 500.0);
+    }
+
+    #[test]
+    fn kelly_warms_up_with_the_full_stake() {
+        let sizing = Sizing::Kelly { fraction: 0.5, lookback: 10 };
+        assert_eq!(sizing.shares(10_000.0, 10.0, None, &[]), 500.0);
+    }
+
+    #[test]
+    fn kelly_only_looks_back_over_recent_trades() {
+        let trades = [trade(10.0), trade(10.0), trade(10.0), trade(-10.0), trade(5.0), trade(-10.0)];
+        assert!(Sizing::Kelly { fraction: 1.0, lookback: 6 }.shares(10_000.0, 10.0, None, &trades) > 0.0);
+        // The last three trades lose more than they win, so no stake is left
+        assert_eq!(Sizing::Kelly { fraction: 1.0, lookback: 3 }.shares(10_000.0, 10.0, None, &trades), 0.0);
+    }
+
+    #[test]
+    fn kelly_stakes_nothing_after_only_losses() {
+        let trades = [trade(10.0), trade(10.0), trade(-5.0), trade(-3.0)];
+        // The two losses in the window stake nothing, the earlier wins are out of it
+        assert_eq!(Sizing::Kelly { fraction: 1.0, lookback: 2 }.shares(10_000.0, 10.0, None, &trades), 0.0);
+    }
+
+    #[test]
+    fn atr_sizing_risks_a_share_of_equity_per_atr() {
+        let sizing = Sizing::Atr { risk_pct: 1.0, window: 14 };
+        assert_eq!(sizing.shares(10_000.0, 50.0, Some(2.0), &[]), 50.0);
+        assert_eq!(sizing.shares(10_000.0, 50.0, Some(4.0), &[]), 25.0);
+        assert_eq!(sizing.shares(10_000.0, 50.0, Some(0.0), &[]), 0.0);
+        assert_eq!(sizing.shares(10_000.0, 50.0, None, &[]), 0.0);
+    }
+
+    #[test]
+    fn sizing_is_read_from_the_query() {
+        assert_eq!(Sizing::from_query(&query(&[("sizing", "kelly")])).unwrap(), Sizing::Kelly { fraction: 0.5, lookback: 14 });
+        assert_eq!(
+            Sizing::from_query(&query(&[("sizing", "atr"), ("size_risk_pct", "2"), ("size_atr_window", "20")])).unwrap(),
+            Sizing::Atr { risk_pct: 2.0, window: 20 }
+        );
+        assert!(Sizing::from_query(&query(&[("sizing", "kelly"), ("kelly_fraction", "0")])).is_err());
+        assert!(Sizing::from_query(&query(&[("sizing", "martingale")])).is_err());
+    }
+}
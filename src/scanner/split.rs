@@ -0,0 +1,144 @@
+use polars::prelude::*;
+use serde::Serialize;
+
+use crate::strategy::{indicators, StrategyParams, StrategySpec};
+use super::walk_forward::{datetime_at, run_segment};
+use super::{Backtest, BacktestRun, GridSearch, ParamGrid, PerformanceMetrics};
+
+/// Where the optimized in-sample bars end and the out-of-sample bars begin
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum TrainTestSplit {
+    /// Share of the newest bars kept out of sample
+    TestRatio(f32),
+    /// First datetime of the out-of-sample bars
+    SplitDate(String),
+}
+
+/// Out-of-sample minus in-sample values, a lower CAGR or Sharpe and a deeper drawdown on the
+/// new bars point to overfitted parameters
+#[derive(Serialize, Debug, Clone)]
+pub struct Degradation {
+    pub cagr_pct: f32,
+    pub sharpe: Option<f32>,
+    pub max_drawdown_pct: f32,
+    /// Out-of-sample score of the backtest's metric as a percentage of the in-sample score,
+    /// only when the in-sample score is positive
+    pub score_retained_pct: Option<f32>,
+}
+
+pub struct SplitRun {
+    pub train_start: String,
+    pub train_end: String,
+    pub test_start: String,
+    pub test_end: String,
+    pub params: StrategyParams,
+    pub in_sample: PerformanceMetrics,
+    pub out_of_sample: PerformanceMetrics,
+    pub degradation: Degradation,
+    /// Backtest of the out-of-sample bars
+    pub run: BacktestRun,
+}
+
+impl TrainTestSplit {
+    /// Reads at most one of `test_ratio` and `split_date`
+    pub fn from_params(test_ratio: Option<f32>, split_date: Option<String>) -> Result<Option<Self>, Box<dyn std::error::Error>> {
+        match (test_ratio, split_date) {
+            (Some(_), Some(_)) => Err("Use either test_ratio or split_date, not both".into()),
+            (Some(ratio), None) if ratio.is_nan() || ratio <= 0.0 || ratio >= 1.0 => Err("test_ratio must be between 0 and 1".into()),
+            (Some(ratio), None) => Ok(Some(TrainTestSplit::TestRatio(ratio))),
+            (None, Some(date)) => Ok(Some(TrainTestSplit::SplitDate(date))),
+            (None, None) => Ok(None),
+        }
+    }
+
+    /// Number of bars, counted chronologically, before the first out-of-sample bar
+    fn train_bars(&self, df: &DataFrame) -> Result<usize, Box<dyn std::error::Error>> {
+        let train_bars = match self {
+            TrainTestSplit::TestRatio(ratio) => (df.height() as f32 * (1.0 - ratio)).round() as usize,
+            TrainTestSplit::SplitDate(date) => {
+                let datetime = df.column("datetime")?.str()?;
+                datetime.into_iter().filter(|dt| dt.is_some_and(|dt| dt < date.as_str())).count()
+            }
+        };
+        if train_bars == 0 || train_bars >= df.height() {
+            return Err(format!("{:?} leaves no bars in one of the segments", self).into());
+        }
+        Ok(train_bars)
+    }
+
+    /// Searches `grid` on the in-sample bars and backtests the best parameters on both segments,
+    /// scored with the metric of `backtest`
+    pub fn run(
+        &self,
+        df: &DataFrame,
+        spec: &StrategySpec,
+        backtest: &Backtest,
+        grid: &ParamGrid
+    ) -> Result<SplitRun, Box<dyn std::error::Error>> {
+        let train_bars = self.train_bars(df)?;
+        let (params, _) = GridSearch::new(grid.clone(), backtest.clone())
+            .best(&indicators::chronological_slice(df, 0, train_bars)?, spec)
+            .map_err(|e| format!("No best parameters on the in-sample bars: {}", e))?;
+        let in_sample = PerformanceMetrics::from_run(&run_segment(df, spec, &params, backtest, 0, 0, train_bars)?)?;
+        let run = run_segment(df, spec, &params, backtest, 0, train_bars, df.height())?;
+        let out_of_sample = PerformanceMetrics::from_run(&run)?;
+
+        let (score_in, score_out) = (backtest.metric.score(&in_sample), backtest.metric.score(&out_of_sample));
+        let degradation = Degradation {
+            cagr_pct: out_of_sample.cagr_pct - in_sample.cagr_pct,
+            sharpe: in_sample.sharpe.zip(out_of_sample.sharpe).map(|(is, oos)| oos - is),
+            max_drawdown_pct: out_of_sample.max_drawdown_pct - in_sample.max_drawdown_pct,
+            score_retained_pct: (score_in > 0.0 && score_out.is_finite()).then(|| 100.0 * score_out / score_in),
+        };
+        Ok(SplitRun {
+            train_start: datetime_at(df, 0)?,
+            train_end: datetime_at(df, train_bars - 1)?,
+            test_start: datetime_at(df, train_bars)?,
+            test_end: datetime_at(df, df.height() - 1)?,
+            params,
+            in_sample,
+            out_of_sample,
+            degradation,
+            run,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::strategy::StrategyRegistry;
+    use crate::test_fixtures::{bars, day, query};
+    use super::*;
+
+    #[test]
+    fn at_most_one_split_is_read() {
+        assert_eq!(TrainTestSplit::from_params(None, None).unwrap(), None);
+        assert_eq!(TrainTestSplit::from_params(Some(0.3), None).unwrap(), Some(TrainTestSplit::TestRatio(0.3)));
+        assert!(TrainTestSplit::from_params(Some(0.3), Some(day(10))).is_err());
+        for ratio in [0.0, 1.0, f32::NAN] {
+            assert!(TrainTestSplit::from_params(Some(ratio), None).is_err(), "{}", ratio);
+        }
+    }
+
+    #[test]
+    fn split_counts_the_bars_before_it() {
+        let df = bars(&[100.0; 120]);
+        assert_eq!(TrainTestSplit::TestRatio(0.25).train_bars(&df).unwrap(), 90);
+        // January and the 29 days of February 2024
+        assert_eq!(TrainTestSplit::SplitDate("2024-03-01".to_string()).train_bars(&df).unwrap(), 60);
+        // Every bar on one side of the split
+        assert!(TrainTestSplit::SplitDate(day(0)).train_bars(&df).is_err());
+        assert!(TrainTestSplit::SplitDate(day(120)).train_bars(&df).is_err());
+    }
+
+    #[test]
+    fn out_of_sample_bars_follow_the_training_bars() {
+        let close: Vec<f32> = (0..120).map(|i| 100.0 + 10.0 * (i as f32 / 8.0).sin() + 0.1 * i as f32).collect();
+        let spec = StrategyRegistry::global().get("sma").unwrap();
+        let grid = ParamGrid::from_query(spec, &query(&[("short_ma_to", "20"), ("long_ma_from", "30"), ("long_ma_to", "40")])).unwrap();
+        let split = TrainTestSplit::TestRatio(0.25).run(&bars(&close), spec, &Backtest::new(), &grid).unwrap();
+        assert_eq!([split.train_start, split.train_end, split.test_start, split.test_end], [day(0), day(89), day(90), day(119)]);
+        assert_eq!(split.run.equity_curve.height(), 30);
+    }
+}
@@ -0,0 +1,204 @@
+use polars::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::strategy::{indicators, StrategyParams, StrategySpec};
+use super::{Backtest, BacktestRun, GridSearch, ParamGrid, PerformanceMetrics};
+
+pub const DEFAULT_TRAIN_BARS: usize = 252;
+pub const DEFAULT_TEST_BARS: usize = 63;
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum WalkForwardMode {
+    /// The training window keeps its length and slides forward
+    #[default]
+    Rolling,
+    /// The training window always starts on the first bar and grows
+    Anchored,
+}
+
+/// One optimization on a training window and the out-of-sample bars that follow it
+#[derive(Serialize, Debug, Clone)]
+pub struct WalkForwardWindow {
+    pub train_start: String,
+    pub train_end: String,
+    pub test_start: String,
+    pub test_end: String,
+    pub params: StrategyParams,
+    /// Score of the backtest's metric for the chosen parameters on the training window
+    pub in_sample_score: f32,
+    pub out_of_sample: PerformanceMetrics,
+}
+
+pub struct WalkForwardRun {
+    pub windows: Vec<WalkForwardWindow>,
+    /// Out-of-sample windows stitched together, each starting with the equity the previous ended on
+    pub run: BacktestRun,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct WalkForward {
+    pub mode: WalkForwardMode,
+    pub train_bars: usize,
+    pub test_bars: usize,
+}
+
+/// Backtests `params` on the chronological bars `start..end`, with indicators computed from
+/// `history_start` so they are warmed up by the bars before `start`
+pub(super) fn run_segment(
+    df: &DataFrame,
+    spec: &StrategySpec,
+    params: &StrategyParams,
+    backtest: &Backtest,
+    history_start: usize,
+    start: usize,
+    end: usize
+) -> Result<BacktestRun, Box<dyn std::error::Error>> {
+    let mut strategy = spec.build(indicators::chronological_slice(df, history_start, end)?, params)?;
+    let signals = strategy.calc_signal()?;
+    let segment = indicators::chronological_slice(&signals, start - history_start, end - history_start)?;
+    backtest.run(&segment, &strategy.signal_col())
+}
+
+pub(super) fn datetime_at(df: &DataFrame, chronological_row: usize) -> Result<String, Box<dyn std::error::Error>> {
+    let row = indicators::chronological_rows(df)?[chronological_row];
+    Ok(df.column("datetime")?.str()?.get(row).unwrap_or_default().to_string())
+}
+
+impl WalkForward {
+    pub fn new(mode: WalkForwardMode, train_bars: usize, test_bars: usize) -> Result<Self, Box<dyn std::error::Error>> {
+        if train_bars == 0 || test_bars == 0 {
+            return Err("train_bars and test_bars must be positive".into());
+        }
+        Ok(WalkForward { mode, train_bars, test_bars })
+    }
+
+    /// Searches `grid` on every training window and backtests the winning parameters, built from
+    /// `spec`, on the next `test_bars`. Indicators of a test window are computed from
+    /// the start of its training window so they are warmed up without seeing later bars, and a
+    /// position still open at the end of a test window is closed there.
+    pub fn run(
+        &self,
+        df: &DataFrame,
+        spec: &StrategySpec,
+        backtest: &Backtest,
+        grid: &ParamGrid
+    ) -> Result<WalkForwardRun, Box<dyn std::error::Error>> {
+        let search = GridSearch::new(grid.clone(), backtest.clone());
+        let height = df.height();
+        if height <= self.train_bars {
+            return Err(format!("Walk-forward needs more than {} bars, got {}", self.train_bars, height).into());
+        }
+
+        let mut windows = Vec::new();
+        let mut curves = Vec::new();
+        let mut trades = Vec::new();
+        let mut equity = backtest.initial_capital;
+        let mut test_start = self.train_bars;
+        while test_start < height {
+            let test_end = (test_start + self.test_bars).min(height);
+            let train_start = match self.mode {
+                WalkForwardMode::Rolling => test_start - self.train_bars,
+                WalkForwardMode::Anchored => 0,
+            };
+
+            let (params, in_sample_score) = search.best(&indicators::chronological_slice(df, train_start, test_start)?, spec)
+                .map_err(|e| format!("No best parameters on the window ending {}: {}", datetime_at(df, test_start - 1).unwrap_or_default(), e))?;
+
+            let mut window_backtest = backtest.clone();
+            window_backtest.initial_capital = equity;
+            let run = run_segment(df, spec, &params, &window_backtest, train_start, test_start, test_end)?;
+            equity = run.final_equity;
+
+            windows.push(WalkForwardWindow {
+                train_start: datetime_at(df, train_start)?,
+                train_end: datetime_at(df, test_start - 1)?,
+                test_start: datetime_at(df, test_start)?,
+                test_end: datetime_at(df, test_end - 1)?,
+                params,
+                in_sample_score,
+                out_of_sample: PerformanceMetrics::from_run(&run)?,
+            });
+            curves.push(run.equity_curve);
+            trades.extend(run.trades);
+            test_start = test_end;
+        }
+
+        if indicators::newest_first(df)? {
+            curves.reverse();
+        }
+        let mut equity_curve = curves.remove(0);
+        for curve in &curves {
+            equity_curve.vstack_mut(curve)?;
+        }
+        Ok(WalkForwardRun {
+            windows,
+            run: BacktestRun {
+                signal: format!("{} walk-forward", spec.name),
+                initial_capital: backtest.initial_capital,
+                final_equity: equity,
+                total_return_pct: (100.0 * (equity / backtest.initial_capital - 1.0)) as f32,
+                equity_curve,
+                trades,
+                sizing: backtest.sizing.clone(),
+                regimes: None,
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::strategy::StrategyRegistry;
+    use crate::test_fixtures::{bars, day, query};
+    use super::*;
+
+    /// 120 daily closes swinging 10 points around a line rising a tenth of a point a day
+    fn prices() -> DataFrame {
+        let close: Vec<f32> = (0..120).map(|i| 100.0 + 10.0 * (i as f32 / 8.0).sin() + 0.1 * i as f32).collect();
+        bars(&close)
+    }
+
+    fn walk(mode: WalkForwardMode) -> WalkForwardRun {
+        let spec = StrategyRegistry::global().get("sma").unwrap();
+        let grid = ParamGrid::from_query(spec, &query(&[("short_ma_to", "20"), ("long_ma_from", "30"), ("long_ma_to", "40")])).unwrap();
+        WalkForward::new(mode, 60, 25).unwrap().run(&prices(), spec, &Backtest::new(), &grid).unwrap()
+    }
+
+    /// First and last dates of the training and test bars of every window
+    fn bounds(run: &WalkForwardRun) -> Vec<[String; 4]> {
+        run.windows.iter()
+            .map(|w| [w.train_start.clone(), w.train_end.clone(), w.test_start.clone(), w.test_end.clone()])
+            .collect()
+    }
+
+    #[test]
+    fn rolling_windows_slide_their_training_bars() {
+        let run = walk(WalkForwardMode::Rolling);
+        // The last test window is cut short by the end of the data
+        assert_eq!(bounds(&run), [
+            [day(0), day(59), day(60), day(84)],
+            [day(25), day(84), day(85), day(109)],
+            [day(50), day(109), day(110), day(119)],
+        ]);
+        // Only the test bars are in the stitched curve, the latest first like the prices
+        assert_eq!(run.run.equity_curve.height(), 60);
+        assert_eq!(run.run.equity_curve.column("datetime").unwrap().str().unwrap().get(0), Some(day(119).as_str()));
+    }
+
+    #[test]
+    fn anchored_windows_train_from_the_first_bar() {
+        let run = walk(WalkForwardMode::Anchored);
+        assert!(run.windows.iter().all(|w| w.train_start == day(0)));
+        let test_starts: Vec<&str> = run.windows.iter().map(|w| w.test_start.as_str()).collect();
+        assert_eq!(test_starts, [day(60), day(85), day(110)]);
+    }
+
+    #[test]
+    fn windows_need_bars_to_test_on() {
+        let spec = StrategyRegistry::global().get("sma").unwrap();
+        let grid = ParamGrid::from_query(spec, &query(&[])).unwrap();
+        assert!(WalkForward::new(WalkForwardMode::Rolling, 120, 25).unwrap().run(&prices(), spec, &Backtest::new(), &grid).is_err());
+        assert!(WalkForward::new(WalkForwardMode::Rolling, 60, 0).is_err());
+    }
+}
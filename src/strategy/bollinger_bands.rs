@@ -1,8 +1,10 @@
+use std::sync::Arc;
 use polars::{prelude::*};
 use log::info;
 use serde::{Deserialize, Serialize};
 
-use super::Strategy;
+use crate::converter::DfConverter;
+use super::{indicators, ParamSpec, Strategy, StrategySpec, BUY_SIGNAL, SELL_SIGNAL};
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct StrategyBollingerBands {
@@ -29,6 +31,24 @@ impl StrategyBollingerBands {
         }
     }
     
+    pub fn spec() -> StrategySpec {
+        StrategySpec {
+            name: "bb".to_string(),
+            description: "Close crossing the Bollinger bands of a simple moving average".to_string(),
+            params: vec![
+                ParamSpec::int("ma_window", 20, 5, 200, 5),
+                ParamSpec::int("std_bands", 2, 2, 3, 1),
+            ],
+            constraints: vec![],
+            builder: Arc::new(|df, params| {
+                let mut bb = StrategyBollingerBands::new(df, params.get_usize("ma_window")?);
+                bb.update_param(None, Some(params.get_usize("std_bands")?));
+                Ok(Box::new(bb))
+            }),
+            to_json: DfConverter::bb_df_to_json,
+        }
+    }
+
     pub fn update_param(&mut self, ma_window: Option<usize>, std: Option<usize>) {
         if let Some(ma_window) = ma_window {
             self.ma_window = ma_window;
@@ -44,20 +64,12 @@ impl StrategyBollingerBands {
                 let ma_type = format!("SMA_{}", self.ma_window);
                 let std_col = "Std";
                 self.sma_options.window_size = self.ma_window;
-                let mut df_result = df.clone()
-                    .lazy()
-                    .with_column(
-                        col("close").rolling_mean(self.sma_options.clone())
-                                    .alias(ma_type.clone())
-                                    // Shift the calculated moving average to the corresponding datetime
-                                    .shift((-(self.ma_window as i32)).into()),
-                    )
-                    .collect().ok().unwrap();
-                df_result = df_result.clone()
-                    .lazy()
-                    .with_column(
-                        col(ma_type.clone()).rolling_std(self.sma_options.clone()).alias(std_col)
-                    )
+                // Bands of a bar cover that bar's close and the ones before it
+                let df_result = indicators::in_chronological_order(df, |lf| lf
+                    .with_columns([
+                        col("close").rolling_mean(self.sma_options.clone()).alias(ma_type.clone()),
+                        col("close").rolling_std(self.sma_options.clone()).alias(std_col),
+                    ])
                     .with_column(
                         (col(ma_type.clone()) + col(std_col) * lit(self.std_bands as f32))
                             .alias(format!("Upper_SMA_{}_Std_{}", self.ma_window, self.std_bands))
@@ -66,7 +78,7 @@ impl StrategyBollingerBands {
                         (col(ma_type.clone()) - col(std_col) * lit(self.std_bands as f32))
                             .alias(format!("Lower_SMA_{}_Std_{}", self.ma_window, self.std_bands))
                     )
-                    .collect().ok().unwrap();
+                )?;
             info!("Calculated bollinger bands {}", ma_type.clone());
 
             return Ok(df_result);
@@ -82,7 +94,7 @@ impl Strategy for StrategyBollingerBands {
             return Err("Dataframe is None".into());
         }
 
-        let signal_name = format!("Sig_SMA_{}_Std_{}", self.ma_window, self.std_bands);
+        let signal_name = self.signal_col();
         let upper_band_name = format!("Upper_SMA_{}_Std_{}", self.ma_window, self.std_bands);
         let lower_band_name = format!("Lower_SMA_{}_Std_{}", self.ma_window, self.std_bands);
         let mut df_result = self.calc_ma()?;
@@ -94,12 +106,12 @@ impl Strategy for StrategyBollingerBands {
                 when(
                     col("close").gt(col(&upper_band_name))
                 )
-                .then(lit(1))
+                .then(lit(SELL_SIGNAL))
                 // Buy signal
                 .when(
                     col("close").lt(col(&lower_band_name))
                 )
-                .then(lit(-1))
+                .then(lit(BUY_SIGNAL))
                 .otherwise(lit(0))
                 .alias(&signal_name)
             ])
@@ -107,4 +119,8 @@ impl Strategy for StrategyBollingerBands {
         info!("Calculated bollinger bands signal: {}", signal_name);
         Ok(df_result)
     }
+
+    fn signal_col(&self) -> String {
+        format!("Sig_SMA_{}_Std_{}", self.ma_window, self.std_bands)
+    }
 }
\ No newline at end of file
@@ -1,8 +1,10 @@
+use std::sync::Arc;
 use polars::prelude::*;
 use log::info;
 use serde::{Deserialize, Serialize};
 
-use super::Strategy;
+use crate::converter::DfConverter;
+use super::{indicators, ParamConstraint, ParamSpec, Strategy, StrategySpec, BUY_SIGNAL, SELL_SIGNAL};
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct StrategyCrossingMA {
@@ -40,46 +42,42 @@ impl StrategyCrossingMA {
         }
     }
     
-    pub fn update_params(&mut self, short_ma: Option<usize>, long_ma: Option<usize>, ma_type: Option<String>) {
-        if let Some(s_ma) = short_ma {
-            self.short_ma = s_ma;
-        }
-        if let Some(l_ma) = long_ma {
-            self.long_ma = l_ma;
-        }
-        if let Some(ma_type) = ma_type {
-            self.ma_type = ma_type;
-        }
+    pub fn specs() -> Vec<StrategySpec> {
+        ["SMA", "EWMA"].iter().map(|ma_type| {
+            let ma_type = ma_type.to_string();
+            StrategySpec {
+                name: ma_type.to_lowercase(),
+                description: format!("Crossing of a short and a long {}", ma_type),
+                params: vec![
+                    ParamSpec::int("short_ma", 20, 10, 200, 10),
+                    ParamSpec::int("long_ma", 50, 10, 200, 10),
+                ],
+                constraints: vec![ParamConstraint::below("short_ma", "long_ma")],
+                builder: Arc::new(move |df, params| {
+                    let (short_ma, long_ma) = (params.get_usize("short_ma")?, params.get_usize("long_ma")?);
+                    Ok(Box::new(StrategyCrossingMA::new(df, short_ma, long_ma, ma_type.clone())))
+                }),
+                to_json: DfConverter::crossingma_df_to_json,
+            }
+        }).collect()
     }
-    
+
     pub fn calc_ma(&mut self, window_size: usize, ma_name: String) -> Result<DataFrame, Box<dyn std::error::Error>> {
         match &mut self.df {
             Some(df) => {
                 let mut df_result = DataFrame::empty();
                 // Implementation of calculating signal for moving average strategy
+                // The average of a bar covers that bar's close and the ones before it
                 if self.ma_type == "SMA" {
                     self.sma_options.window_size = window_size;
                     self.sma_options.min_periods = window_size;
-                    df_result = df.clone()
-                        .lazy()
-                        .with_column(
-                            col("close").rolling_mean(self.sma_options.clone())
-                                        .alias(ma_name.clone())
-                                        // Shift the calculated moving average to the corresponding datetime
-                                        .shift((-(window_size as i32)).into()),
-                        )
-                        .collect().ok().unwrap();
+                    let ma = col("close").rolling_mean(self.sma_options.clone()).alias(ma_name.clone());
+                    df_result = indicators::in_chronological_order(df, |lf| lf.with_column(ma))?;
                 } else if self.ma_type == "EWMA" {
                     self.ewma_options.alpha = 2.0 / (window_size + 1) as f64;
                     self.ewma_options.min_periods = window_size;
-                    df_result = df.clone()
-                        .lazy()
-                        .with_column(
-                            col("close").ewm_mean(self.ewma_options.clone())
-                                        .alias(ma_name.clone())
-                                        .shift((-(window_size as i32)).into()),
-                        )
-                        .collect().ok().unwrap();
+                    let ma = col("close").ewm_mean(self.ewma_options).alias(ma_name.clone());
+                    df_result = indicators::in_chronological_order(df, |lf| lf.with_column(ma))?;
                 }
                 info!("Calculated {}", ma_name);
                 return Ok(df_result);
@@ -95,10 +93,9 @@ impl Strategy for StrategyCrossingMA {
             return Err("Dataframe is None".into());
         }
 
-        let signal_name = format!("Sig_{}_{}_{}", self.ma_type, self.short_ma, self.long_ma);
+        let signal_name = self.signal_col();
         let short_ma_name = format!("{}_{}", self.ma_type, self.short_ma);
         let long_ma_name = format!("{}_{}", self.ma_type, self.long_ma);
-        let shift_days = 1;
 
         // Calculate MAs up front
         let mut short_ma_df = self.calc_ma(self.short_ma, short_ma_name.clone())?;
@@ -107,28 +104,30 @@ impl Strategy for StrategyCrossingMA {
             long_ma_df.column(long_ma_name.as_ref())?.clone()
         ).unwrap().clone();
         
-        df_result = df_result
-            .lazy()
-            .with_columns([
-                // Sell signal
-                when(
-                    col(&short_ma_name).gt(col(&long_ma_name)).and(
-                        col(&short_ma_name).shift(lit(shift_days)).lt(col(&long_ma_name).shift(lit(shift_days)))
-                    )
+        // Compared with the previous bar, which is `shift(1)` in chronological order
+        let signal =
+            // Sell signal: the short MA crosses below the long MA
+            when(
+                col(&short_ma_name).lt(col(&long_ma_name)).and(
+                    col(&short_ma_name).shift(lit(1)).gt(col(&long_ma_name).shift(lit(1)))
                 )
-                .then(lit(1))
-                // Buy signal
-                .when(
-                    col(&short_ma_name).lt(col(&long_ma_name)).and(
-                        col(&short_ma_name).shift(lit(shift_days)).gt(col(&long_ma_name).shift(lit(shift_days)))
-                    )
+            )
+            .then(lit(SELL_SIGNAL))
+            // Buy signal: the short MA crosses above the long MA
+            .when(
+                col(&short_ma_name).gt(col(&long_ma_name)).and(
+                    col(&short_ma_name).shift(lit(1)).lt(col(&long_ma_name).shift(lit(1)))
                 )
-                .then(lit(-1))
-                .otherwise(lit(0))
-                .alias(&signal_name)
-            ])
-            .collect()?;
+            )
+            .then(lit(BUY_SIGNAL))
+            .otherwise(lit(0))
+            .alias(&signal_name);
+        df_result = indicators::in_chronological_order(&df_result, |lf| lf.with_column(signal))?;
         info!("Calculated crossing average signal: {}", signal_name);
         Ok(df_result)
     }
+
+    fn signal_col(&self) -> String {
+        format!("Sig_{}_{}_{}", self.ma_type, self.short_ma, self.long_ma)
+    }
 }
\ No newline at end of file
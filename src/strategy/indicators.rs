@@ -0,0 +1,74 @@
+use polars::prelude::*;
+
+pub fn newest_first(df: &DataFrame) -> Result<bool, Box<dyn std::error::Error>> {
+    let datetime = df.column("datetime")?.str()?;
+    match (datetime.get(0), datetime.get(df.height().saturating_sub(1))) {
+        (Some(first), Some(last)) => Ok(first > last),
+        _ => Ok(false)
+    }
+}
+
+/// Row indices of the frame in chronological order, cached prices are stored newest first
+pub fn chronological_rows(df: &DataFrame) -> Result<Vec<usize>, Box<dyn std::error::Error>> {
+    let rows: Vec<usize> = (0..df.height()).collect();
+    if newest_first(df)? {
+        Ok(rows.into_iter().rev().collect())
+    } else {
+        Ok(rows)
+    }
+}
+
+/// Bars `start..end` counted in chronological order, kept in the frame's row order
+pub fn chronological_slice(df: &DataFrame, start: usize, end: usize) -> Result<DataFrame, Box<dyn std::error::Error>> {
+    let end = end.min(df.height());
+    let start = start.min(end);
+    if newest_first(df)? {
+        Ok(df.slice((df.height() - end) as i64, end - start))
+    } else {
+        Ok(df.slice(start as i64, end - start))
+    }
+}
+
+/// Runs a lazy computation on the bars in chronological order and returns the frame in its
+/// original row order, so rolling windows and `shift(1)` only ever see earlier bars
+pub fn in_chronological_order<F>(df: &DataFrame, compute: F) -> Result<DataFrame, Box<dyn std::error::Error>>
+where
+    F: FnOnce(LazyFrame) -> LazyFrame
+{
+    if newest_first(df)? {
+        Ok(compute(df.reverse().lazy()).collect()?.reverse())
+    } else {
+        Ok(compute(df.clone().lazy()).collect()?)
+    }
+}
+
+pub fn column_f32(df: &DataFrame, name: &str) -> Result<Vec<Option<f32>>, Box<dyn std::error::Error>> {
+    let column = df.column(name)?.cast(&DataType::Float32)?;
+    Ok(column.f32()?.into_iter().collect())
+}
+
+/// Average true range over `window` bars, indexed like the frame rows
+pub fn atr(df: &DataFrame, window: usize) -> Result<Vec<Option<f32>>, Box<dyn std::error::Error>> {
+    let high = column_f32(df, "high")?;
+    let low = column_f32(df, "low")?;
+    let close = column_f32(df, "close")?;
+    let mut atr = vec![None; df.height()];
+    let mut true_ranges: Vec<f32> = Vec::new();
+    let mut prev_close: Option<f32> = None;
+
+    for row in chronological_rows(df)? {
+        if let (Some(h), Some(l), Some(c)) = (high[row], low[row], close[row]) {
+            let tr = match prev_close {
+                Some(pc) => (h - l).max((h - pc).abs()).max((l - pc).abs()),
+                None => h - l,
+            };
+            true_ranges.push(tr);
+            prev_close = Some(c);
+            if window > 0 && true_ranges.len() >= window {
+                let last = &true_ranges[true_ranges.len() - window..];
+                atr[row] = Some(last.iter().sum::<f32>() / window as f32);
+            }
+        }
+    }
+    Ok(atr)
+}
@@ -0,0 +1,158 @@
+use chrono::{Datelike, NaiveDate};
+use polars::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use super::indicators;
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum PivotMethod {
+    Classic,
+    Fibonacci,
+    Camarilla,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum PivotPeriod {
+    Day,
+    Week,
+    Month,
+}
+
+impl PivotPeriod {
+    fn key(&self, datetime: &str) -> Option<String> {
+        let date = NaiveDate::parse_from_str(datetime.get(..10)?, "%Y-%m-%d").ok()?;
+        match self {
+            PivotPeriod::Day => Some(date.to_string()),
+            PivotPeriod::Week => Some(format!("{}-W{:02}", date.iso_week().year(), date.iso_week().week())),
+            PivotPeriod::Month => Some(date.format("%Y-%m").to_string()),
+        }
+    }
+}
+
+/// Pivot levels of a period, computed from the high, low and close of the previous period
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct PivotPoints {
+    pub period: String,
+    pub method: PivotMethod,
+    pub pivot: f32,
+    pub resistance: Vec<f32>,
+    pub support: Vec<f32>,
+}
+
+impl PivotPoints {
+    fn new(period: String, method: PivotMethod, high: f32, low: f32, close: f32) -> Self {
+        let pivot = (high + low + close) / 3.0;
+        let range = high - low;
+        let (resistance, support) = match method {
+            PivotMethod::Classic => (
+                vec![2.0 * pivot - low, pivot + range, high + 2.0 * (pivot - low)],
+                vec![2.0 * pivot - high, pivot - range, low - 2.0 * (high - pivot)],
+            ),
+            PivotMethod::Fibonacci => (
+                [0.382, 0.618, 1.0].iter().map(|f| pivot + f * range).collect(),
+                [0.382, 0.618, 1.0].iter().map(|f| pivot - f * range).collect(),
+            ),
+            PivotMethod::Camarilla => (
+                [12.0, 6.0, 4.0, 2.0].iter().map(|d| close + range * 1.1 / d).collect(),
+                [12.0, 6.0, 4.0, 2.0].iter().map(|d| close - range * 1.1 / d).collect(),
+            ),
+        };
+        PivotPoints { period, method, pivot, resistance, support }
+    }
+}
+
+pub fn pivot_points(
+    df: &DataFrame,
+    method: PivotMethod,
+    period: PivotPeriod
+) -> Result<Vec<PivotPoints>, Box<dyn std::error::Error>> {
+    let datetime = df.column("datetime")?.str()?;
+    let high = indicators::column_f32(df, "high")?;
+    let low = indicators::column_f32(df, "low")?;
+    let close = indicators::column_f32(df, "close")?;
+
+    // (period, high, low, last close) in chronological order
+    let mut periods: Vec<(String, f32, f32, f32)> = Vec::new();
+    for row in indicators::chronological_rows(df)? {
+        let (Some(key), Some(h), Some(l), Some(c)) = (
+            datetime.get(row).and_then(|dt| period.key(dt)), high[row], low[row], close[row]
+        ) else {
+            continue;
+        };
+        match periods.last_mut() {
+            Some(last) if last.0 == key => {
+                last.1 = last.1.max(h);
+                last.2 = last.2.min(l);
+                last.3 = c;
+            }
+            _ => periods.push((key, h, l, c)),
+        }
+    }
+    Ok(periods.windows(2)
+        .map(|w| PivotPoints::new(w[1].0.clone(), method, w[0].1, w[0].2, w[0].3))
+        .collect())
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct PriceZone {
+    pub price: f32,
+    pub lower: f32,
+    pub upper: f32,
+    pub touches: usize,
+}
+
+/// Swing highs and lows as (row, price). A swing needs `window` bars on each side,
+/// so it is only confirmed `window` bars after it happened.
+pub fn swing_points(df: &DataFrame, window: usize) -> Result<Vec<(usize, f32)>, Box<dyn std::error::Error>> {
+    let high = indicators::column_f32(df, "high")?;
+    let low = indicators::column_f32(df, "low")?;
+    let rows = indicators::chronological_rows(df)?;
+    let mut swings = Vec::new();
+    if window == 0 || rows.len() <= 2 * window {
+        return Ok(swings);
+    }
+    for i in window..rows.len() - window {
+        let around = &rows[i - window..=i + window];
+        let row = rows[i];
+        if let Some(h) = high[row].filter(|h| around.iter().all(|r| high[*r].is_none_or(|other| other <= *h))) {
+            swings.push((i, h));
+        }
+        if let Some(l) = low[row].filter(|l| around.iter().all(|r| low[*r].is_none_or(|other| other >= *l))) {
+            swings.push((i, l));
+        }
+    }
+    Ok(swings)
+}
+
+/// Groups prices lying within `tolerance_pct` of a zone's average into horizontal zones
+pub fn cluster_zones(prices: &[f32], tolerance_pct: f32, min_touches: usize) -> Vec<PriceZone> {
+    let mut sorted = prices.to_vec();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    let mut zones: Vec<PriceZone> = Vec::new();
+    for price in sorted {
+        match zones.last_mut() {
+            Some(zone) if price <= zone.price * (1.0 + tolerance_pct / 100.0) => {
+                zone.price = (zone.price * zone.touches as f32 + price) / (zone.touches + 1) as f32;
+                zone.upper = price;
+                zone.touches += 1;
+            }
+            _ => zones.push(PriceZone { price, lower: price, upper: price, touches: 1 }),
+        }
+    }
+    zones.retain(|zone| zone.touches >= min_touches);
+    zones
+}
+
+pub fn support_resistance(
+    df: &DataFrame,
+    window: usize,
+    tolerance_pct: f32,
+    min_touches: usize
+) -> Result<Vec<PriceZone>, Box<dyn std::error::Error>> {
+    let prices: Vec<f32> = swing_points(df, window)?.into_iter().map(|(_, price)| price).collect();
+    let mut zones = cluster_zones(&prices, tolerance_pct, min_touches);
+    zones.sort_by_key(|zone| std::cmp::Reverse(zone.touches));
+    Ok(zones)
+}
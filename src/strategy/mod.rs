@@ -1,12 +1,27 @@
 mod crossing_ma;
 mod rsi;
 mod bollinger_bands;
+mod registry;
+mod risk;
+mod regime;
+mod support_resistance;
+pub mod levels;
+pub mod indicators;
 
 pub use crossing_ma::StrategyCrossingMA;
 use polars::frame::DataFrame;
 pub use rsi::StrategyRSI;
 pub use bollinger_bands::StrategyBollingerBands;
+pub use support_resistance::StrategySupportResistance;
+pub use registry::{ParamConstraint, ParamSpec, ParamType, StrategyParams, StrategyRegistry, StrategySpec};
+pub use risk::{ExitReason, RiskRules, StrategyRiskOverlay, EXIT_PRICE_COL, EXIT_REASON_COL};
+pub use regime::{Regime, RegimeClassifier, StrategyRegimeFilter, REGIME_COL};
+
+/// Every strategy marks buys with +1 and sells with -1, the sign is the direction of the trade
+pub const BUY_SIGNAL: i32 = 1;
+pub const SELL_SIGNAL: i32 = -1;
 
 pub trait Strategy {
     fn calc_signal(&mut self) -> Result<DataFrame, Box<dyn std::error::Error>>;
+    fn signal_col(&self) -> String;
 }
\ No newline at end of file
@@ -0,0 +1,212 @@
+use std::collections::HashMap;
+use log::info;
+use polars::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::scanner::{PositionMode, Side};
+use super::{indicators, Strategy};
+
+pub const REGIME_COL: &str = "Regime";
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum Regime {
+    Trend,
+    Range,
+    HighVolatility,
+}
+
+impl Regime {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Regime::Trend => "trend",
+            Regime::Range => "range",
+            Regime::HighVolatility => "high_volatility",
+        }
+    }
+
+    pub fn parse(raw: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        match raw {
+            "trend" => Ok(Regime::Trend),
+            "range" => Ok(Regime::Range),
+            "high_volatility" => Ok(Regime::HighVolatility),
+            other => Err(format!("Unknown regime '{}'", other).into()),
+        }
+    }
+}
+
+/// Labels bars from rolling volatility, an ADX-like trend strength and the slope of a moving average.
+/// Volatility is compared to its own expanding average, so labels only depend on past bars.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct RegimeClassifier {
+    pub adx_window: usize,
+    pub adx_threshold: f32,
+    pub vol_window: usize,
+    pub vol_multiple: f32,
+    pub ma_window: usize,
+    pub slope_window: usize,
+    pub slope_threshold_pct: f32,
+}
+
+impl Default for RegimeClassifier {
+    fn default() -> Self {
+        RegimeClassifier {
+            adx_window: 14,
+            adx_threshold: 25.0,
+            vol_window: 20,
+            vol_multiple: 1.5,
+            ma_window: 50,
+            slope_window: 5,
+            slope_threshold_pct: 1.0,
+        }
+    }
+}
+
+fn mean(values: &[f32]) -> f32 {
+    values.iter().sum::<f32>() / values.len() as f32
+}
+
+impl RegimeClassifier {
+    /// Regime of every row, `None` while the indicators are still warming up
+    pub fn classify(&self, df: &DataFrame) -> Result<Vec<Option<Regime>>, Box<dyn std::error::Error>> {
+        let high = indicators::column_f32(df, "high")?;
+        let low = indicators::column_f32(df, "low")?;
+        let close = indicators::column_f32(df, "close")?;
+        let rows: Vec<usize> = indicators::chronological_rows(df)?
+            .into_iter()
+            .filter(|row| high[*row].is_some() && low[*row].is_some() && close[*row].is_some())
+            .collect();
+        let (h, l, c): (Vec<f32>, Vec<f32>, Vec<f32>) = (
+            rows.iter().map(|r| high[*r].unwrap()).collect(),
+            rows.iter().map(|r| low[*r].unwrap()).collect(),
+            rows.iter().map(|r| close[*r].unwrap()).collect(),
+        );
+
+        let mut regimes = vec![None; df.height()];
+        let mut plus_dm = vec![0.0; rows.len()];
+        let mut minus_dm = vec![0.0; rows.len()];
+        let mut true_range = vec![0.0; rows.len()];
+        let mut returns = vec![0.0; rows.len()];
+        let mut dx: Vec<f32> = Vec::new();
+        let mut vol_sum = 0.0;
+        let mut vol_count = 0;
+
+        for t in 1..rows.len() {
+            let up = h[t] - h[t - 1];
+            let down = l[t - 1] - l[t];
+            plus_dm[t] = if up > down && up > 0.0 { up } else { 0.0 };
+            minus_dm[t] = if down > up && down > 0.0 { down } else { 0.0 };
+            true_range[t] = (h[t] - l[t]).max((h[t] - c[t - 1]).abs()).max((l[t] - c[t - 1]).abs());
+            returns[t] = (c[t] / c[t - 1]).ln();
+
+            if t >= self.adx_window {
+                let span = t + 1 - self.adx_window..=t;
+                let tr = mean(&true_range[span.clone()]);
+                let plus_di = 100.0 * mean(&plus_dm[span.clone()]) / tr;
+                let minus_di = 100.0 * mean(&minus_dm[span]) / tr;
+                let di_sum = plus_di + minus_di;
+                dx.push(if di_sum > 0.0 { 100.0 * (plus_di - minus_di).abs() / di_sum } else { 0.0 });
+            }
+            if dx.len() < self.adx_window || t < self.vol_window || t < self.ma_window + self.slope_window {
+                continue;
+            }
+            let adx = mean(&dx[dx.len() - self.adx_window..]);
+
+            let window_returns = &returns[t + 1 - self.vol_window..=t];
+            let avg = mean(window_returns);
+            let vol = (window_returns.iter().map(|r| (r - avg).powi(2)).sum::<f32>() / self.vol_window as f32).sqrt();
+            vol_sum += vol;
+            vol_count += 1;
+
+            let ma_now = mean(&c[t + 1 - self.ma_window..=t]);
+            let ma_before = mean(&c[t + 1 - self.ma_window - self.slope_window..=t - self.slope_window]);
+            let slope_pct = 100.0 * (ma_now - ma_before) / ma_before;
+
+            let regime = if vol > self.vol_multiple * vol_sum / vol_count as f32 {
+                Regime::HighVolatility
+            } else if adx >= self.adx_threshold && slope_pct.abs() >= self.slope_threshold_pct {
+                Regime::Trend
+            } else {
+                Regime::Range
+            };
+            regimes[rows[t]] = Some(regime);
+        }
+        Ok(regimes)
+    }
+
+    pub fn with_regime(&self, df: &DataFrame) -> Result<DataFrame, Box<dyn std::error::Error>> {
+        let regimes = self.classify(df)?;
+        let mut df_result = df.clone();
+        df_result.with_column(regime_series(&regimes))?;
+        Ok(df_result)
+    }
+}
+
+fn regime_series(regimes: &[Option<Regime>]) -> Series {
+    let labels: Vec<Option<&str>> = regimes.iter().map(|r| r.map(|r| r.as_str())).collect();
+    Series::new(REGIME_COL.into(), labels)
+}
+
+/// Only lets a strategy enter in the allowed regimes, exits always pass through. Entries are buys
+/// in `long_only` mode and sells in `short_only` mode. In `long_short` mode every signal can
+/// reverse the position, so entries cannot be dropped without dropping exits and the filter errors.
+pub struct StrategyRegimeFilter {
+    pub strategy: Box<dyn Strategy + Send>,
+    pub classifier: RegimeClassifier,
+    pub allowed: Vec<Regime>,
+    pub mode: PositionMode,
+}
+
+impl StrategyRegimeFilter {
+    pub fn new(strategy: Box<dyn Strategy + Send>, classifier: RegimeClassifier, allowed: Vec<Regime>) -> Self {
+        StrategyRegimeFilter { strategy, classifier, allowed, mode: PositionMode::default() }
+    }
+
+    pub fn with_mode(mut self, mode: PositionMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    pub fn supports(mode: PositionMode) -> bool {
+        mode != PositionMode::LongShort
+    }
+
+    /// Reads a comma separated `regime` query parameter, e.g. `regime=trend,range`
+    pub fn allowed_from_query(raw: &HashMap<String, String>) -> Result<Vec<Regime>, Box<dyn std::error::Error>> {
+        match raw.get("regime") {
+            Some(regimes) => regimes.split(',').map(|r| Regime::parse(r.trim())).collect(),
+            None => Ok(Vec::new()),
+        }
+    }
+}
+
+impl Strategy for StrategyRegimeFilter {
+    fn calc_signal(&mut self) -> Result<DataFrame, Box<dyn std::error::Error>> {
+        if !Self::supports(self.mode) {
+            return Err("The regime filter needs position_mode long_only or short_only".into());
+        }
+        let df = self.strategy.calc_signal()?;
+        let signal_name = self.signal_col();
+        let regimes = self.classifier.classify(&df)?;
+        let signals: Vec<i32> = df.column(&signal_name)?
+            .i32()?
+            .into_iter()
+            .zip(regimes.iter())
+            .map(|(signal, regime)| match signal.unwrap_or(0) {
+                signal if Side::from_signal(signal).is_some_and(|side| self.mode.allows(side))
+                    && !regime.is_some_and(|r| self.allowed.contains(&r)) => 0,
+                signal => signal,
+            })
+            .collect();
+
+        let mut df_result = df;
+        df_result.with_column(regime_series(&regimes))?;
+        df_result.with_column(Series::new(signal_name.as_str().into(), signals))?;
+        info!("Filtered {} on regimes {:?}", signal_name, self.allowed);
+        Ok(df_result)
+    }
+
+    fn signal_col(&self) -> String {
+        self.strategy.signal_col()
+    }
+}
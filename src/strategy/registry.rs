@@ -0,0 +1,191 @@
+use std::{collections::HashMap, sync::{Arc, OnceLock}};
+use indexmap::IndexMap;
+use polars::frame::DataFrame;
+use serde::{Deserialize, Serialize};
+
+use super::{Strategy, StrategyBollingerBands, StrategyCrossingMA, StrategyRSI, StrategySupportResistance};
+
+pub type StrategyBuilder = Arc<
+    dyn Fn(DataFrame, &StrategyParams) -> Result<Box<dyn Strategy + Send>, Box<dyn std::error::Error>>
+        + Send + Sync
+>;
+pub type StrategyJsonFn = fn(&DataFrame) -> String;
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ParamType {
+    Int,
+    Float,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ParamSpec {
+    pub name: String,
+    pub param_type: ParamType,
+    pub default: f64,
+    pub min: f64,
+    pub max: f64,
+    pub step: f64,
+}
+
+impl ParamSpec {
+    pub fn int(name: &str, default: usize, min: usize, max: usize, step: usize) -> Self {
+        ParamSpec {
+            name: name.to_string(),
+            param_type: ParamType::Int,
+            default: default as f64,
+            min: min as f64,
+            max: max as f64,
+            step: step as f64,
+        }
+    }
+
+    pub fn float(name: &str, default: f64, min: f64, max: f64, step: f64) -> Self {
+        ParamSpec {
+            name: name.to_string(),
+            param_type: ParamType::Float,
+            default,
+            min,
+            max,
+            step,
+        }
+    }
+
+    pub fn parse(&self, raw: &str) -> Result<f64, Box<dyn std::error::Error>> {
+        let value = match self.param_type {
+            ParamType::Int => raw.parse::<usize>()
+                .map_err(|e| format!("Invalid integer for '{}': {}", self.name, e))? as f64,
+            ParamType::Float => raw.parse::<f64>()
+                .map_err(|e| format!("Invalid float for '{}': {}", self.name, e))?,
+        };
+        if value < self.min || value > self.max {
+            return Err(format!("'{}' must be within [{}, {}]", self.name, self.min, self.max).into());
+        }
+        Ok(value)
+    }
+}
+
+/// One parameter must stay below another, such as a short MA below the long one
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct ParamConstraint {
+    pub below: String,
+    pub above: String,
+}
+
+impl ParamConstraint {
+    pub fn below(below: &str, above: &str) -> Self {
+        ParamConstraint { below: below.to_string(), above: above.to_string() }
+    }
+
+    pub fn allows(&self, params: &StrategyParams) -> bool {
+        match (params.get(&self.below), params.get(&self.above)) {
+            (Some(below), Some(above)) => below < above,
+            _ => true,
+        }
+    }
+}
+
+/// Parameter values keyed by name, in the order declared by the strategy spec
+#[derive(Deserialize, Serialize, Debug, Clone, Default, PartialEq)]
+#[serde(transparent)]
+pub struct StrategyParams(pub IndexMap<String, f64>);
+
+impl StrategyParams {
+    pub fn get(&self, name: &str) -> Option<f64> {
+        self.0.get(name).copied()
+    }
+
+    pub fn get_usize(&self, name: &str) -> Result<usize, Box<dyn std::error::Error>> {
+        self.get(name)
+            .map(|v| v as usize)
+            .ok_or_else(|| format!("Missing parameter '{}'", name).into())
+    }
+
+    pub fn get_f32(&self, name: &str) -> Result<f32, Box<dyn std::error::Error>> {
+        self.get(name)
+            .map(|v| v as f32)
+            .ok_or_else(|| format!("Missing parameter '{}'", name).into())
+    }
+
+    pub fn set(&mut self, name: &str, value: f64) {
+        self.0.insert(name.to_string(), value);
+    }
+}
+
+#[derive(Serialize, Clone)]
+pub struct StrategySpec {
+    pub name: String,
+    pub description: String,
+    pub params: Vec<ParamSpec>,
+    pub constraints: Vec<ParamConstraint>,
+    #[serde(skip)]
+    pub builder: StrategyBuilder,
+    #[serde(skip)]
+    pub to_json: StrategyJsonFn,
+}
+
+impl StrategySpec {
+    pub fn default_params(&self) -> StrategyParams {
+        let mut params = StrategyParams::default();
+        for p in &self.params {
+            params.set(&p.name, p.default);
+        }
+        params
+    }
+
+    /// Defaults overridden by any known parameter found in the raw query, unknown keys are ignored
+    pub fn parse_params(&self, raw: &HashMap<String, String>) -> Result<StrategyParams, Box<dyn std::error::Error>> {
+        let mut params = self.default_params();
+        for p in &self.params {
+            if let Some(value) = raw.get(&p.name) {
+                params.set(&p.name, p.parse(value)?);
+            }
+        }
+        Ok(params)
+    }
+
+    pub fn build(&self, df: DataFrame, params: &StrategyParams) -> Result<Box<dyn Strategy + Send>, Box<dyn std::error::Error>> {
+        if let Some(broken) = self.constraints.iter().find(|c| !c.allows(params)) {
+            return Err(format!("{} must be below {}", broken.below, broken.above).into());
+        }
+        (self.builder)(df, params)
+    }
+}
+
+pub struct StrategyRegistry {
+    strategies: IndexMap<String, StrategySpec>,
+}
+
+impl Default for StrategyRegistry {
+    fn default() -> Self {
+        let mut registry = StrategyRegistry { strategies: IndexMap::new() };
+        for spec in StrategyCrossingMA::specs() {
+            registry.register(spec);
+        }
+        registry.register(StrategyRSI::spec());
+        registry.register(StrategyBollingerBands::spec());
+        for spec in StrategySupportResistance::specs() {
+            registry.register(spec);
+        }
+        registry
+    }
+}
+
+impl StrategyRegistry {
+    pub fn global() -> &'static StrategyRegistry {
+        static REGISTRY: OnceLock<StrategyRegistry> = OnceLock::new();
+        REGISTRY.get_or_init(StrategyRegistry::default)
+    }
+
+    pub fn register(&mut self, spec: StrategySpec) {
+        self.strategies.insert(spec.name.clone(), spec);
+    }
+
+    pub fn get(&self, name: &str) -> Option<&StrategySpec> {
+        self.strategies.get(&name.to_lowercase())
+    }
+
+    pub fn specs(&self) -> Vec<&StrategySpec> {
+        self.strategies.values().collect()
+    }
+}
@@ -0,0 +1,296 @@
+use std::collections::HashMap;
+use log::info;
+use polars::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::scanner::{Execution, PositionMode, Side};
+use super::{indicators, Strategy, BUY_SIGNAL, SELL_SIGNAL};
+
+pub const EXIT_REASON_COL: &str = "Exit_reason";
+pub const EXIT_PRICE_COL: &str = "Exit_price";
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum StopRule {
+    Percent { pct: f32 },
+    Atr { multiple: f32 },
+}
+
+impl StopRule {
+    fn distance(&self, price: f32, atr: Option<f32>) -> Option<f32> {
+        match self {
+            StopRule::Percent { pct } => Some(price * pct / 100.0),
+            StopRule::Atr { multiple } => atr.map(|a| a * multiple),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ExitReason {
+    Signal,
+    StopLoss,
+    TakeProfit,
+    TrailingStop,
+}
+
+impl ExitReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ExitReason::Signal => "signal",
+            ExitReason::StopLoss => "stop_loss",
+            ExitReason::TakeProfit => "take_profit",
+            ExitReason::TrailingStop => "trailing_stop",
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct RiskRules {
+    pub stop_loss: Option<StopRule>,
+    pub take_profit: Option<StopRule>,
+    pub trailing_stop: Option<StopRule>,
+    pub atr_window: usize,
+}
+
+impl Default for RiskRules {
+    fn default() -> Self {
+        RiskRules {
+            stop_loss: None,
+            take_profit: None,
+            trailing_stop: None,
+            atr_window: 14,
+        }
+    }
+}
+
+impl RiskRules {
+    /// Reads `{stop_loss,take_profit,trailing_stop}_{pct,atr}` and `atr_window` from query parameters
+    pub fn from_query(raw: &HashMap<String, String>) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut rules = RiskRules::default();
+        if let Some(window) = raw.get("atr_window") {
+            rules.atr_window = window.parse()
+                .map_err(|e| format!("Invalid atr_window: {}", e))?;
+        }
+        rules.stop_loss = Self::parse_rule(raw, "stop_loss")?;
+        rules.take_profit = Self::parse_rule(raw, "take_profit")?;
+        rules.trailing_stop = Self::parse_rule(raw, "trailing_stop")?;
+        Ok(rules)
+    }
+
+    fn parse_rule(raw: &HashMap<String, String>, prefix: &str) -> Result<Option<StopRule>, Box<dyn std::error::Error>> {
+        let parse = |key: String| -> Result<Option<f32>, Box<dyn std::error::Error>> {
+            match raw.get(&key) {
+                Some(value) => {
+                    let value: f32 = value.parse().map_err(|e| format!("Invalid {}: {}", key, e))?;
+                    if value <= 0.0 {
+                        return Err(format!("{} must be positive", key).into());
+                    }
+                    Ok(Some(value))
+                }
+                None => Ok(None)
+            }
+        };
+        match (parse(format!("{}_pct", prefix))?, parse(format!("{}_atr", prefix))?) {
+            (Some(_), Some(_)) => Err(format!("Only one of {}_pct and {}_atr can be set", prefix, prefix).into()),
+            (Some(pct), None) => Ok(Some(StopRule::Percent { pct })),
+            (None, Some(multiple)) => Ok(Some(StopRule::Atr { multiple })),
+            (None, None) => Ok(None),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stop_loss.is_none() && self.take_profit.is_none() && self.trailing_stop.is_none()
+    }
+
+    fn uses_atr(&self) -> bool {
+        [self.stop_loss, self.take_profit, self.trailing_stop]
+            .iter()
+            .any(|rule| matches!(rule, Some(StopRule::Atr { .. })))
+    }
+}
+
+struct OpenPosition {
+    side: Side,
+    stop: Option<f32>,
+    target: Option<f32>,
+    trail_distance: Option<f32>,
+    highest: f32,
+    lowest: f32,
+}
+
+impl OpenPosition {
+    /// Stops sit below the entry of a long and above the entry of a short, targets the other way
+    fn open(side: Side, price: f32, atr: Option<f32>, rules: &RiskRules) -> Self {
+        let sign = match side {
+            Side::Long => 1.0,
+            Side::Short => -1.0,
+        };
+        OpenPosition {
+            side,
+            stop: rules.stop_loss.and_then(|r| r.distance(price, atr)).map(|d| price - sign * d),
+            target: rules.take_profit.and_then(|r| r.distance(price, atr)).map(|d| price + sign * d),
+            trail_distance: rules.trailing_stop.and_then(|r| r.distance(price, atr)),
+            highest: price,
+            lowest: price,
+        }
+    }
+
+    fn track(&mut self, high: f32, low: f32) {
+        self.highest = self.highest.max(high);
+        self.lowest = self.lowest.min(low);
+    }
+
+    /// Stops are checked before targets, so a bar touching both is treated as the worse outcome.
+    /// A bar opening beyond a level fills at the open instead of the level.
+    fn check_exit(&self, open: f32, high: f32, low: f32) -> Option<(ExitReason, f32)> {
+        match self.side {
+            Side::Long => {
+                let trailing = self.trail_distance.map(|d| self.highest - d);
+                let stop = match (self.stop, trailing) {
+                    (Some(s), Some(t)) if t > s => Some((ExitReason::TrailingStop, t)),
+                    (Some(s), _) => Some((ExitReason::StopLoss, s)),
+                    (None, Some(t)) => Some((ExitReason::TrailingStop, t)),
+                    (None, None) => None,
+                };
+                if let Some((reason, level)) = stop.filter(|(_, level)| low <= *level) {
+                    return Some((reason, open.min(level)));
+                }
+                self.target
+                    .filter(|target| high >= *target)
+                    .map(|target| (ExitReason::TakeProfit, open.max(target)))
+            }
+            Side::Short => {
+                let trailing = self.trail_distance.map(|d| self.lowest + d);
+                let stop = match (self.stop, trailing) {
+                    (Some(s), Some(t)) if t < s => Some((ExitReason::TrailingStop, t)),
+                    (Some(s), _) => Some((ExitReason::StopLoss, s)),
+                    (None, Some(t)) => Some((ExitReason::TrailingStop, t)),
+                    (None, None) => None,
+                };
+                if let Some((reason, level)) = stop.filter(|(_, level)| high >= *level) {
+                    return Some((reason, open.max(level)));
+                }
+                self.target
+                    .filter(|target| low <= *target)
+                    .map(|target| (ExitReason::TakeProfit, open.min(target)))
+            }
+        }
+    }
+
+    /// Signal that closes the position
+    fn exit_signal(&self) -> i32 {
+        match self.side {
+            Side::Long => SELL_SIGNAL,
+            Side::Short => BUY_SIGNAL,
+        }
+    }
+}
+
+/// Wraps any strategy with stop-loss, take-profit and trailing-stop exits.
+/// Entries follow the wrapped signal on the sides the position mode allows, exits are added to
+/// the signal column together with the reason and the fill price of each exit.
+/// Levels are set from the price the entry fills at under the backtest's execution.
+pub struct StrategyRiskOverlay {
+    pub strategy: Box<dyn Strategy + Send>,
+    pub rules: RiskRules,
+    pub mode: PositionMode,
+    pub execution: Execution,
+}
+
+impl StrategyRiskOverlay {
+    pub fn new(strategy: Box<dyn Strategy + Send>, rules: RiskRules) -> Self {
+        StrategyRiskOverlay { strategy, rules, mode: PositionMode::default(), execution: Execution::default() }
+    }
+
+    pub fn with_mode(mut self, mode: PositionMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    pub fn with_execution(mut self, execution: Execution) -> Self {
+        self.execution = execution;
+        self
+    }
+}
+
+impl Strategy for StrategyRiskOverlay {
+    fn calc_signal(&mut self) -> Result<DataFrame, Box<dyn std::error::Error>> {
+        let mut df = self.strategy.calc_signal()?;
+        let signal_name = self.signal_col();
+        let signals: Vec<i32> = df.column(&signal_name)?
+            .i32()?
+            .into_iter()
+            .map(|s| s.unwrap_or(0))
+            .collect();
+        let open = indicators::column_f32(&df, "open")?;
+        let high = indicators::column_f32(&df, "high")?;
+        let low = indicators::column_f32(&df, "low")?;
+        let close = indicators::column_f32(&df, "close")?;
+        let atr = if self.rules.uses_atr() {
+            indicators::atr(&df, self.rules.atr_window)?
+        } else {
+            vec![None; df.height()]
+        };
+
+        let mut new_signals = signals.clone();
+        let mut exit_reasons: Vec<Option<&str>> = vec![None; df.height()];
+        let mut exit_prices: Vec<Option<f32>> = vec![None; df.height()];
+        let mut position: Option<OpenPosition> = None;
+        // Entry signalled on an earlier bar that fills on this one, with the ATR known at the signal
+        let mut pending: Option<(Side, Option<f32>)> = None;
+
+        for row in indicators::chronological_rows(&df)? {
+            let (Some(o), Some(h), Some(l), Some(c)) = (open[row], high[row], low[row], close[row]) else {
+                continue;
+            };
+            let entry = pending.take();
+            if let Some((side, entry_atr)) = entry.filter(|_| self.execution == Execution::NextOpen) {
+                position = Some(OpenPosition::open(side, o, entry_atr, &self.rules));
+            }
+            if let Some(pos) = position.as_mut() {
+                if let Some((reason, price)) = pos.check_exit(o, h, l) {
+                    new_signals[row] = pos.exit_signal();
+                    exit_reasons[row] = Some(reason.as_str());
+                    exit_prices[row] = Some(price);
+                    position = None;
+                    continue;
+                }
+                pos.track(h, l);
+            }
+            if let Some((side, entry_atr)) = entry.filter(|_| self.execution == Execution::NextClose) {
+                position = Some(OpenPosition::open(side, c, entry_atr, &self.rules));
+            }
+            let Some(side) = Side::from_signal(signals[row]) else {
+                continue;
+            };
+            match position.as_ref() {
+                // Only one position is held at a time, repeated entries are dropped
+                Some(pos) if pos.side == side => new_signals[row] = 0,
+                Some(_) => {
+                    exit_reasons[row] = Some(ExitReason::Signal.as_str());
+                    exit_prices[row] = Some(c);
+                    position = None;
+                }
+                None => {}
+            }
+            // The exit of a position may also reverse it when both sides are allowed
+            if position.is_none() && new_signals[row] != 0 && self.mode.allows(side) {
+                match self.execution {
+                    Execution::SameClose => position = Some(OpenPosition::open(side, c, atr[row], &self.rules)),
+                    Execution::NextOpen | Execution::NextClose => pending = Some((side, atr[row])),
+                }
+            }
+        }
+
+        df.with_column(Series::new(signal_name.as_str().into(), new_signals))?;
+        df.with_column(Series::new(EXIT_REASON_COL.into(), exit_reasons))?;
+        df.with_column(Series::new(EXIT_PRICE_COL.into(), exit_prices))?;
+        info!("Applied risk overlay on {}", signal_name);
+        Ok(df)
+    }
+
+    fn signal_col(&self) -> String {
+        self.strategy.signal_col()
+    }
+}
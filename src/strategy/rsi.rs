@@ -1,8 +1,10 @@
+use std::sync::Arc;
 use polars::prelude::*;
 use log::{info, debug};
 use serde::{Deserialize, Serialize};
 
-use super::Strategy;
+use crate::converter::DfConverter;
+use super::{indicators, ParamConstraint, ParamSpec, Strategy, StrategySpec, BUY_SIGNAL, SELL_SIGNAL};
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct StrategyRSI {
@@ -34,18 +36,24 @@ impl StrategyRSI {
         }
     }
     
-    pub fn update_params(&mut self, period: Option<usize>, upper_bound: Option<usize>, lower_bound: Option<usize>) {
-        if let Some(p) = period {
-            self.sma_options.window_size = p;
-        }
-        if let Some(u) = upper_bound {
-            self.upper_bound = u;
-        }
-        if let Some(l) = lower_bound {
-            self.lower_bound = l;
+    pub fn spec() -> StrategySpec {
+        StrategySpec {
+            name: "rsi".to_string(),
+            description: "Relative strength index crossing its upper or lower bound".to_string(),
+            params: vec![
+                ParamSpec::int("period", 14, 5, 20, 2),
+                ParamSpec::int("upper_bound", 80, 50, 100, 5),
+                ParamSpec::int("lower_bound", 20, 0, 50, 5),
+            ],
+            constraints: vec![ParamConstraint::below("lower_bound", "upper_bound")],
+            builder: Arc::new(|df, params| {
+                let (upper_bound, lower_bound) = (params.get_usize("upper_bound")?, params.get_usize("lower_bound")?);
+                Ok(Box::new(StrategyRSI::new(df, params.get_usize("period")?, upper_bound, lower_bound)))
+            }),
+            to_json: DfConverter::rsi_df_to_json,
         }
     }
-    
+
     pub fn calc_rsi(&mut self) -> Result<DataFrame, Box<dyn std::error::Error>> {
         match &mut self.df {
             Some(df) => {
@@ -57,17 +65,11 @@ impl StrategyRSI {
                 let gain = "gain";
                 let loss = "loss";
 
-                let mut updated_df = df.clone()
-                    .lazy()
+                // Deltas and averages of a bar cover that bar's close and the ones before it
+                let mut updated_df = indicators::in_chronological_order(df, |lf| lf
                     .with_column(
-                        (col("close").shift(lit(1)) - col("close"))
-                            .alias("delta")
-                            .shift(lit(-1))
+                        (col("close") - col("close").shift(lit(1))).alias(delta)
                     )
-                    .collect()?;
-
-                updated_df = updated_df.clone()
-                    .lazy()
                     .with_columns([
                         when(col(delta).gt(lit(0.0)))
                             .then(col(delta))
@@ -78,34 +80,18 @@ impl StrategyRSI {
                             .otherwise(lit(0.0))
                             .alias(loss),
                     ])
-                    .collect()?;
-
-                updated_df = updated_df.clone()
-                    .lazy()
                     .with_columns([
-                        col(gain).rolling_mean(self.sma_options.clone())
-                                    .shift(lit(-(self.sma_options.window_size as i32)))
-                                    .alias(&avg_gain),
-                        col(loss).rolling_mean(self.sma_options.clone())
-                                    .shift(lit(-(self.sma_options.window_size as i32)))
-                                    .alias(&avg_loss)
+                        col(gain).rolling_mean(self.sma_options.clone()).alias(&avg_gain),
+                        col(loss).rolling_mean(self.sma_options.clone()).alias(&avg_loss)
                     ])
-                    .collect()?;
-
-                updated_df = updated_df.clone()
-                    .lazy()
                     .with_column(
                         (col(&avg_gain) / col(&avg_loss)).alias(&rs)
                     )
-                    .collect()?;
-
-                updated_df = updated_df.clone()
-                    .lazy()
                     .with_column(
                         (lit(100.0) - (lit(100.0) / (lit(1.0) + col(&rs)))).alias(&rsi)
                     )
-                    .collect()?;
-                
+                )?;
+
                 updated_df = updated_df.drop_many(
                                 vec![delta.to_string(), gain.to_string(), 
                                             loss.to_string(), avg_gain, avg_loss, rs]
@@ -127,20 +113,43 @@ impl Strategy for StrategyRSI{
         let columns = df.get_column_names();
         let rsi_col_name = format!("RSI_{}", self.sma_options.window_size);
         let rsi_col = columns.iter()
-            .find(|name| name.contains(&rsi_col_name))
+            .find(|name| name.as_str() == rsi_col_name)
             .ok_or("RSI column not found")?;
-        let signal_name = format!("Sig_{}", self.sma_options.window_size);
+        let signal_name = self.signal_col();
         df = df.clone()
             .lazy()
             .with_column(
                 when(col(rsi_col.to_string()).gt(lit(self.upper_bound as u32)))
-                    .then(1)
+                    .then(lit(SELL_SIGNAL))
                     .when(col(rsi_col.to_string()).lt(lit(self.lower_bound as u32)))
-                    .then(-1)
-                    .otherwise(0)
+                    .then(lit(BUY_SIGNAL))
+                    .otherwise(lit(0))
                     .alias(signal_name)
             )
             .collect().ok().unwrap();
         return Ok(df);                                 
     }
+
+    fn signal_col(&self) -> String {
+        format!("Sig_RSI_{}_{}_{}", self.sma_options.window_size, self.lower_bound, self.upper_bound)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_fixtures::bars;
+    use super::*;
+
+    #[test]
+    fn signal_is_named_by_every_parameter() {
+        let close: Vec<f32> = (0..40).map(|i| 100.0 + (i as f32 / 3.0).sin() * 5.0).collect();
+        let mut narrow = StrategyRSI::new(bars(&close), 14, 70, 30);
+        let mut wide = StrategyRSI::new(bars(&close), 14, 80, 20);
+        assert_eq!(narrow.signal_col(), "Sig_RSI_14_30_70");
+        assert_ne!(narrow.signal_col(), wide.signal_col());
+        for strategy in [&mut narrow, &mut wide] {
+            let df = strategy.calc_signal().unwrap();
+            assert!(df.column(&strategy.signal_col()).is_ok());
+        }
+    }
 }
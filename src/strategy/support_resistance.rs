@@ -0,0 +1,141 @@
+use std::sync::Arc;
+use polars::prelude::*;
+use log::info;
+use serde::{Deserialize, Serialize};
+
+use crate::converter::DfConverter;
+use super::{indicators, levels, ParamSpec, Strategy, StrategySpec, BUY_SIGNAL, SELL_SIGNAL};
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum LevelMode {
+    Breakout,
+    Bounce,
+}
+
+impl LevelMode {
+    fn name(&self) -> &'static str {
+        match self {
+            LevelMode::Breakout => "Breakout",
+            LevelMode::Bounce => "Bounce",
+        }
+    }
+}
+
+/// Trades breakouts through, or bounces off, horizontal zones clustered from swing highs and lows.
+/// Zones at each bar only use swings already confirmed by then.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct StrategySupportResistance {
+    pub df: Option<DataFrame>,
+    pub mode: LevelMode,
+    pub swing_window: usize,
+    pub tolerance_pct: f32,
+    pub min_touches: usize,
+}
+
+impl StrategySupportResistance {
+    pub fn new(df: DataFrame, mode: LevelMode, swing_window: usize, tolerance_pct: f32, min_touches: usize) -> Self {
+        StrategySupportResistance {
+            df: Some(df),
+            mode,
+            swing_window,
+            tolerance_pct,
+            min_touches,
+        }
+    }
+
+    pub fn specs() -> Vec<StrategySpec> {
+        [LevelMode::Breakout, LevelMode::Bounce].iter().map(|mode| {
+            let mode = *mode;
+            StrategySpec {
+                name: format!("sr_{}", mode.name().to_lowercase()),
+                description: format!("{} of support and resistance zones from clustered swing points", mode.name()),
+                params: vec![
+                    ParamSpec::int("swing_window", 5, 2, 20, 1),
+                    ParamSpec::float("tolerance_pct", 1.0, 0.25, 5.0, 0.25),
+                    ParamSpec::int("min_touches", 2, 1, 5, 1),
+                ],
+                constraints: vec![],
+                builder: Arc::new(move |df, params| {
+                    Ok(Box::new(StrategySupportResistance::new(
+                        df,
+                        mode,
+                        params.get_usize("swing_window")?,
+                        params.get_f32("tolerance_pct")?,
+                        params.get_usize("min_touches")?
+                    )))
+                }),
+                to_json: DfConverter::sr_df_to_json,
+            }
+        }).collect()
+    }
+}
+
+impl Strategy for StrategySupportResistance {
+    fn calc_signal(&mut self) -> Result<DataFrame, Box<dyn std::error::Error>> {
+        let mut df = match &self.df {
+            Some(df) => df.clone(),
+            None => return Err("Dataframe is None".into()),
+        };
+        let signal_name = self.signal_col();
+        let high = indicators::column_f32(&df, "high")?;
+        let low = indicators::column_f32(&df, "low")?;
+        let close = indicators::column_f32(&df, "close")?;
+        let rows = indicators::chronological_rows(&df)?;
+        let swings = levels::swing_points(&df, self.swing_window)?;
+
+        let mut signals = vec![0; df.height()];
+        let mut support: Vec<Option<f32>> = vec![None; df.height()];
+        let mut resistance: Vec<Option<f32>> = vec![None; df.height()];
+        // Confirmed swing prices kept sorted, the zones only change when a swing is confirmed
+        let mut confirmed: Vec<f32> = Vec::new();
+        let mut zones = Vec::new();
+        let mut next_swing = 0;
+        let mut prev_close: Option<f32> = None;
+
+        for (t, row) in rows.iter().enumerate() {
+            let first_new = next_swing;
+            while next_swing < swings.len() && swings[next_swing].0 + self.swing_window <= t {
+                let price = swings[next_swing].1;
+                confirmed.insert(confirmed.partition_point(|p| p.total_cmp(&price).is_le()), price);
+                next_swing += 1;
+            }
+            if next_swing > first_new {
+                zones = levels::cluster_zones(&confirmed, self.tolerance_pct, self.min_touches);
+            }
+            let (Some(h), Some(l), Some(c)) = (high[*row], low[*row], close[*row]) else {
+                continue;
+            };
+            support[*row] = zones.iter().filter(|z| z.price <= c).map(|z| z.price).reduce(f32::max);
+            resistance[*row] = zones.iter().filter(|z| z.price > c).map(|z| z.price).reduce(f32::min);
+
+            if let Some(pc) = prev_close {
+                for zone in &zones {
+                    let signal = match self.mode {
+                        LevelMode::Breakout if pc <= zone.upper && c > zone.upper => BUY_SIGNAL,
+                        LevelMode::Breakout if pc >= zone.lower && c < zone.lower => SELL_SIGNAL,
+                        LevelMode::Bounce if pc > zone.upper && l <= zone.upper && c > zone.upper => BUY_SIGNAL,
+                        LevelMode::Bounce if pc < zone.lower && h >= zone.lower && c < zone.lower => SELL_SIGNAL,
+                        _ => 0,
+                    };
+                    if signal != 0 {
+                        signals[*row] = signal;
+                        break;
+                    }
+                }
+            }
+            prev_close = Some(c);
+        }
+
+        let suffix = format!("SR_{}_{}_{}", self.swing_window, self.tolerance_pct, self.min_touches);
+        df.with_column(Series::new(format!("Support_{}", suffix).as_str().into(), support))?;
+        df.with_column(Series::new(format!("Resistance_{}", suffix).as_str().into(), resistance))?;
+        df.with_column(Series::new(signal_name.as_str().into(), signals))?;
+        info!("Calculated support resistance signal: {}", signal_name);
+        Ok(df)
+    }
+
+    fn signal_col(&self) -> String {
+        format!("Sig_SR_{}_{}_{}_{}", self.mode.name(), self.swing_window, self.tolerance_pct, self.min_touches)
+    }
+}
@@ -0,0 +1,46 @@
+//! Query maps and price frames shared by the unit tests.
+
+use std::collections::HashMap;
+use chrono::{Duration, NaiveDate};
+use polars::prelude::*;
+
+/// Query string parameters as the handlers receive them
+pub fn query(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+    pairs.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect()
+}
+
+const FIRST_DAY: (i32, u32, u32) = (2024, 1, 1);
+
+/// Date of the bar `days` after the first one of `bars`
+pub fn day(days: i64) -> String {
+    let (year, month, day) = FIRST_DAY;
+    (NaiveDate::from_ymd_opt(year, month, day).unwrap() + Duration::days(days)).to_string()
+}
+
+/// One daily bar per close from 2024-01-01 on, each opening at its close and ranging one point
+/// either side of it. Closes are given oldest first and the frame holds the latest day in its first row.
+pub fn bars(close: &[f32]) -> DataFrame {
+    let datetime: Vec<String> = (0..close.len()).map(|i| day(i as i64)).collect();
+    let df = df!(
+        "datetime" => datetime,
+        "high" => close.iter().map(|c| c + 1.0).collect::<Vec<f32>>(),
+        "low" => close.iter().map(|c| c - 1.0).collect::<Vec<f32>>(),
+        "open" => close.to_vec(),
+        "close" => close.to_vec()
+    ).unwrap();
+    df.reverse()
+}
+
+/// Adds or replaces a price column with values given oldest first
+pub fn with_prices(mut df: DataFrame, name: &str, values: &[f32]) -> DataFrame {
+    let values: Vec<f32> = values.iter().rev().copied().collect();
+    df.with_column(Series::new(name.into(), values)).unwrap();
+    df
+}
+
+/// Adds a signal column with signals given oldest first
+pub fn with_signals(mut df: DataFrame, name: &str, signals: &[i32]) -> DataFrame {
+    let signals: Vec<i32> = signals.iter().rev().copied().collect();
+    df.with_column(Series::new(name.into(), signals)).unwrap();
+    df
+}
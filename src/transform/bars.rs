@@ -0,0 +1,146 @@
+use std::collections::HashMap;
+use log::info;
+use polars::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::strategy::{indicators, Strategy};
+use super::{heikin_ashi::heikin_ashi, range_bars::range_bars, renko::renko};
+
+const BASE_COLS: [&str; 5] = ["datetime", "high", "low", "open", "close"];
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum BrickSize {
+    Fixed(f32),
+    Atr(usize),
+}
+
+impl BrickSize {
+    /// Reads `brick_size`, or an ATR over `atr_window` when it is not set
+    fn from_query(raw: &HashMap<String, String>) -> Result<Self, Box<dyn std::error::Error>> {
+        if let Some(size) = raw.get("brick_size") {
+            let size: f32 = size.parse().map_err(|e| format!("Invalid brick_size: {}", e))?;
+            return Ok(BrickSize::Fixed(size));
+        }
+        let window = match raw.get("atr_window") {
+            Some(window) => window.parse().map_err(|e| format!("Invalid atr_window: {}", e))?,
+            None => 14,
+        };
+        Ok(BrickSize::Atr(window))
+    }
+
+    /// Size of the bricks and the first of the chronological `rows` they are built from
+    pub(super) fn resolve(&self, df: &DataFrame, rows: &[usize]) -> Result<(f32, usize), Box<dyn std::error::Error>> {
+        let (size, start) = match self {
+            BrickSize::Fixed(size) => (Some(*size), 0),
+            // The ATR at the end of its warmup sets the size, later values would leak future volatility
+            BrickSize::Atr(window) => {
+                let atr = indicators::atr(df, *window)?;
+                let start = rows.iter().position(|row| atr[*row].is_some());
+                (start.and_then(|i| atr[rows[i]]), start.unwrap_or(rows.len()))
+            }
+        };
+        match size {
+            Some(size) if size > 0.0 => Ok((size, start)),
+            _ => Err("Brick size must be positive".into()),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum BarType {
+    Candles,
+    HeikinAshi,
+    Renko(BrickSize),
+    Range(BrickSize),
+}
+
+impl BarType {
+    /// Reads `bars=heikin_ashi|renko|range`, Renko bricks and range bars use `brick_size` or an ATR over `atr_window`
+    pub fn from_query(raw: &HashMap<String, String>) -> Result<Self, Box<dyn std::error::Error>> {
+        match raw.get("bars").map(|b| b.as_str()) {
+            None | Some("candles") => Ok(BarType::Candles),
+            Some("heikin_ashi") => Ok(BarType::HeikinAshi),
+            Some("renko") => Ok(BarType::Renko(BrickSize::from_query(raw)?)),
+            Some("range") => Ok(BarType::Range(BrickSize::from_query(raw)?)),
+            Some(other) => Err(format!("Unknown bars '{}'", other).into()),
+        }
+    }
+
+    pub fn transform(&self, df: &DataFrame) -> Result<DataFrame, Box<dyn std::error::Error>> {
+        match self {
+            BarType::Candles => Ok(df.clone()),
+            BarType::HeikinAshi => heikin_ashi(df),
+            BarType::Renko(brick_size) => renko(df, brick_size),
+            BarType::Range(brick_size) => range_bars(df, brick_size),
+        }
+    }
+}
+
+/// Runs a strategy built on transformed bars and maps its signals back onto the real bars.
+/// Every signal lands on the real bar that completed the transformed one, with real prices,
+/// so backtests never fill at synthetic Heikin-Ashi, brick or range bar prices.
+pub struct StrategyOnBars {
+    pub strategy: Box<dyn Strategy + Send>,
+    pub df: DataFrame,
+}
+
+impl StrategyOnBars {
+    pub fn new(strategy: Box<dyn Strategy + Send>, df: DataFrame) -> Self {
+        StrategyOnBars { strategy, df }
+    }
+}
+
+impl Strategy for StrategyOnBars {
+    fn calc_signal(&mut self) -> Result<DataFrame, Box<dyn std::error::Error>> {
+        let df_bars = self.strategy.calc_signal()?;
+        let signal_name = self.signal_col();
+        let bar_signals = df_bars.column(&signal_name)?.i32()?;
+        let bar_datetime = df_bars.column("datetime")?.str()?;
+
+        // Latest transformed bar and latest non-zero signal completed by each real bar
+        let mut latest_bar: HashMap<String, IdxSize> = HashMap::new();
+        let mut latest_signal: HashMap<String, i32> = HashMap::new();
+        for row in indicators::chronological_rows(&df_bars)? {
+            if let Some(dt) = bar_datetime.get(row) {
+                latest_bar.insert(dt.to_string(), row as IdxSize);
+                let signal = bar_signals.get(row).unwrap_or(0);
+                if signal != 0 {
+                    latest_signal.insert(dt.to_string(), signal);
+                }
+            }
+        }
+
+        let datetime = self.df.column("datetime")?.str()?;
+        let (bar_rows, signals): (Vec<Option<IdxSize>>, Vec<i32>) = datetime
+            .into_iter()
+            .map(|dt| match dt {
+                Some(dt) => (latest_bar.get(dt).copied(), latest_signal.get(dt).copied().unwrap_or(0)),
+                None => (None, 0),
+            })
+            .unzip();
+        let indicator_cols: Vec<String> = df_bars.get_column_names()
+            .iter()
+            .filter(|name| !BASE_COLS.contains(&name.as_str()))
+            .map(|name| name.to_string())
+            .collect();
+        let df_mapped = df_bars
+            .select(indicator_cols)?
+            .take(&IdxCa::new("bar_rows".into(), bar_rows))?;
+
+        let real_cols: Vec<&str> = self.df.get_column_names()
+            .into_iter()
+            .map(|name| name.as_str())
+            .filter(|name| BASE_COLS.contains(name) || *name == "volume")
+            .collect();
+        let mut df_result = self.df.select(real_cols)?.hstack(df_mapped.get_columns())?;
+        df_result.with_column(Series::new(signal_name.as_str().into(), signals))?;
+        info!("Mapped {} back to real bars", signal_name);
+        Ok(df_result)
+    }
+
+    fn signal_col(&self) -> String {
+        self.strategy.signal_col()
+    }
+}
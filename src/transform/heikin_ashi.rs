@@ -0,0 +1,40 @@
+use polars::prelude::*;
+
+use crate::strategy::indicators;
+
+/// Heikin-Ashi candles, one per real bar and in the same row order as the input frame
+pub fn heikin_ashi(df: &DataFrame) -> Result<DataFrame, Box<dyn std::error::Error>> {
+    let open = indicators::column_f32(df, "open")?;
+    let high = indicators::column_f32(df, "high")?;
+    let low = indicators::column_f32(df, "low")?;
+    let close = indicators::column_f32(df, "close")?;
+    let mut ha_open: Vec<Option<f32>> = vec![None; df.height()];
+    let mut ha_high: Vec<Option<f32>> = vec![None; df.height()];
+    let mut ha_low: Vec<Option<f32>> = vec![None; df.height()];
+    let mut ha_close: Vec<Option<f32>> = vec![None; df.height()];
+    let mut prev: Option<(f32, f32)> = None;
+
+    for row in indicators::chronological_rows(df)? {
+        if let (Some(o), Some(h), Some(l), Some(c)) = (open[row], high[row], low[row], close[row]) {
+            let bar_close = (o + h + l + c) / 4.0;
+            let bar_open = match prev {
+                Some((prev_open, prev_close)) => (prev_open + prev_close) / 2.0,
+                None => (o + c) / 2.0,
+            };
+            ha_open[row] = Some(bar_open);
+            ha_close[row] = Some(bar_close);
+            ha_high[row] = Some(h.max(bar_open).max(bar_close));
+            ha_low[row] = Some(l.min(bar_open).min(bar_close));
+            prev = Some((bar_open, bar_close));
+        }
+    }
+
+    let df_ha = DataFrame::new(vec![
+        df.column("datetime")?.clone(),
+        Series::new("high".into(), ha_high).into(),
+        Series::new("low".into(), ha_low).into(),
+        Series::new("open".into(), ha_open).into(),
+        Series::new("close".into(), ha_close).into(),
+    ])?;
+    Ok(df_ha)
+}
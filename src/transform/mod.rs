@@ -0,0 +1,6 @@
+mod heikin_ashi;
+mod range_bars;
+mod renko;
+mod bars;
+
+pub use bars::{BarType, BrickSize, StrategyOnBars};
\ No newline at end of file
@@ -0,0 +1,67 @@
+use polars::prelude::*;
+
+use crate::strategy::indicators;
+use super::BrickSize;
+
+struct RangeBar {
+    datetime: String,
+    open: f32,
+    high: f32,
+    low: f32,
+    close: f32,
+}
+
+/// Range bars whose high and low are `brick_size` apart, walked through each real bar's open, its
+/// extreme nearest the open, the other extreme and its close. Each bar is stamped with the real bar
+/// that completed it, the bar still forming is left out. The last completed bar is the first row.
+pub fn range_bars(df: &DataFrame, brick_size: &BrickSize) -> Result<DataFrame, Box<dyn std::error::Error>> {
+    let open = indicators::column_f32(df, "open")?;
+    let high = indicators::column_f32(df, "high")?;
+    let low = indicators::column_f32(df, "low")?;
+    let close = indicators::column_f32(df, "close")?;
+    let datetime = df.column("datetime")?.str()?;
+    let rows = indicators::chronological_rows(df)?;
+    let (size, start) = brick_size.resolve(df, &rows)?;
+
+    let mut bars: Vec<RangeBar> = Vec::new();
+    let mut forming: Option<(f32, f32, f32)> = None;
+    for row in &rows[start..] {
+        let (Some(c), Some(dt)) = (close[*row], datetime.get(*row)) else {
+            continue;
+        };
+        let (o, h, l) = (open[*row].unwrap_or(c), high[*row].unwrap_or(c), low[*row].unwrap_or(c));
+        let path = match h - o < o - l {
+            true => [o, h, l, c],
+            false => [o, l, h, c],
+        };
+        for price in path {
+            let (mut bar_open, mut bar_high, mut bar_low) = *forming.get_or_insert((price, price, price));
+            // A move beyond the range closes the bar at its edge and opens the next one there
+            while price > bar_low + size || price < bar_high - size {
+                let close = match price > bar_low + size {
+                    true => bar_low + size,
+                    false => bar_high - size,
+                };
+                bars.push(RangeBar {
+                    datetime: dt.to_string(),
+                    open: bar_open,
+                    high: bar_high.max(close),
+                    low: bar_low.min(close),
+                    close,
+                });
+                (bar_open, bar_high, bar_low) = (close, close, close);
+            }
+            forming = Some((bar_open, bar_high.max(price), bar_low.min(price)));
+        }
+    }
+
+    bars.reverse();
+    let df_range = DataFrame::new(vec![
+        Series::new("datetime".into(), bars.iter().map(|b| b.datetime.clone()).collect::<Vec<String>>()).into(),
+        Series::new("high".into(), bars.iter().map(|b| b.high).collect::<Vec<f32>>()).into(),
+        Series::new("low".into(), bars.iter().map(|b| b.low).collect::<Vec<f32>>()).into(),
+        Series::new("open".into(), bars.iter().map(|b| b.open).collect::<Vec<f32>>()).into(),
+        Series::new("close".into(), bars.iter().map(|b| b.close).collect::<Vec<f32>>()).into(),
+    ])?;
+    Ok(df_range)
+}
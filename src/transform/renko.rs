@@ -0,0 +1,50 @@
+use polars::prelude::*;
+
+use crate::strategy::indicators;
+use super::BrickSize;
+
+struct Brick {
+    datetime: String,
+    open: f32,
+    close: f32,
+}
+
+/// Renko bricks built from closing prices. Each brick is stamped with the real bar that completed it,
+/// so a single bar can produce several bricks. The last brick is the first row, as strategies expect of any prices.
+pub fn renko(df: &DataFrame, brick_size: &BrickSize) -> Result<DataFrame, Box<dyn std::error::Error>> {
+    let close = indicators::column_f32(df, "close")?;
+    let datetime = df.column("datetime")?.str()?;
+    let rows = indicators::chronological_rows(df)?;
+    let (size, start) = brick_size.resolve(df, &rows)?;
+
+    let mut bricks: Vec<Brick> = Vec::new();
+    let mut level: Option<(f32, f32)> = None;
+    for row in &rows[start..] {
+        let (Some(c), Some(dt)) = (close[*row], datetime.get(*row)) else {
+            continue;
+        };
+        // The last brick spans [bottom, top], so continuing from its close takes one size and reversing takes two
+        let (mut bottom, mut top) = *level.get_or_insert((c, c));
+        while c >= top + size {
+            bricks.push(Brick { datetime: dt.to_string(), open: top, close: top + size });
+            bottom = top;
+            top += size;
+        }
+        while c <= bottom - size {
+            bricks.push(Brick { datetime: dt.to_string(), open: bottom, close: bottom - size });
+            top = bottom;
+            bottom -= size;
+        }
+        level = Some((bottom, top));
+    }
+
+    bricks.reverse();
+    let df_renko = DataFrame::new(vec![
+        Series::new("datetime".into(), bricks.iter().map(|b| b.datetime.clone()).collect::<Vec<String>>()).into(),
+        Series::new("high".into(), bricks.iter().map(|b| b.open.max(b.close)).collect::<Vec<f32>>()).into(),
+        Series::new("low".into(), bricks.iter().map(|b| b.open.min(b.close)).collect::<Vec<f32>>()).into(),
+        Series::new("open".into(), bricks.iter().map(|b| b.open).collect::<Vec<f32>>()).into(),
+        Series::new("close".into(), bricks.iter().map(|b| b.close).collect::<Vec<f32>>()).into(),
+    ])?;
+    Ok(df_renko)
+}